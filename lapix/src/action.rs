@@ -1,21 +1,27 @@
-use crate::{Bitmap, CanvasEffect, Color, Layer, Layers, Point};
+use crate::{
+    AnimationTag, Bitmap, BlendMode, CanvasEffect, Color, Error, Frame, FrameId, GroupId, Layer,
+    LayerGroup, LayerId, Layers, Point, Rect, Result, Size,
+};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 pub type LayerIndex = usize;
 
-pub struct Action<IMG>(Vec<AtomicAction<IMG>>);
-
-impl<IMG> Default for Action<IMG> {
-    fn default() -> Self {
-        Self(Default::default())
-    }
+/// A group of [`AtomicAction`]s that are undone (or redone) together, along
+/// with a human-readable label describing what they do and the frame that was
+/// active when they happened
+#[derive(Serialize, Deserialize)]
+pub struct Action<IMG> {
+    label: String,
+    frame: Option<FrameId>,
+    actions: Vec<AtomicAction<IMG>>,
 }
 
 impl<IMG> Debug for Action<IMG> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_str("Action([")?;
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Action({:?}, [", self.label))?;
 
-        for action in self.0.iter() {
+        for action in self.actions.iter() {
             f.write_fmt(format_args!("{:?}, ", action))?;
         }
 
@@ -23,15 +29,49 @@ impl<IMG> Debug for Action<IMG> {
     }
 }
 
-impl<IMG> From<Vec<AtomicAction<IMG>>> for Action<IMG> {
-    fn from(actions: Vec<AtomicAction<IMG>>) -> Self {
-        Self(actions)
+impl<IMG: Bitmap> Action<IMG> {
+    pub fn new(label: impl Into<String>, actions: Vec<AtomicAction<IMG>>) -> Self {
+        Self {
+            label: label.into(),
+            frame: None,
+            actions,
+        }
+    }
+
+    /// Merge several actions into one that reverses all of them at once. The
+    /// actions must be in the order they were registered. If they happened in
+    /// different frames, each one switches back to its frame before being
+    /// applied
+    pub fn merge(label: impl Into<String>, actions: Vec<Self>) -> Self {
+        let mut merged = Self::new(label, Vec::new());
+        let single_frame = actions.windows(2).all(|w| w[0].frame == w[1].frame);
+        for action in actions {
+            merged.actions.extend(action.actions);
+            // Actions are applied from the last one, so the switch goes after
+            match action.frame {
+                Some(frame) if !single_frame => {
+                    merged.actions.push(AtomicAction::SwitchFrame(frame));
+                }
+                _ => (),
+            }
+        }
+
+        merged
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Set the frame that was active when this action happened. It becomes
+    /// the active frame again when the action is applied, so that changes to
+    /// the cels of the active frame go to the right ones
+    pub fn set_frame(&mut self, frame: FrameId) {
+        self.frame = Some(frame);
     }
-}
 
-impl<IMG: Bitmap> Action<IMG> {
     pub fn push(&mut self, action: AtomicAction<IMG>) {
-        self.0.push(action);
+        self.actions.push(action);
     }
 
     pub fn append(&mut self, actions: Vec<AtomicAction<IMG>>) {
@@ -40,26 +80,179 @@ impl<IMG: Bitmap> Action<IMG> {
         }
     }
 
-    pub fn apply(mut self, layers: &mut Layers<IMG>) -> CanvasEffect {
+    /// Replace the [`AtomicAction::SetPixel`]s of this action by a single
+    /// [`AtomicAction::SetArea`] covering all of them, if that takes less
+    /// memory (and the same for [`AtomicAction::SetMaskPixel`] and
+    /// [`AtomicAction::SetMaskArea`]). This is only done if the action is made
+    /// exclusively of `SetPixel`s (or `SetMaskPixel`s) on the same layer, and
+    /// must be called right after the action being reversed happened, as it
+    /// takes the pixels that were not changed from the current state of the
+    /// layers.
+    pub fn compact(&mut self, layers: &Layers<IMG>) {
+        let mut target = None;
+        let (mut min, mut max) = (
+            Point::new(i32::MAX, i32::MAX),
+            Point::new(i32::MIN, i32::MIN),
+        );
+
+        for action in self.actions.iter() {
+            let (i, p, mask) = match action {
+                AtomicAction::SetPixel(i, p, _) => (*i, p, false),
+                AtomicAction::SetMaskPixel(i, p, _) => (*i, p, true),
+                _ => return,
+            };
+            if target.unwrap_or((i, mask)) != (i, mask) {
+                return;
+            }
+            target = Some((i, mask));
+            min = Point::new(min.x.min(p.x), min.y.min(p.y));
+            max = Point::new(max.x.max(p.x), max.y.max(p.y));
+        }
+
+        let (layer, mask) = match target {
+            Some(target) => target,
+            None => return,
+        };
+
+        let area = Rect::new(min.x, min.y, max.x - min.x + 1, max.y - min.y + 1);
+        let area_bytes = area.w as usize * area.h as usize * 4;
+        if area_bytes >= self.actions.len() * std::mem::size_of::<AtomicAction<IMG>>() {
+            return;
+        }
+
+        let canvas = match (layers.by_id(layer), mask) {
+            (Ok(layer), true) => match layer.mask() {
+                Some(canvas) => canvas,
+                None => return,
+            },
+            (Ok(layer), false) => layer.canvas(),
+            (Err(_), _) => return,
+        };
+        let mut img = canvas.img_from_area(area);
+
+        // The first change to a pixel is the one holding its original color,
+        // and they are applied last when reversing
+        for action in self.actions.drain(..).rev() {
+            if let AtomicAction::SetPixel(_, p, color) | AtomicAction::SetMaskPixel(_, p, color) =
+                action
+            {
+                img.set_pixel(p - area.pos(), color);
+            }
+        }
+
+        self.actions.push(match mask {
+            true => AtomicAction::SetMaskArea(layer, area.pos(), img),
+            false => AtomicAction::SetArea(layer, area.pos(), img),
+        });
+    }
+
+    /// Apply this action, returning the [`CanvasEffect`] it caused and the
+    /// action that reverses it (used to redo what was undone and vice-versa).
+    /// If one of its atomic actions can't be applied, the ones applied before
+    /// it are reverted, and the error is returned along with this same action
+    /// so that it can be kept in the history
+    pub fn apply(
+        mut self,
+        layers: &mut Layers<IMG>,
+    ) -> std::result::Result<(CanvasEffect, Self), (Error, Box<Self>)> {
         let mut effect = CanvasEffect::None;
+        let mut reverse = Self::new(std::mem::take(&mut self.label), Vec::new());
+        reverse.frame = self.frame;
+        let mut switched_frames = false;
+        let previous_frame = layers.active_frame();
 
-        while let Some(action) = self.0.pop() {
-            effect = action.apply(layers);
+        // The frame may not exist anymore (or yet), if this action is the one
+        // that deletes (or creates) it
+        let frame = self.frame.and_then(|id| layers.frame_index_of(id).ok());
+        let switches_frame = frame.is_some_and(|i| i != layers.active_frame());
+        if let Some(i) = frame {
+            layers.switch_frame(i);
         }
 
-        effect
+        while let Some(action) = self.actions.pop() {
+            if let Err(e) = action.check(layers) {
+                self.actions.push(action);
+                while let Some(reversal) = reverse.actions.pop() {
+                    let (_, action) = reversal
+                        .apply(layers)
+                        .expect("the reversal of an action that was just applied is valid");
+                    self.actions.push(action);
+                }
+                self.label = reverse.label;
+                layers.switch_frame(previous_frame);
+
+                return Err((e, Box::new(self)));
+            }
+
+            switched_frames |= matches!(action, AtomicAction::SwitchFrame(_));
+            let (fx, reversal) = action
+                .apply(layers)
+                .expect("atomic actions are checked before being applied");
+            effect = fx;
+            reverse.push(reversal);
+        }
+
+        // The first part of the reverse action to be applied is the one that
+        // reverses the last part applied here, in the frame active now
+        if switched_frames {
+            reverse.set_frame(layers.active_frame_id());
+        }
+
+        // All layers show a different cel after switching frames
+        if switches_frame {
+            effect = CanvasEffect::Layer;
+        }
+
+        Ok((effect, reverse))
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub enum AtomicAction<IMG> {
-    SetPixel(LayerIndex, Point<i32>, Color),
-    DestroyLayer(LayerIndex),
+    SetPixel(LayerId, Point<i32>, Color),
+    DestroyLayer(LayerId),
+    /// Add a layer that was deleted back at an index, keeping its id
     CreateLayer(LayerIndex, Layer<IMG>),
-    SetLayerCanvas(LayerIndex, IMG),
+    SetLayerCanvas(LayerId, IMG),
+    /// Replace a rectangular area of a layer, starting at a point, with an
+    /// image
+    SetArea(LayerId, Point<i32>, IMG),
+    SetLayerBlendMode(LayerId, BlendMode),
+    /// Replace all layer groups (but not the groups each layer belongs to)
+    SetGroups(Vec<LayerGroup>),
+    SetLayerGroup(LayerId, Option<GroupId>),
+    SetLayerName(LayerId, String),
+    /// Set (or remove, if the value is `None`) a metadata entry of a layer
+    SetLayerMetadata(LayerId, String, Option<String>),
+    /// Set (or remove, if `None`) the mask of a layer
+    SetLayerMask(LayerId, Option<IMG>),
+    SetMaskPixel(LayerId, Point<i32>, Color),
+    /// Same as [`AtomicAction::SetArea`], but on the mask of a layer
+    SetMaskArea(LayerId, Point<i32>, IMG),
+    /// Move the image (and mask) of a layer so that its top left corner is at
+    /// a point of the canvas
+    SetLayerOffset(LayerId, Point<i32>),
+    SetCanvasSize(Size<i32>),
+    /// Replace the images of all cels of a layer (one for each frame)
+    SetLayerCels(LayerId, Vec<IMG>),
+    DestroyFrame(FrameId),
+    /// Add a frame that was deleted back at an index, keeping its id, with the
+    /// images of its cels (one for each layer, from the bottom up). It becomes
+    /// the active frame
+    CreateFrame(usize, Frame, Vec<IMG>),
+    /// Move a frame to an index
+    MoveFrame(FrameId, usize),
+    /// Set for how long a frame is shown, in milliseconds
+    SetFrameDuration(FrameId, u32),
+    /// Replace all the animation tags
+    SetAnimationTags(Vec<AnimationTag>),
+    /// Make a frame the active one, so that the atomic actions applied after
+    /// it change the cels of that frame
+    SwitchFrame(FrameId),
 }
 
 impl<IMG> Debug for AtomicAction<IMG> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::SetPixel(i, p, c) => f
                 .debug_tuple("SetPixel")
@@ -70,33 +263,333 @@ impl<IMG> Debug for AtomicAction<IMG> {
             Self::DestroyLayer(i) => f.debug_tuple("DestroyLayer").field(&i).finish(),
             Self::CreateLayer(i, _) => f.debug_tuple("CreateLayer").field(&i).finish(),
             Self::SetLayerCanvas(i, _) => f.debug_tuple("SetLayerCanvas").field(&i).finish(),
+            Self::SetArea(i, p, _) => f.debug_tuple("SetArea").field(&i).field(&p).finish(),
+            Self::SetLayerBlendMode(i, mode) => f
+                .debug_tuple("SetLayerBlendMode")
+                .field(&i)
+                .field(&mode)
+                .finish(),
+            Self::SetGroups(groups) => f.debug_tuple("SetGroups").field(&groups.len()).finish(),
+            Self::SetLayerGroup(i, group) => f
+                .debug_tuple("SetLayerGroup")
+                .field(&i)
+                .field(&group)
+                .finish(),
+            Self::SetLayerName(i, name) => f
+                .debug_tuple("SetLayerName")
+                .field(&i)
+                .field(&name)
+                .finish(),
+            Self::SetLayerMetadata(i, key, _) => f
+                .debug_tuple("SetLayerMetadata")
+                .field(&i)
+                .field(&key)
+                .finish(),
+            Self::SetLayerMask(i, mask) => f
+                .debug_tuple("SetLayerMask")
+                .field(&i)
+                .field(&mask.is_some())
+                .finish(),
+            Self::SetMaskPixel(i, p, c) => f
+                .debug_tuple("SetMaskPixel")
+                .field(&i)
+                .field(&p)
+                .field(&c)
+                .finish(),
+            Self::SetMaskArea(i, p, _) => f.debug_tuple("SetMaskArea").field(&i).field(&p).finish(),
+            Self::SetLayerOffset(i, p) => {
+                f.debug_tuple("SetLayerOffset").field(&i).field(&p).finish()
+            }
+            Self::SetCanvasSize(size) => f.debug_tuple("SetCanvasSize").field(&size).finish(),
+            Self::SetLayerCels(i, imgs) => f
+                .debug_tuple("SetLayerCels")
+                .field(&i)
+                .field(&imgs.len())
+                .finish(),
+            Self::DestroyFrame(id) => f.debug_tuple("DestroyFrame").field(&id).finish(),
+            Self::CreateFrame(i, frame, _) => f
+                .debug_tuple("CreateFrame")
+                .field(&i)
+                .field(&frame.id())
+                .finish(),
+            Self::MoveFrame(id, i) => f.debug_tuple("MoveFrame").field(&id).field(&i).finish(),
+            Self::SetFrameDuration(id, duration) => f
+                .debug_tuple("SetFrameDuration")
+                .field(&id)
+                .field(&duration)
+                .finish(),
+            Self::SetAnimationTags(tags) => f.debug_tuple("SetAnimationTags").field(&tags).finish(),
+            Self::SwitchFrame(id) => f.debug_tuple("SwitchFrame").field(&id).finish(),
         }
     }
 }
 
 impl<IMG: Bitmap> AtomicAction<IMG> {
-    pub fn set_pixel_vec(i: LayerIndex, values: Vec<(Point<i32>, Color)>) -> Vec<Self> {
+    pub fn set_pixel_vec(id: LayerId, values: Vec<(Point<i32>, Color)>) -> Vec<Self> {
         values
             .into_iter()
-            .map(|(p, c)| AtomicAction::SetPixel(i, p, c))
+            .map(|(p, c)| AtomicAction::SetPixel(id, p, c))
             .collect()
     }
 
-    pub fn apply(self, layers: &mut Layers<IMG>) -> CanvasEffect {
+    pub fn set_mask_pixel_vec(id: LayerId, values: Vec<(Point<i32>, Color)>) -> Vec<Self> {
+        values
+            .into_iter()
+            .map(|(p, c)| AtomicAction::SetMaskPixel(id, p, c))
+            .collect()
+    }
+
+    /// Check that this atomic action can be applied, i.e. that the layer (or
+    /// frame, or mask) it refers to exists, and that what it adds fits in the
+    /// layers (indices within bounds, one cel per frame, etc.)
+    pub fn check(&self, layers: &Layers<IMG>) -> Result<()> {
         match self {
-            Self::SetPixel(i, p, color) => {
-                layers.canvas_at_mut(i).set_pixel(p, color);
+            Self::SetPixel(id, _, _)
+            | Self::DestroyLayer(id)
+            | Self::SetLayerCanvas(id, _)
+            | Self::SetArea(id, _, _)
+            | Self::SetLayerBlendMode(id, _)
+            | Self::SetLayerName(id, _)
+            | Self::SetLayerMetadata(id, _, _)
+            | Self::SetLayerGroup(id, _)
+            | Self::SetLayerMask(id, _)
+            | Self::SetLayerOffset(id, _) => layers.index_of(*id).map(|_| ()),
+            Self::SetLayerCels(id, imgs) => {
+                layers.index_of(*id)?;
+                check_count(imgs.len(), layers.frame_count())
+            }
+            Self::CreateLayer(i, layer) => {
+                if *i > layers.count() {
+                    return Err(Error::InvalidLayerIndex(*i, layers.count()));
+                }
+                check_count(layer.cel_count(), layers.frame_count())
             }
-            Self::DestroyLayer(i) => {
-                layers.delete(i);
+            Self::CreateFrame(i, _, imgs) => {
+                if *i > layers.frame_count() {
+                    return Err(Error::InvalidFrameIndex(*i, layers.frame_count()));
+                }
+                check_count(imgs.len(), layers.count())
+            }
+            Self::MoveFrame(id, i) => {
+                layers.frame_index_of(*id)?;
+                match *i < layers.frame_count() {
+                    true => Ok(()),
+                    false => Err(Error::InvalidFrameIndex(*i, layers.frame_count())),
+                }
+            }
+            Self::SetGroups(groups) => layers.check_groups(groups),
+            Self::SetAnimationTags(tags) => tags.iter().try_for_each(|tag| layers.check_tag(tag)),
+            Self::SetMaskPixel(id, _, _) | Self::SetMaskArea(id, _, _) => {
+                match layers.by_id(*id)?.mask() {
+                    Some(_) => Ok(()),
+                    None => Err(Error::MissingLayerMask(*id)),
+                }
+            }
+            Self::DestroyFrame(id) | Self::SetFrameDuration(id, _) | Self::SwitchFrame(id) => {
+                layers.frame_index_of(*id).map(|_| ())
+            }
+            Self::SetCanvasSize(_) => Ok(()),
+        }
+    }
+
+    /// Apply this atomic action, returning the [`CanvasEffect`] it caused and
+    /// the atomic action that reverses it, capturing whatever was overwritten.
+    /// Fails if the layer (or frame) it refers to doesn't exist
+    pub fn apply(self, layers: &mut Layers<IMG>) -> Result<(CanvasEffect, Self)> {
+        let reversal = match self {
+            Self::SetPixel(id, p, color) => {
+                let i = layers.index_of(id)?;
+                let old = layers
+                    .canvas_at_mut(i)
+                    .set_pixel(p, color)
+                    .map_or(color, |(_, old)| old);
+                Self::SetPixel(id, p, old)
+            }
+            Self::DestroyLayer(id) => {
+                let i = layers.index_of(id)?;
+                let layer = layers.delete(i);
+                Self::CreateLayer(i, layer)
             }
             Self::CreateLayer(i, layer) => {
-                layers.add_at(i, layer);
+                let id = layer.id();
+                layers.restore_at(i, layer);
+                Self::DestroyLayer(id)
             }
-            Self::SetLayerCanvas(i, img) => {
+            Self::SetLayerCanvas(id, img) => {
+                let i = layers.index_of(id)?;
+                let old = layers.canvas_at_mut(i).take_inner();
                 layers.canvas_at_mut(i).set_img(img);
+                Self::SetLayerCanvas(id, old)
             }
-        }
-        CanvasEffect::Layer
+            Self::SetArea(id, p, img) => {
+                let i = layers.index_of(id)?;
+                let old = layers.canvas_at_mut(i).replace_area(p, &img);
+                Self::SetArea(id, p, old)
+            }
+            Self::SetLayerBlendMode(id, mode) => {
+                let old = layers.set_blend_mode(layers.index_of(id)?, mode);
+                Self::SetLayerBlendMode(id, old)
+            }
+            Self::SetGroups(groups) => Self::SetGroups(layers.set_groups(groups)),
+            Self::SetLayerName(id, name) => {
+                let old = layers.rename(layers.index_of(id)?, name);
+                Self::SetLayerName(id, old)
+            }
+            Self::SetLayerMetadata(id, key, value) => {
+                let old = layers
+                    .set_metadata(layers.index_of(id)?, key.clone(), value)
+                    .expect("metadata keys are validated before being stored");
+                Self::SetLayerMetadata(id, key, old)
+            }
+            Self::SetLayerGroup(id, group) => {
+                let old = layers.set_layer_group(layers.index_of(id)?, group);
+                Self::SetLayerGroup(id, old)
+            }
+            Self::SetLayerMask(id, mask) => {
+                let old = layers.set_mask(layers.index_of(id)?, mask);
+                Self::SetLayerMask(id, old)
+            }
+            Self::SetMaskPixel(id, p, color) => {
+                let old = layers
+                    .mask_at_mut(layers.index_of(id)?)
+                    .ok_or(Error::MissingLayerMask(id))?
+                    .set_pixel(p, color)
+                    .map_or(color, |(_, old)| old);
+                Self::SetMaskPixel(id, p, old)
+            }
+            Self::SetMaskArea(id, p, img) => {
+                let old = layers
+                    .mask_at_mut(layers.index_of(id)?)
+                    .ok_or(Error::MissingLayerMask(id))?
+                    .replace_area(p, &img);
+                Self::SetMaskArea(id, p, old)
+            }
+            Self::SetLayerOffset(id, p) => {
+                let old = layers.set_offset(layers.index_of(id)?, p);
+                Self::SetLayerOffset(id, old)
+            }
+            Self::SetCanvasSize(size) => Self::SetCanvasSize(layers.set_canvas_size(size)),
+            Self::SetLayerCels(id, imgs) => {
+                let old = layers.get_mut(layers.index_of(id)?).set_cels(imgs);
+                Self::SetLayerCels(id, old)
+            }
+            Self::DestroyFrame(id) => {
+                let i = layers.frame_index_of(id)?;
+                let (frame, imgs) = layers.delete_frame(i);
+                Self::CreateFrame(i, frame, imgs)
+            }
+            Self::CreateFrame(i, frame, imgs) => {
+                let id = frame.id();
+                layers.restore_frame(i, frame, imgs);
+                layers.switch_frame(i);
+                Self::DestroyFrame(id)
+            }
+            Self::MoveFrame(id, i) => {
+                let from = layers.frame_index_of(id)?;
+                layers.move_frame(from, i);
+                Self::MoveFrame(id, from)
+            }
+            Self::SetFrameDuration(id, duration) => {
+                let old = layers.set_frame_duration(layers.frame_index_of(id)?, duration);
+                Self::SetFrameDuration(id, old)
+            }
+            Self::SetAnimationTags(tags) => Self::SetAnimationTags(layers.set_tags(tags)),
+            Self::SwitchFrame(id) => {
+                let old = layers.active_frame_id();
+                layers.switch_frame(layers.frame_index_of(id)?);
+                Self::SwitchFrame(old)
+            }
+        };
+
+        Ok((CanvasEffect::Layer, reversal))
+    }
+}
+
+/// Check that there is one cel for each frame (or for each layer)
+fn check_count(count: usize, expected: usize) -> Result<()> {
+    match count == expected {
+        true => Ok(()),
+        false => Err(Error::InvalidCelCount(count, expected)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::TestImage;
+    use crate::color::{BLACK, TRANSPARENT};
+
+    #[test]
+    fn failed_apply_reverts() {
+        let mut layers = Layers::<TestImage>::new(Size::new(2, 2));
+        let id = layers.active_id();
+        let missing = LayerId(id.0 + 1);
+        let p = Point::new(0, 0);
+        // Atomic actions are applied from last to first
+        let action = Action::new(
+            "Test",
+            vec![
+                AtomicAction::SetPixel(missing, p, BLACK),
+                AtomicAction::SetPixel(id, p, BLACK),
+            ],
+        );
+
+        let (e, action) = action.apply(&mut layers).unwrap_err();
+        assert!(matches!(e, Error::MissingLayer(i) if i == missing));
+        assert_eq!(layers.active_canvas().pixel(p), TRANSPARENT);
+        assert_eq!(action.label(), "Test");
+        assert_eq!(action.actions.len(), 2);
+        assert!(matches!(action.actions[1], AtomicAction::SetPixel(i, _, BLACK) if i == id));
+    }
+
+    #[test]
+    fn check_inserted_items() {
+        let mut layers = Layers::<TestImage>::new(Size::new(2, 2));
+        layers.new_frame_at(1);
+        let new_layer = || Layer::<TestImage>::new(Size::new(2, 2));
+        let group = |id, parent: Option<usize>| LayerGroup::new(GroupId(id), parent.map(GroupId));
+        let tag = |to| AnimationTag::new("Tag", 0, to, Default::default());
+        let check = |action: AtomicAction<TestImage>| action.check(&layers);
+
+        assert!(matches!(
+            check(AtomicAction::CreateLayer(2, new_layer())),
+            Err(Error::InvalidLayerIndex(2, 1))
+        ));
+        assert!(matches!(
+            check(AtomicAction::CreateLayer(1, new_layer())),
+            Err(Error::InvalidCelCount(1, 2))
+        ));
+        let cels = vec![TestImage::new(Size::new(2, 2), TRANSPARENT); 2];
+        assert!(check(AtomicAction::CreateLayer(1, Layer::from_cels(cels))).is_ok());
+
+        let frame = Frame::new(FrameId(5));
+        assert!(matches!(
+            check(AtomicAction::CreateFrame(3, frame.clone(), Vec::new())),
+            Err(Error::InvalidFrameIndex(3, 2))
+        ));
+        assert!(matches!(
+            check(AtomicAction::CreateFrame(2, frame, Vec::new())),
+            Err(Error::InvalidCelCount(0, 1))
+        ));
+
+        let groups = vec![group(0, None), group(1, Some(0))];
+        assert!(check(AtomicAction::SetGroups(groups)).is_ok());
+        assert!(matches!(
+            check(AtomicAction::SetGroups(vec![group(1, Some(0))])),
+            Err(Error::MissingLayerGroup(GroupId(0)))
+        ));
+        assert!(matches!(
+            check(AtomicAction::SetGroups(vec![
+                group(0, Some(1)),
+                group(1, Some(0))
+            ])),
+            Err(Error::LayerGroupCycle(_))
+        ));
+
+        assert!(check(AtomicAction::SetAnimationTags(vec![tag(1)])).is_ok());
+        assert!(matches!(
+            check(AtomicAction::SetAnimationTags(vec![tag(2)])),
+            Err(Error::InvalidAnimationTagRange(0, 2, 2))
+        ));
     }
 }
@@ -0,0 +1,16 @@
+use lapix::Error as LapixError;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Lapix(#[from] LapixError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid script: {0}")]
+    Script(#[from] serde_json::Error),
+    #[error("Failed to write metadata: {0}")]
+    Metadata(serde_json::Error),
+}
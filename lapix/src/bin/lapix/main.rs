@@ -0,0 +1,276 @@
+use clap::Parser;
+use lapix::{AnimationExport, Event, LoadProject, Point, SaveProject, Size, State, Transform};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+mod error;
+mod project;
+mod wrapped_image;
+
+use error::{Error, Result};
+use wrapped_image::WrappedImage;
+
+/// Headless batch operations on images and Tarsila projects.
+///
+/// The input is loaded, then the operations run in this order: script, resize,
+/// transforms, flatten. Finally, the result is exported and/or saved.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Image or Tarsila project (`.tarsila` file) to load
+    input: PathBuf,
+    /// JSON file containing a list of events to execute, e.g.
+    /// `[{"SetMainColor": {"r": 255, "g": 0, "b": 0, "a": 255}}, {"Bucket": {"x": 0, "y": 0}}]`
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// Resize the canvas, e.g. `64x32`
+    #[arg(long, value_parser = parse_size)]
+    resize: Option<Size<i32>>,
+    /// Apply a transform (e.g. `Silhouete`) to every layer in every frame.
+    /// Can be used more than once
+    #[arg(long = "transform", value_parser = parse_transform)]
+    transforms: Vec<Transform>,
+    /// Merge all visible layers into a single one
+    #[arg(long)]
+    flatten: bool,
+    /// Export the resulting image (PNG or JPEG)
+    #[arg(long)]
+    export: Option<PathBuf>,
+    /// Export a JSON file with where each frame is in the exported image, its
+    /// duration and the animation tags
+    #[arg(long)]
+    export_metadata: Option<PathBuf>,
+    /// Export the animation as an animated image (GIF, APNG or WebP)
+    #[arg(long)]
+    export_animation: Option<PathBuf>,
+    /// Index of the animation tag to export with `--export-animation` (all
+    /// frames are exported by default)
+    #[arg(long)]
+    animation_tag: Option<usize>,
+    /// How many times the animation exported with `--export-animation` is
+    /// repeated after playing once (it loops forever by default)
+    #[arg(long)]
+    repeat: Option<u16>,
+    /// Scale of the animation exported with `--export-animation` (e.g. `4`
+    /// to make each pixel a 4x4 square)
+    #[arg(long, default_value_t = 1)]
+    animation_scale: u8,
+    /// Export each layer as a PNG image (with the layer's name and metadata)
+    /// into a directory
+    #[arg(long)]
+    export_layers: Option<PathBuf>,
+    /// Save the result as a Tarsila project
+    #[arg(long)]
+    save_project: Option<PathBuf>,
+}
+
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("ERROR: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut state = load(&args.input)?;
+
+    if let Some(path) = args.script {
+        let events: Vec<Event> = serde_json::from_reader(std::fs::File::open(path)?)?;
+        for event in events {
+            state.execute(event)?;
+        }
+    }
+
+    if let Some(size) = args.resize {
+        state.execute(Event::ResizeCanvas(size))?;
+    }
+
+    for transform in args.transforms {
+        transform_all_layers(&mut state, transform)?;
+    }
+
+    if args.flatten {
+        state.execute(Event::FlattenImage)?;
+    }
+
+    if let Some(path) = args.export {
+        state.execute(Event::Save(path))?;
+    }
+
+    if let Some(path) = args.export_metadata {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, &state.spritesheet_metadata())
+            .map_err(Error::Metadata)?;
+    }
+
+    if let Some(path) = args.export_animation {
+        let settings = AnimationExport {
+            tag: args.animation_tag,
+            repeat: args.repeat,
+            scale: args.animation_scale,
+            ..Default::default()
+        };
+        state.execute(Event::ExportAnimation(path, settings))?;
+    }
+
+    if let Some(dir) = args.export_layers {
+        state.execute(Event::SaveLayers(dir))?;
+    }
+
+    if let Some(path) = args.save_project {
+        state.execute(Event::SaveProject(path))?;
+    }
+
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<State<WrappedImage>> {
+    let is_project = path.extension() == Some(OsStr::new("tarsila"));
+
+    let size = if is_project {
+        Size::new(1, 1)
+    } else {
+        let (w, h) = image::image_dimensions(path).map_err(lapix::Error::from)?;
+        Size::new(w as i32, h as i32)
+    };
+
+    let mut state = State::new(
+        size,
+        Some(LoadProject(project::load)),
+        Some(SaveProject(project::save)),
+    );
+
+    if is_project {
+        state.execute(Event::LoadProject(path.to_owned()))?;
+    } else {
+        state.execute(Event::OpenFile(path.to_owned()))?;
+        // The imported image is a free image, anchor it to the canvas
+        state.execute(Event::ClearSelection)?;
+    }
+
+    Ok(state)
+}
+
+fn parse_size(s: &str) -> std::result::Result<Size<i32>, String> {
+    let err = || format!("invalid size `{s}`, expected something like `64x32`");
+    let (w, h) = s.split_once('x').ok_or_else(err)?;
+    let w = w.parse().map_err(|_| err())?;
+    let h = h.parse().map_err(|_| err())?;
+
+    Ok(Size::new(w, h))
+}
+
+fn parse_transform(s: &str) -> std::result::Result<Transform, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_owned()))
+        .map_err(|_| format!("unknown transform `{s}`"))
+}
+
+/// Transforms only apply to the selection, so select the whole image of each
+/// layer (which can be partly outside of the canvas) in every frame before
+/// applying it. Reference layers can't be switched to and locked layers can't
+/// be edited, so both are left as they are
+fn transform_all_layers(state: &mut State<WrappedImage>, transform: Transform) -> Result<()> {
+    let active_layer = state.layers().active_id();
+    let active_frame = state.layers().active_frame_id();
+    let frames: Vec<_> = state.layers().frames().iter().map(|f| f.id()).collect();
+
+    for frame in frames {
+        state.execute(Event::SwitchFrame(frame))?;
+
+        for i in 0..state.layers().count() {
+            let layer = state.layers().get(i);
+            if layer.reference().is_some() || layer.locked() {
+                continue;
+            }
+            let id = layer.id();
+            state.execute(Event::SwitchLayer(id))?;
+
+            let rect = state.canvas().rect();
+            state.execute(Event::StartSelection(rect.pos()))?;
+            state.execute(Event::EndSelection(Point::new(
+                rect.x + rect.w - 1,
+                rect.y + rect.h - 1,
+            )))?;
+            state.execute(Event::ApplyTransform(transform.clone()))?;
+            state.execute(Event::ClearSelection)?;
+        }
+    }
+
+    state.execute(Event::SwitchFrame(active_frame))?;
+    state.execute(Event::SwitchLayer(active_layer))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapix::{Bitmap, Color};
+
+    const RED: Color = Color::new(255, 0, 0, 255);
+    const BLACK: Color = Color::new(0, 0, 0, 255);
+
+    fn args(input: PathBuf) -> Args {
+        Args::parse_from([OsStr::new("lapix"), input.as_os_str()])
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("64x32").unwrap(), Size::new(64, 32));
+        assert!(parse_size("64").is_err());
+        assert!(parse_size("64x").is_err());
+        assert!(parse_size("ax32").is_err());
+    }
+
+    #[test]
+    fn transform() {
+        assert_eq!(parse_transform("Silhouete").unwrap(), Transform::Silhouete);
+        assert_eq!(parse_transform("Identity").unwrap(), Transform::Identity);
+        assert!(parse_transform("Sepia").is_err());
+    }
+
+    #[test]
+    fn transform_outside_canvas_and_in_all_frames() {
+        let mut state = State::<WrappedImage>::new(Size::new(2, 2), None, None);
+        let id = state.layers().get(0).id();
+        state.execute(Event::SetMainColor(RED)).unwrap();
+        state.execute(Event::BrushStart).unwrap();
+        state.execute(Event::BrushStroke(Point::ZERO)).unwrap();
+        state.execute(Event::BrushEnd).unwrap();
+        state
+            .execute(Event::ShiftLayer(id, Point::new(-1, 0)))
+            .unwrap();
+        state.execute(Event::AddFrame).unwrap();
+        state.execute(Event::Bucket(Point::ZERO)).unwrap();
+        let frame = state.layers().frames()[0].id();
+        state.execute(Event::SwitchFrame(frame)).unwrap();
+
+        transform_all_layers(&mut state, Transform::Silhouete).unwrap();
+
+        let layer = state.layers().get(0);
+        assert_eq!(layer.cel(0).pixel(Point::new(-1, 0)), BLACK);
+        assert_eq!(layer.cel(1).pixel(Point::new(1, 1)), BLACK);
+        assert_eq!(state.layers().active_frame_id(), frame);
+    }
+
+    #[test]
+    fn export_transformed_image() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("lapix_cli_export_transformed_image_in.png");
+        let output = dir.join("lapix_cli_export_transformed_image_out.png");
+        let mut img = image::RgbaImage::new(2, 1);
+        img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        img.save(&input).unwrap();
+
+        run(Args {
+            transforms: vec![Transform::Silhouete],
+            export: Some(output.clone()),
+            ..args(input)
+        })
+        .unwrap();
+
+        let img = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(0, 0), &image::Rgba([0, 0, 0, 255]));
+        assert_eq!(img.get_pixel(1, 0), &image::Rgba([0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,19 @@
+use lapix::{Error, Result};
+use std::path::PathBuf;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// These follow the same file layout used by `tarsila`, so projects can be
+// exchanged between both programs. The project header itself is handled by
+// lapix, which skips anything before `lapix::MAGIC` when loading.
+
+pub fn save(path: PathBuf, bytes: Vec<u8>) -> Result<()> {
+    let mut file = format!("lapix {VERSION} ").into_bytes();
+    file.extend(bytes);
+
+    std::fs::write(&path, file).map_err(|e| Error::ProjectIo(path, e))
+}
+
+pub fn load(path: PathBuf) -> Result<Vec<u8>> {
+    std::fs::read(&path).map_err(|e| Error::ProjectIo(path, e))
+}
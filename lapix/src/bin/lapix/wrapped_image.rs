@@ -0,0 +1,87 @@
+use image::RgbaImage;
+use lapix::{Bitmap, Color, Point, Size};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Image type used by the command-line tool. Like `tarsila`'s images, it is
+/// serialized as PNG bytes, so project files are compatible between both.
+#[derive(Debug, Clone)]
+pub struct WrappedImage(pub RgbaImage);
+
+impl Serialize for WrappedImage {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::Error as _;
+        let bytes = self
+            .png_bytes()
+            .map_err(|e| S::Error::custom(format!("Error serializing image: {}", e)))?;
+
+        ser.serialize_bytes(&bytes)
+    }
+}
+
+impl<'a> Deserialize<'a> for WrappedImage {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        use serde::de::Error as _;
+        let vec = Vec::<u8>::deserialize(d)?;
+        Self::try_from_file_bytes(vec)
+            .map_err(|e| D::Error::custom(format!("Error deserializing image: {}", e)))
+    }
+}
+
+impl Bitmap for WrappedImage {
+    fn new(size: Size<i32>, color: Color) -> Self {
+        let pixel = image::Rgba([color.r, color.g, color.b, color.a]);
+
+        Self(RgbaImage::from_pixel(size.x as u32, size.y as u32, pixel))
+    }
+
+    fn size(&self) -> Size<i32> {
+        (self.width(), self.height()).into()
+    }
+
+    fn width(&self) -> i32 {
+        self.0.width() as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.0.height() as i32
+    }
+
+    fn pixel(&self, p: Point<i32>) -> Color {
+        let [r, g, b, a] = self.0.get_pixel(p.x as u32, p.y as u32).0;
+
+        Color::new(r, g, b, a)
+    }
+
+    fn set_pixel(&mut self, p: Point<i32>, color: Color) {
+        let pixel = image::Rgba([color.r, color.g, color.b, color.a]);
+        self.0.put_pixel(p.x as u32, p.y as u32, pixel);
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.0.as_raw()
+    }
+
+    fn from_parts(size: Size<i32>, bytes: &[u8]) -> Self {
+        Self(
+            RgbaImage::from_raw(size.x as u32, size.y as u32, bytes.to_owned())
+                .expect("byte count should match the image size"),
+        )
+    }
+
+    fn set_from(&mut self, other: &Self) {
+        let w = std::cmp::min(self.width(), other.width());
+        let h = std::cmp::min(self.height(), other.height());
+
+        for x in 0..w {
+            for y in 0..h {
+                self.set_pixel((x, y).into(), other.pixel((x, y).into()));
+            }
+        }
+    }
+}
@@ -17,10 +17,13 @@ pub enum CanvasEffect {
 
 /// The canvas is the area where drawing can take place. Each layer has a
 /// canvas, and the canvas in turn holds an image internally to represent the
-/// drawing on it.
+/// drawing on it. The image may be placed anywhere (see [`Canvas::offset`]),
+/// and all points taken or returned by the canvas are relative to the top left
+/// corner of the drawing, not of the image.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Canvas<IMG> {
     inner: IMG,
+    offset: Point<i32>,
 }
 
 impl<IMG: Bitmap> Canvas<IMG> {
@@ -28,6 +31,7 @@ impl<IMG: Bitmap> Canvas<IMG> {
     pub fn new(size: Size<i32>) -> Self {
         Self {
             inner: IMG::new(size, TRANSPARENT),
+            offset: Point::ZERO,
         }
     }
 
@@ -54,10 +58,21 @@ impl<IMG: Bitmap> Canvas<IMG> {
         self.inner.height()
     }
 
-    /// Get the rectangle representing the canvas dimensions, starting from the
-    /// origin at (0, 0).
+    /// Get the position of the top left corner of the inner image
+    pub fn offset(&self) -> Point<i32> {
+        self.offset
+    }
+
+    /// Move the inner image so that its top left corner is at a certain
+    /// position, returning the previous one
+    pub fn set_offset(&mut self, offset: Point<i32>) -> Point<i32> {
+        std::mem::replace(&mut self.offset, offset)
+    }
+
+    /// Get the rectangle representing the canvas dimensions, starting from its
+    /// offset
     pub fn rect(&self) -> Rect<i32> {
-        Rect::new(0, 0, self.width(), self.height())
+        Rect::new(self.offset.x, self.offset.y, self.width(), self.height())
     }
 
     /// Get a representation of the inner image as a slice of bytes
@@ -67,6 +82,7 @@ impl<IMG: Bitmap> Canvas<IMG> {
 
     /// Check whether a point is inside the canvas
     pub fn is_in_bounds(&self, p: Point<i32>) -> bool {
+        let p = p - self.offset;
         p.x >= 0 && p.y >= 0 && p.x < self.width() && p.y < self.height()
     }
 
@@ -96,9 +112,13 @@ impl<IMG: Bitmap> Canvas<IMG> {
         old_img
     }
 
-    /// Get the color of a pixel in a certain position in the canvas
+    /// Get the color of a pixel in a certain position in the canvas. Pixels
+    /// out of bounds are transparent
     pub fn pixel(&self, p: Point<i32>) -> Color {
-        self.inner.pixel(p)
+        match self.is_in_bounds(p) {
+            true => self.inner.pixel(p - self.offset),
+            false => TRANSPARENT,
+        }
     }
 
     /// Set the color of a pixel in a certain position in the canvas. If there
@@ -106,13 +126,13 @@ impl<IMG: Bitmap> Canvas<IMG> {
     /// which point needs to be set to which color to reverse the action.
     pub fn set_pixel(&mut self, p: Point<i32>, color: Color) -> Option<(Point<i32>, Color)> {
         if self.is_in_bounds(p) {
-            let old = self.inner.pixel(p);
+            let old = self.pixel(p);
 
             if color == old {
                 return None;
             }
 
-            self.inner.set_pixel(p, color);
+            self.inner.set_pixel(p - self.offset, color);
             return Some((p, old));
         }
 
@@ -160,27 +180,36 @@ impl<IMG: Bitmap> Canvas<IMG> {
     }
 
     /// Set an area of the canvas (determined by a rectangle) to a certain
-    /// color. Returns a set of reversals (points and colors they need to be set
-    /// to in order to reverse the action).
-    pub fn set_area(&mut self, area: Rect<i32>, color: Color) -> Vec<(Point<i32>, Color)> {
-        let mut reversals = Vec::new();
+    /// color. Returns the reversal (the position of the affected area and the
+    /// image it had before), if the area is inside the canvas.
+    pub fn set_area(&mut self, area: Rect<i32>, color: Color) -> Option<(Point<i32>, IMG)> {
+        let area = area.clip_to(self.rect());
+        if area.w <= 0 || area.h <= 0 {
+            return None;
+        }
 
+        let old = self.img_from_area(area);
         for i in 0..area.w {
             for j in 0..area.h {
-                if let Some(action) = self.set_pixel((i + area.x, j + area.y).into(), color) {
-                    reversals.push(action);
-                }
+                let p = Point::new(i + area.x, j + area.y);
+                self.inner.set_pixel(p - self.offset, color);
             }
         }
 
-        reversals
+        Some((area.pos(), old))
     }
 
     /// Paste a free image into the canvas, overriding the contents that existed
-    /// below that area. Returns a set of reversals (points and colors they need
-    /// to be set to in order to reverse the action).
-    pub fn paste_obj(&mut self, obj: &FreeImage<IMG>) -> Vec<(Point<i32>, Color)> {
-        let mut reversals = Vec::new();
+    /// below that area. Returns the reversal (the position of the affected
+    /// area and the image it had before), if the free image is inside the
+    /// canvas.
+    pub fn paste_obj(&mut self, obj: &FreeImage<IMG>) -> Option<(Point<i32>, IMG)> {
+        let area = obj.rect.clip_to(self.rect());
+        if area.w <= 0 || area.h <= 0 {
+            return None;
+        }
+
+        let old = self.img_from_area(area);
         for i in 0..obj.rect.w {
             for j in 0..obj.rect.h {
                 let ij = Point::new(i, j);
@@ -189,24 +218,45 @@ impl<IMG: Bitmap> Canvas<IMG> {
 
                 if self.is_in_bounds(p) {
                     let blended = color.blend_over(self.pixel(p));
-                    if let Some(action) = self.set_pixel(p, blended) {
-                        reversals.push(action);
-                    }
+                    self.inner.set_pixel(p - self.offset, blended);
                 }
             }
         }
 
-        reversals
+        Some((area.pos(), old))
     }
 
-    /// Paint an enclosed area with a certain color. Returns a set of reversals
-    /// (points and colors they need to be set to in order to reverse the
-    /// action).
-    pub fn bucket(&mut self, p: Point<i32>, color: Color) -> Vec<(Point<i32>, Color)> {
+    /// Replace an area of the canvas starting at a certain position with an
+    /// image (without blending). Returns the image that was in that area
+    /// before, so that calling this again with it reverses the action.
+    pub fn replace_area(&mut self, pos: Point<i32>, img: &IMG) -> IMG {
+        let area = Rect::new(pos.x, pos.y, img.width(), img.height());
+        let old = self.img_from_area(area);
+
+        for i in 0..area.w {
+            for j in 0..area.h {
+                let ij = Point::new(i, j);
+                let p = ij + pos;
+
+                if self.is_in_bounds(p) {
+                    self.inner.set_pixel(p - self.offset, img.pixel(ij));
+                }
+            }
+        }
+
+        old
+    }
+
+    /// Paint an enclosed area with a certain color. Returns the reversal (the
+    /// position of the smallest rectangle containing all painted pixels and the
+    /// image it had before), if anything was painted.
+    pub fn bucket(&mut self, p: Point<i32>, color: Color) -> Option<(Point<i32>, IMG)> {
+        // The fill works on the inner image, with points relative to it
+        let p = p - self.offset;
         let old_color = self.inner.pixel(p);
 
         if color == old_color {
-            return Vec::new();
+            return None;
         }
 
         let w = self.inner.width() as usize;
@@ -215,7 +265,7 @@ impl<IMG: Bitmap> Canvas<IMG> {
         let mut marked = vec![false; w * h];
         let mut visit = vec![(p.x, p.y)];
 
-        let mut reversals = Vec::new();
+        let (mut min, mut max) = (p, p);
 
         loop {
             if visit.is_empty() {
@@ -225,10 +275,9 @@ impl<IMG: Bitmap> Canvas<IMG> {
             let mut new_visit = Vec::new();
             while let Some((vx, vy)) = visit.pop() {
                 marked[(vy as usize) * w + vx as usize] = true;
-
-                if let Some(action) = self.set_pixel((vx, vy).into(), color) {
-                    reversals.push(action);
-                }
+                self.inner.set_pixel((vx, vy).into(), color);
+                min = Point::new(min.x.min(vx), min.y.min(vy));
+                max = Point::new(max.x.max(vx), max.y.max(vy));
 
                 for (nx, ny) in self.neighbors(vx, vy).into_iter().flatten() {
                     let ind = (ny as usize) * w + nx as usize;
@@ -242,7 +291,22 @@ impl<IMG: Bitmap> Canvas<IMG> {
             visit.append(&mut new_visit);
         }
 
-        reversals
+        // Every painted pixel had the old color, and all others in the area
+        // were left untouched
+        let area = Rect::new(min.x, min.y, max.x - min.x + 1, max.y - min.y + 1);
+        let pos = area.pos() + self.offset;
+        let mut old = self.img_from_area(Rect::new(pos.x, pos.y, area.w, area.h));
+        for i in 0..area.w {
+            for j in 0..area.h {
+                let ij = Point::new(i, j);
+                let p = ij + area.pos();
+                if marked[(p.y as usize) * w + p.x as usize] {
+                    old.set_pixel(ij, old_color);
+                }
+            }
+        }
+
+        Some((pos, old))
     }
 
     fn neighbors(&self, x: i32, y: i32) -> [Option<(i32, i32)>; 4] {
@@ -276,7 +340,7 @@ impl<IMG: Bitmap> Canvas<IMG> {
                 let ij = Point::new(i, j);
                 let p = area.pos() + ij;
 
-                if p.x < self.width() && p.y < self.height() {
+                if self.is_in_bounds(p) {
                     let color = self.pixel(p);
                     img.set_pixel(ij, color);
                 }
@@ -318,6 +382,25 @@ mod tests {
         assert_eq!(canvas.is_in_bounds(p.into()), res);
     }
 
+    #[test]
+    fn offset() {
+        let black = Color::new(0, 0, 0, 255);
+        let mut canvas = Canvas::<TestImage>::new(Size::new(2, 2));
+        canvas.set_offset(Point::new(-1, 3));
+        canvas.set_pixel(Point::new(0, 4), black);
+
+        assert_eq!(canvas.rect(), Rect::new(-1, 3, 2, 2));
+        assert!(canvas.is_in_bounds(Point::new(-1, 3)));
+        assert!(!canvas.is_in_bounds(Point::new(1, 3)));
+        assert_eq!(canvas.inner().pixel(Point::new(1, 1)), black);
+        assert_eq!(canvas.pixel(Point::new(0, 4)), black);
+        assert_eq!(canvas.pixel(Point::new(0, 0)), TRANSPARENT);
+        assert_eq!(
+            canvas.bucket(Point::new(0, 4), TRANSPARENT).unwrap().0,
+            Point::new(0, 4)
+        );
+    }
+
     #[test]
     fn resize_same() {
         let black = Color::new(0, 0, 0, 255);
@@ -403,6 +486,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_area_reversal() {
+        let mut canvas = Canvas::<TestImage>::new(Size::new(5, 5));
+        let black = Color::new(0, 0, 0, 255);
+        canvas.set_pixel(Point::new(4, 4), black);
+        let (pos, old) = canvas.set_area(Rect::new(2, 2, 5, 5), black).unwrap();
+
+        assert_eq!(pos, Point::new(2, 2));
+        assert_eq!(old.size(), Size::new(3, 3));
+        canvas.replace_area(pos, &old);
+        assert_points(&canvas, &[(4, 4)]);
+    }
+
+    #[test]
+    fn bucket_reversal() {
+        let mut canvas = Canvas::<TestImage>::new(Size::new(5, 5));
+        let black = Color::new(0, 0, 0, 255);
+        let red = Color::new(255, 0, 0, 255);
+        for i in 0..5 {
+            canvas.set_pixel(Point::new(2, i), black);
+        }
+        let (pos, old) = canvas.bucket(Point::new(3, 1), red).unwrap();
+
+        assert_eq!(pos, Point::new(3, 0));
+        assert_eq!(old.size(), Size::new(2, 5));
+        canvas.replace_area(pos, &old);
+        assert_points(&canvas, &[(2, 0), (2, 1), (2, 2), (2, 3), (2, 4)]);
+    }
+
     #[test]
     fn bucket() {
         let mut canvas = Canvas::<TestImage>::new(Size::new(5, 5));
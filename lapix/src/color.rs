@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 pub const TRANSPARENT: Color = Color::new(0, 0, 0, 0);
 /// The color black
 pub const BLACK: Color = Color::new(0, 0, 0, 255);
+/// The color white
+pub const WHITE: Color = Color::new(255, 255, 255, 255);
 
 /// Represents an RGBA color, with component values from 0-255
 #[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
@@ -16,6 +18,132 @@ pub struct Color {
     pub a: u8,
 }
 
+/// How the colors of a layer are combined with the colors of the layers below
+/// it
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendMode {
+    /// The layer is simply drawn on top of the ones below
+    #[default]
+    Normal,
+    /// Multiply the colors, resulting in a darker color
+    Multiply,
+    /// Multiply the inverse of the colors, resulting in a lighter color
+    Screen,
+    /// Multiply dark colors of the layers below and screen light ones
+    Overlay,
+    /// Add the colors
+    Add,
+    /// Subtract the colors of the layer from the ones below
+    Subtract,
+    /// Keep the darkest value of each component
+    Darken,
+    /// Keep the lightest value of each component
+    Lighten,
+    /// Use the hue and saturation of the layer with the luminosity of the
+    /// layers below
+    Color,
+    /// Use the hue of the layer with the saturation and luminosity of the
+    /// layers below
+    Hue,
+}
+
+impl BlendMode {
+    /// All blend modes
+    pub const ALL: [Self; 10] = [
+        Self::Normal,
+        Self::Multiply,
+        Self::Screen,
+        Self::Overlay,
+        Self::Add,
+        Self::Subtract,
+        Self::Darken,
+        Self::Lighten,
+        Self::Color,
+        Self::Hue,
+    ];
+
+    /// Get the name of this blend mode
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "Normal",
+            Self::Multiply => "Multiply",
+            Self::Screen => "Screen",
+            Self::Overlay => "Overlay",
+            Self::Add => "Add",
+            Self::Subtract => "Subtract",
+            Self::Darken => "Darken",
+            Self::Lighten => "Lighten",
+            Self::Color => "Color",
+            Self::Hue => "Hue",
+        }
+    }
+
+    /// Combine the RGB components of a color (`src`) with the ones of the
+    /// color below it (`dst`), ignoring transparency
+    fn mix(&self, src: [f32; 3], dst: [f32; 3]) -> [f32; 3] {
+        let each = |f: fn(f32, f32) -> f32| [0, 1, 2].map(|i| f(src[i], dst[i]));
+
+        match self {
+            Self::Normal => src,
+            Self::Multiply => each(|s, d| s * d),
+            Self::Screen => each(|s, d| s + d - s * d),
+            Self::Overlay => each(|s, d| {
+                if d <= 0.5 {
+                    2. * s * d
+                } else {
+                    1. - 2. * (1. - s) * (1. - d)
+                }
+            }),
+            Self::Add => each(|s, d| (s + d).min(1.)),
+            Self::Subtract => each(|s, d| (d - s).max(0.)),
+            Self::Darken => each(f32::min),
+            Self::Lighten => each(f32::max),
+            Self::Color => with_lum(src, lum(dst)),
+            Self::Hue => with_lum(with_sat(src, sat(dst)), lum(dst)),
+        }
+    }
+}
+
+// Helpers for the non-separable blend modes, as defined by the W3C compositing
+// specification
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn with_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    let c = c.map(|x| x + d);
+    let l = lum(c);
+    let min = c[0].min(c[1]).min(c[2]);
+    let max = c[0].max(c[1]).max(c[2]);
+
+    c.map(|x| {
+        if min < 0. {
+            l + (x - l) * l / (l - min)
+        } else if max > 1. {
+            l + (x - l) * (1. - l) / (max - l)
+        } else {
+            x
+        }
+    })
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn with_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let min = c[0].min(c[1]).min(c[2]);
+    let max = c[0].max(c[1]).max(c[2]);
+
+    if max > min {
+        c.map(|x| (x - min) * s / (max - min))
+    } else {
+        [0.; 3]
+    }
+}
+
 /// Represents an RGBA color, with component values from 0-1
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ColorF32 {
@@ -114,6 +242,28 @@ impl Color {
         .into()
     }
 
+    /// Blend this color on top of another using a [`BlendMode`]. Where the
+    /// color below is transparent, this color is kept as is.
+    pub fn blend_over_with(&self, other: Self, mode: BlendMode) -> Self {
+        if mode == BlendMode::Normal {
+            return self.blend_over(other);
+        }
+
+        let fg = ColorF32::from(*self);
+        let bg = ColorF32::from(other);
+        let src = [fg.r, fg.g, fg.b];
+        let dst = [bg.r, bg.g, bg.b];
+        let mixed = mode.mix(src, dst);
+
+        let res_alpha = fg.a + bg.a * (1. - fg.a);
+        let [r, g, b] = [0, 1, 2].map(|i| {
+            let src = (1. - bg.a) * src[i] + bg.a * mixed[i];
+            (src * fg.a + dst[i] * bg.a * (1. - fg.a)) / res_alpha
+        });
+
+        ColorF32::new(r, g, b, res_alpha).into()
+    }
+
     /// Get the resulting color from this one but with the alpha multiplied by
     /// another alpha. For example, if this color has full opacity (alpha = 255)
     /// but it's in a layer with 50% opacity (alpha = 127), the resulting color
@@ -173,6 +323,28 @@ mod tests {
         assert_eq!(a.blend_over(b), res.into(), "colors: {a:?} over {b:?}");
     }
 
+    #[test_case(BlendMode::Normal, (200, 100, 50, 255), (100, 100, 100, 255), (200, 100, 50, 255))]
+    #[test_case(BlendMode::Multiply, (255, 127, 0, 255), (100, 100, 100, 255), (100, 49, 0, 255))]
+    #[test_case(BlendMode::Screen, (255, 127, 0, 255), (100, 100, 100, 255), (255, 177, 100, 255))]
+    #[test_case(BlendMode::Overlay, (255, 127, 0, 255), (100, 200, 100, 255), (200, 199, 0, 255))]
+    #[test_case(BlendMode::Add, (200, 100, 0, 255), (100, 100, 100, 255), (255, 200, 100, 255))]
+    #[test_case(BlendMode::Subtract, (200, 50, 0, 255), (100, 100, 100, 255), (0, 50, 100, 255))]
+    #[test_case(BlendMode::Darken, (200, 50, 0, 255), (100, 100, 100, 255), (100, 50, 0, 255))]
+    #[test_case(BlendMode::Lighten, (200, 50, 0, 255), (100, 100, 100, 255), (200, 100, 100, 255))]
+    #[test_case(BlendMode::Color, (255, 0, 0, 255), (100, 100, 100, 255), (255, 33, 33, 255))]
+    #[test_case(BlendMode::Hue, (255, 0, 0, 255), (100, 100, 100, 255), (99, 99, 99, 255))]
+    #[test_case(BlendMode::Multiply, (255, 127, 0, 255), (100, 100, 100, 0), (255, 127, 0, 255))]
+    #[test_case(BlendMode::Multiply, (0, 0, 0, 127), (200, 200, 200, 255), (100, 100, 100, 255))]
+    fn blend_mode<C: Into<Color>>(mode: BlendMode, a: C, b: C, res: C) {
+        let a = a.into();
+        let b = b.into();
+        assert_eq!(
+            a.blend_over_with(b, mode),
+            res.into(),
+            "{mode:?}: {a:?} over {b:?}"
+        );
+    }
+
     #[test_case((0, 0, 0, 255), 255, (0, 0, 0, 255))]
     #[test_case((0, 0, 0, 255), 127, (0, 0, 0, 127))]
     #[test_case((0, 0, 0, 127), 127, (0, 0, 0, 63))]
@@ -20,4 +20,60 @@ pub enum Error {
     ReversalNotSet,
     #[error("Codec error: {0}")]
     CodecError(#[from] bincode::Error),
+    #[error("There is no macro with index {0}")]
+    MissingMacro(usize),
+    #[error(
+        "Unsupported project file version {0} (the latest supported one is {latest}), it \
+        was probably created by a newer version of the program",
+        latest = crate::PROJECT_VERSION
+    )]
+    UnsupportedProjectVersion(u32),
+    #[error("There is no layer group with id {0:?}")]
+    MissingLayerGroup(crate::GroupId),
+    #[error("Layers {0} to {1} can't be grouped (the range must exist and can't include only part of a group)")]
+    InvalidLayerGroupRange(usize, usize),
+    #[error("There is no layer with id {0:?}")]
+    MissingLayer(crate::LayerId),
+    #[error("There is no frame with id {0:?}")]
+    MissingFrame(crate::FrameId),
+    #[error("Invalid frame index {0} (there are only {1} frames)")]
+    InvalidFrameIndex(usize, usize),
+    #[error("Invalid frame duration {0} (it must be at least 1 millisecond)")]
+    InvalidFrameDuration(u32),
+    #[error("There is no animation tag with index {0}")]
+    MissingAnimationTag(usize),
+    #[error("Invalid animation tag range {0} to {1} (there are only {2} frames)")]
+    InvalidAnimationTagRange(usize, usize, usize),
+    #[error("Invalid onion skin falloff {0} (it must be between 0 and 1)")]
+    InvalidOnionSkinFalloff(f32),
+    #[error("Invalid animation export scale {0} (it must be at least 1)")]
+    InvalidExportScale(u8),
+    #[error("Invalid spritesheet layout {}x{} (it needs at least one column and row)", .0.x, .0.y)]
+    InvalidSpritesheet(crate::Size<u8>),
+    #[error("Layer {0:?} is not a reference layer")]
+    NotAReferenceLayer(crate::LayerId),
+    #[error("Invalid reference layer scale {0} (it must be positive)")]
+    InvalidReferenceScale(f32),
+    #[error("Layer {0:?} has no mask")]
+    MissingLayerMask(crate::LayerId),
+    #[error("Layer {0:?} is locked")]
+    LayerLocked(crate::LayerId),
+    #[error("Invalid layer metadata key `{0}`")]
+    InvalidMetadataKey(String),
+    #[error("PNG error: {0}")]
+    PngError(#[from] png::EncodingError),
+    #[error("WebP error: {0}")]
+    WebpError(#[from] image_webp::EncodingError),
+    #[error("Corrupt project file header")]
+    CorruptProjectHeader,
+    #[error("Failed to access project file {}: {1}", .0.display())]
+    ProjectIo(std::path::PathBuf, std::io::Error),
+    #[error("Layer {0:?} can't be merged down over visible layers, because of its blend mode")]
+    MergeBlendMode(crate::LayerId),
+    #[error("Invalid layer index {0} (there are only {1} layers)")]
+    InvalidLayerIndex(usize, usize),
+    #[error("Invalid number of cels {0} (there should be {1})")]
+    InvalidCelCount(usize, usize),
+    #[error("Layer group {0:?} is nested inside itself")]
+    LayerGroupCycle(crate::GroupId),
 }
@@ -1,4 +1,7 @@
-pub use crate::{Bitmap, CanvasEffect, Color, Point, Position, Size, Tool, Transform};
+pub use crate::{
+    AnimationExport, AnimationTag, Bitmap, BlendMode, CanvasEffect, Color, FrameId, GroupId,
+    LayerId, OnionSkin, Point, Position, Size, Tool, Transform,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::path::PathBuf;
@@ -12,8 +15,9 @@ use std::path::PathBuf;
 pub enum Event {
     /// Erase the image of the active canvas
     ClearCanvas,
-    /// Resize the whole canvas (all layers). Parts of the image that are out of
-    /// bounds will be lost, and new pixels will be transparent
+    /// Resize the whole canvas. Layers are not cropped: parts of them that are
+    /// out of bounds are kept (but not shown), and layers grow to cover any new
+    /// area, with transparent pixels
     ResizeCanvas(Size<i32>),
     /// This event should be triggered once the user starts drawing with the
     /// brush (i.e. mouse button is pressed)
@@ -37,6 +41,16 @@ pub enum Event {
     RemoveFromPalette(Color),
     /// Export the image to the defined path
     Save(PathBuf),
+    /// Export each layer as a separate PNG image in the defined directory,
+    /// named after the layer. The name and metadata of each layer are stored
+    /// as text in its image
+    SaveLayers(PathBuf),
+    /// Export the animation (all frames, or those of an animation tag) as an
+    /// animated image to the defined path, with each frame shown for its
+    /// duration. The format is chosen by the file extension: GIF (`.gif`),
+    /// APNG (`.png` or `.apng`) or WebP (`.webp`). The scale must be at least
+    /// 1
+    ExportAnimation(PathBuf, AnimationExport),
     /// Import an image from the defined path. The image will be loaded as a
     /// free image that can be moved around, and will resize the canvas if it's
     /// too big for it.
@@ -74,28 +88,150 @@ pub enum Event {
     NewLayerAbove,
     /// Create a new layer below the current layer
     NewLayerBelow,
-    /// Swith the active layer to the one with specified index
-    SwitchLayer(usize),
-    /// Make the layer specified by its index visible or invisible
-    ChangeLayerVisibility(usize, bool),
-    /// Change the alpha/opacity of the layer with the specified index
-    ChangeLayerOpacity(usize, u8),
-    /// Delete layer at index
-    DeleteLayer(usize),
-    /// Move the layer at specified index down (swap positions with the layer
-    /// below it)
-    MoveLayerDown(usize),
-    /// Move the layer at specified index up (swap positions with the layer
-    /// above it)
-    MoveLayerUp(usize),
-    /// Define how many horizontal and vertical frames this spritesheet has
-    /// (default is (1, 1), that is, just one frame). This is useful for
-    /// displaying animations
+    /// Swith the active layer to the one with the specified id
+    SwitchLayer(LayerId),
+    /// Make the layer specified by its id visible or invisible
+    ChangeLayerVisibility(LayerId, bool),
+    /// Change the alpha/opacity of the layer with the specified id
+    ChangeLayerOpacity(LayerId, u8),
+    /// Change how the layer with the specified id is combined with the
+    /// layers below it
+    ChangeLayerBlendMode(LayerId, BlendMode),
+    /// Lock or unlock the layer with the specified id. Events that draw on
+    /// a locked layer (see [`Event::draws_on_canvas`]) fail
+    SetLayerLock(LayerId, bool),
+    /// Lock or unlock the alpha of the layer with the specified id. Drawing
+    /// on a layer with locked alpha only changes pixels that are not fully
+    /// transparent
+    SetLayerAlphaLock(LayerId, bool),
+    /// Change the name of the layer with the specified id
+    RenameLayer(LayerId, String),
+    /// Set a metadata entry (key and value) of the layer with the specified
+    /// id, or remove it if the value is `None`. See [`Layer::set_metadata`]
+    /// for the keys allowed
+    ///
+    /// [`Layer::set_metadata`]: crate::Layer::set_metadata
+    SetLayerMetadata(LayerId, String, Option<String>),
+    /// Delete the layer with the specified id
+    DeleteLayer(LayerId),
+    /// Copy the layer with the specified id (its image and settings) into a
+    /// new layer right above it, which becomes the active layer
+    DuplicateLayer(LayerId),
+    /// Move the selected area of the active layer (or the free image) into a
+    /// new layer right above the active one, instead of anchoring it to the
+    /// active layer. The new layer becomes the active one
+    SelectionToNewLayer,
+    /// Add a mask to the layer with the specified id, initially revealing the
+    /// whole layer (fully white). Masks hide the parts of the layer where they
+    /// are dark or transparent without changing its pixels
+    AddLayerMask(LayerId),
+    /// Remove the mask of the layer with the specified id, making the whole
+    /// layer visible again
+    RemoveLayerMask(LayerId),
+    /// Make the changes of the mask of the layer with the specified id
+    /// permanent, removing the parts of the layer it hides, and then remove the
+    /// mask
+    ApplyLayerMask(LayerId),
+    /// Invert the mask of the layer with the specified id, so that what was
+    /// hidden becomes visible and vice-versa
+    InvertLayerMask(LayerId),
+    /// Set whether drawing events change the mask of the active layer (if it
+    /// has one) instead of its pixels
+    EditLayerMask(bool),
+    /// Import an image file as a new reference layer on top of all layers.
+    /// Reference layers are shown over the canvas but can't be drawn on and
+    /// are not exported
+    ImportReference(PathBuf),
+    /// Set the position of the top left corner of the image of the reference
+    /// layer with the specified id, in canvas pixels
+    SetReferenceOffset(LayerId, Point<i32>),
+    /// Set the factor by which the image of the reference layer with the
+    /// specified id is scaled. It must be positive
+    SetReferenceScale(LayerId, f32),
+    /// Move the image of the layer with the specified id by an offset (in
+    /// canvas pixels). Content moved out of the canvas is kept, and the layer
+    /// grows to keep covering the whole canvas. Reference layers are moved with
+    /// `SetReferenceOffset` instead
+    ShiftLayer(LayerId, Point<i32>),
+    /// Merge the layer with the specified id into the layer below it. The
+    /// result is a normal, visible and fully opaque layer that looks the same
+    /// as both layers did. Fails if any of them has a blend mode other than
+    /// normal and there are visible layers below them, as the merged layer
+    /// couldn't look the same over them
+    MergeLayerDown(LayerId),
+    /// Merge all layers into a single one that looks like the whole image
+    /// did. Invisible layers are discarded, and reference layers are kept
+    FlattenImage,
+    /// Move the layer with the specified id down (swap positions with the layer
+    /// below it). If the layer below it is in a group next to it, it enters
+    /// that group instead, and if it's at the bottom of its group, it leaves
+    /// the group
+    MoveLayerDown(LayerId),
+    /// Move the layer with the specified id up (swap positions with the layer
+    /// above it). If the layer above it is in a group next to it, it enters
+    /// that group instead, and if it's at the top of its group, it leaves the
+    /// group
+    MoveLayerUp(LayerId),
+    /// Put the layers from the one with the first id up to the one with the
+    /// second id (inclusive) into a new group. The range must not include only part of a group
+    GroupLayers(LayerId, LayerId),
+    /// Remove a group, moving everything inside it to the group that
+    /// contained it (if any)
+    Ungroup(GroupId),
+    /// Make a group (and everything inside it) visible or invisible
+    ChangeGroupVisibility(GroupId, bool),
+    /// Change the alpha/opacity of a group, which applies to everything inside
+    /// it
+    ChangeGroupOpacity(GroupId, u8),
+    /// Collapse or expand a group in the list of layers
+    SetGroupCollapsed(GroupId, bool),
+    /// Delete a group along with all the layers inside it
+    DeleteGroup(GroupId),
+    /// Move a whole group down, like `MoveLayerDown` does with a single layer
+    MoveGroupDown(GroupId),
+    /// Move a whole group up, like `MoveLayerUp` does with a single layer
+    MoveGroupUp(GroupId),
+    /// Add a new frame with empty cels after the last one (reference layers
+    /// keep their image in it), and make it the active frame
+    AddFrame,
+    /// Insert a new frame with empty cels at the specified index (at most the
+    /// number of frames, and reference layers keep their image in it), and
+    /// make it the active frame
+    InsertFrame(usize),
+    /// Copy the frame with the specified id (the cels of all layers in it) into
+    /// a new frame right after it, which becomes the active frame
+    DuplicateFrame(FrameId),
+    /// Delete the frame with the specified id, unless it's the only one
+    DeleteFrame(FrameId),
+    /// Move the frame with the specified id to an index, shifting the frames
+    /// in between
+    MoveFrame(FrameId, usize),
+    /// Make the frame with the specified id the active one. Drawing events
+    /// change the cels of the active frame
+    SwitchFrame(FrameId),
+    /// Set for how long the frame with the specified id is shown, in
+    /// milliseconds (at least 1)
+    SetFrameDuration(FrameId, u32),
+    /// Add an animation tag, which must cover existing frames
+    AddAnimationTag(AnimationTag),
+    /// Replace the animation tag at an index
+    SetAnimationTag(usize, AnimationTag),
+    /// Delete the animation tag at an index
+    DeleteAnimationTag(usize),
+    /// Change the settings of onion skinning (see [`State::onion_skin`]). The
+    /// falloff must be between 0 and 1
+    ///
+    /// [`State::onion_skin`]: crate::State::onion_skin
+    SetOnionSkin(OnionSkin),
+    /// Define the layout of exported images: how many columns and rows of
+    /// frames they have (default is (1, 1)). Frames are placed from left to
+    /// right and top to bottom, and more rows are added if they don't fit
     SetSpritesheet(Size<u8>),
     /// Start a rectangular selection at the specified point
     StartSelection(Point<i32>),
     /// Select a rectangle with corners at this point and the point specified by
-    /// `StartSelection`
+    /// `StartSelection`. The selection is clipped to the canvas and the active
+    /// layer's image, which can be outside of it
     EndSelection(Point<i32>),
     /// Clear the selection
     ClearSelection,
@@ -120,6 +256,31 @@ pub enum Event {
     ApplyTransform(Transform),
     /// Undo the last undoable action
     Undo,
+    /// Redo the last action that was undone. Once a new undoable action
+    /// happens, the actions undone before it cannot be redone anymore
+    Redo,
+    /// Set the maximum number of actions kept in the undo history (which is
+    /// saved along with the project). Older actions beyond this limit are
+    /// discarded and cannot be undone anymore
+    SetHistoryLimit(usize),
+    /// Undo or redo as many actions as needed so that exactly this many
+    /// actions of the history are applied, e.g. `JumpToHistory(0)` undoes
+    /// everything. See [`State::history`]
+    ///
+    /// [`State::history`]: crate::State::history
+    JumpToHistory(usize),
+    /// Start recording a macro. Every event executed from now on (except for
+    /// the ones that deal with files, macros or the undo history) is recorded
+    /// until `StopMacro`. If a macro was already being recorded, it is
+    /// discarded
+    StartMacro,
+    /// Stop recording the macro, storing it in the project. Macros are indexed
+    /// in the order they were recorded
+    StopMacro,
+    /// Play the macro with the specified index, with all its positions moved
+    /// by an offset (e.g. to draw the same thing a bit further). Playing a
+    /// macro can be undone as a single action
+    PlayMacro(usize, Point<i32>),
 }
 
 impl Event {
@@ -128,6 +289,89 @@ impl Event {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 
+    /// A short human-readable description of this event, used to label the
+    /// actions in the undo history
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::ClearCanvas => "Clear canvas",
+            Self::ResizeCanvas(_) => "Resize canvas",
+            Self::BrushStart | Self::BrushStroke(_) | Self::BrushEnd => "Brush stroke",
+            Self::SetTool(_) => "Set tool",
+            Self::SetMainColor(_) => "Set main color",
+            Self::AddToPalette(_) => "Add to palette",
+            Self::RemoveFromPalette(_) => "Remove from palette",
+            Self::Save(_) => "Export image",
+            Self::SaveLayers(_) => "Export layers",
+            Self::ExportAnimation(_, _) => "Export animation",
+            Self::OpenFile(_) => "Import image",
+            Self::SaveProject(_) => "Save project",
+            Self::LoadProject(_) => "Load project",
+            Self::LoadPalette(_) => "Load palette",
+            Self::Bucket(_) => "Bucket fill",
+            Self::EraseStart | Self::Erase(_) | Self::EraseEnd => "Erase",
+            Self::LineStart(_) | Self::LineEnd(_) => "Line",
+            Self::RectStart(_) | Self::RectEnd(_) => "Rectangle",
+            Self::NewLayerAbove | Self::NewLayerBelow => "New layer",
+            Self::SwitchLayer(_) => "Switch layer",
+            Self::ChangeLayerVisibility(_, _) => "Change layer visibility",
+            Self::ChangeLayerOpacity(_, _) => "Change layer opacity",
+            Self::ChangeLayerBlendMode(_, _) => "Change layer blend mode",
+            Self::SetLayerLock(_, _) => "Lock layer",
+            Self::SetLayerAlphaLock(_, _) => "Lock layer alpha",
+            Self::RenameLayer(_, _) => "Rename layer",
+            Self::SetLayerMetadata(_, _, _) => "Set layer metadata",
+            Self::DeleteLayer(_) => "Delete layer",
+            Self::DuplicateLayer(_) => "Duplicate layer",
+            Self::SelectionToNewLayer => "Selection to new layer",
+            Self::AddLayerMask(_) => "Add layer mask",
+            Self::RemoveLayerMask(_) => "Remove layer mask",
+            Self::ApplyLayerMask(_) => "Apply layer mask",
+            Self::InvertLayerMask(_) => "Invert layer mask",
+            Self::EditLayerMask(_) => "Edit layer mask",
+            Self::ImportReference(_) => "Import reference",
+            Self::SetReferenceOffset(_, _) => "Move reference",
+            Self::SetReferenceScale(_, _) => "Scale reference",
+            Self::ShiftLayer(_, _) => "Shift layer",
+            Self::MergeLayerDown(_) => "Merge layer down",
+            Self::FlattenImage => "Flatten image",
+            Self::MoveLayerDown(_) | Self::MoveLayerUp(_) => "Move layer",
+            Self::GroupLayers(_, _) => "Group layers",
+            Self::Ungroup(_) => "Ungroup layers",
+            Self::ChangeGroupVisibility(_, _) => "Change group visibility",
+            Self::ChangeGroupOpacity(_, _) => "Change group opacity",
+            Self::SetGroupCollapsed(_, _) => "Collapse group",
+            Self::DeleteGroup(_) => "Delete group",
+            Self::MoveGroupDown(_) | Self::MoveGroupUp(_) => "Move group",
+            Self::AddFrame | Self::InsertFrame(_) => "New frame",
+            Self::DuplicateFrame(_) => "Duplicate frame",
+            Self::DeleteFrame(_) => "Delete frame",
+            Self::MoveFrame(_, _) => "Move frame",
+            Self::SwitchFrame(_) => "Switch frame",
+            Self::SetFrameDuration(_, _) => "Set frame duration",
+            Self::AddAnimationTag(_) => "New animation tag",
+            Self::SetAnimationTag(_, _) => "Edit animation tag",
+            Self::DeleteAnimationTag(_) => "Delete animation tag",
+            Self::SetOnionSkin(_) => "Set onion skin",
+            Self::SetSpritesheet(_) => "Set spritesheet",
+            Self::StartSelection(_) | Self::EndSelection(_) => "Select",
+            Self::ClearSelection => "Clear selection",
+            Self::DeleteSelection => "Delete selection",
+            Self::MoveStart(_) | Self::MoveEnd(_) => "Move selection",
+            Self::Copy => "Copy",
+            Self::Paste(_) => "Paste",
+            Self::FlipHorizontal => "Flip horizontally",
+            Self::FlipVertical => "Flip vertically",
+            Self::ApplyTransform(_) => "Apply transform",
+            Self::Undo => "Undo",
+            Self::Redo => "Redo",
+            Self::SetHistoryLimit(_) => "Set history limit",
+            Self::JumpToHistory(_) => "Jump to history",
+            Self::StartMacro => "Start macro",
+            Self::StopMacro => "Stop macro",
+            Self::PlayMacro(_, _) => "Play macro",
+        }
+    }
+
     // TODO: maybe this should return a vec of fx, not a single one
     /// Returns the [`CanvasEffect`] caused by this event
     pub fn canvas_effect(&self) -> CanvasEffect {
@@ -150,9 +394,32 @@ impl Event {
             Self::NewLayerAbove
             | Self::NewLayerBelow
             | Self::DeleteLayer(_)
+            | Self::DuplicateLayer(_)
+            | Self::SelectionToNewLayer
+            | Self::AddLayerMask(_)
+            | Self::RemoveLayerMask(_)
+            | Self::ApplyLayerMask(_)
+            | Self::InvertLayerMask(_)
+            | Self::ImportReference(_)
+            | Self::ShiftLayer(_, _)
+            | Self::MergeLayerDown(_)
+            | Self::FlattenImage
             | Self::MoveLayerDown(_)
             | Self::MoveLayerUp(_)
-            | Self::LoadProject(_) => CanvasEffect::Layer,
+            | Self::GroupLayers(_, _)
+            | Self::Ungroup(_)
+            | Self::DeleteGroup(_)
+            | Self::MoveGroupDown(_)
+            | Self::MoveGroupUp(_)
+            | Self::AddFrame
+            | Self::InsertFrame(_)
+            | Self::DuplicateFrame(_)
+            | Self::DeleteFrame(_)
+            | Self::MoveFrame(_, _)
+            | Self::SwitchFrame(_)
+            | Self::LoadProject(_)
+            | Self::ChangeLayerBlendMode(_, _)
+            | Self::PlayMacro(_, _) => CanvasEffect::Layer,
             x if x.triggers_anchoring() => CanvasEffect::Update,
             _ => CanvasEffect::None,
         }
@@ -163,13 +430,32 @@ impl Event {
         matches!(
             self,
             Self::Undo
+                | Self::Redo
+                | Self::JumpToHistory(_)
+                | Self::PlayMacro(_, _)
                 | Self::NewLayerAbove
                 | Self::NewLayerBelow
                 | Self::DeleteLayer(_)
+                | Self::DuplicateLayer(_)
+                | Self::InvertLayerMask(_)
+                | Self::ShiftLayer(_, _)
+                | Self::MergeLayerDown(_)
                 | Self::FlipHorizontal
                 | Self::FlipVertical
                 | Self::MoveLayerDown(_)
                 | Self::MoveLayerUp(_)
+                | Self::GroupLayers(_, _)
+                | Self::Ungroup(_)
+                | Self::DeleteGroup(_)
+                | Self::MoveGroupDown(_)
+                | Self::MoveGroupUp(_)
+                | Self::AddFrame
+                | Self::InsertFrame(_)
+                | Self::DuplicateFrame(_)
+                | Self::DeleteFrame(_)
+                | Self::MoveFrame(_, _)
+                | Self::AddAnimationTag(_)
+                | Self::DeleteAnimationTag(_)
         )
     }
 
@@ -207,7 +493,20 @@ impl Event {
                 | Self::SwitchLayer(_)
                 | Self::ChangeLayerVisibility(_, _)
                 | Self::ChangeLayerOpacity(_, _)
+                | Self::ChangeLayerBlendMode(_, _)
+                | Self::RenameLayer(_, _)
+                | Self::SetLayerMetadata(_, _, _)
                 | Self::DeleteLayer(_)
+                | Self::DuplicateLayer(_)
+                | Self::SelectionToNewLayer
+                | Self::AddLayerMask(_)
+                | Self::RemoveLayerMask(_)
+                | Self::ApplyLayerMask(_)
+                | Self::InvertLayerMask(_)
+                | Self::ImportReference(_)
+                | Self::ShiftLayer(_, _)
+                | Self::MergeLayerDown(_)
+                | Self::FlattenImage
                 | Self::MoveStart(_)
                 | Self::MoveEnd(_)
                 | Self::StartSelection(_)
@@ -217,6 +516,107 @@ impl Event {
                 | Self::OpenFile(_)
                 | Self::MoveLayerDown(_)
                 | Self::MoveLayerUp(_)
+                | Self::GroupLayers(_, _)
+                | Self::Ungroup(_)
+                | Self::ChangeGroupVisibility(_, _)
+                | Self::ChangeGroupOpacity(_, _)
+                | Self::DeleteGroup(_)
+                | Self::MoveGroupDown(_)
+                | Self::MoveGroupUp(_)
+                | Self::AddFrame
+                | Self::InsertFrame(_)
+                | Self::DuplicateFrame(_)
+                | Self::DeleteFrame(_)
+                | Self::MoveFrame(_, _)
+                | Self::SetFrameDuration(_, _)
+                | Self::AddAnimationTag(_)
+                | Self::SetAnimationTag(_, _)
+                | Self::DeleteAnimationTag(_)
+                | Self::ApplyTransform(_)
+                | Self::PlayMacro(_, _)
+        )
+    }
+
+    /// Whether this event is recorded in macros
+    pub fn recordable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Save(_)
+                | Self::SaveLayers(_)
+                | Self::ExportAnimation(_, _)
+                | Self::OpenFile(_)
+                | Self::SaveProject(_)
+                | Self::LoadProject(_)
+                | Self::LoadPalette(_)
+                | Self::Undo
+                | Self::Redo
+                | Self::SetHistoryLimit(_)
+                | Self::SetOnionSkin(_)
+                | Self::JumpToHistory(_)
+                | Self::StartMacro
+                | Self::StopMacro
+        )
+    }
+
+    /// Get this same event with all its positions moved by an offset
+    pub fn translated(&self, offset: Point<i32>) -> Self {
+        match self.clone() {
+            Self::BrushStroke(p) => Self::BrushStroke(p + offset),
+            Self::Erase(p) => Self::Erase(p + offset),
+            Self::Bucket(p) => Self::Bucket(p + offset),
+            Self::LineStart(p) => Self::LineStart(p + offset),
+            Self::LineEnd(p) => Self::LineEnd(p + offset),
+            Self::RectStart(p) => Self::RectStart(p + offset),
+            Self::RectEnd(p) => Self::RectEnd(p + offset),
+            Self::StartSelection(p) => Self::StartSelection(p + offset),
+            Self::EndSelection(p) => Self::EndSelection(p + offset),
+            Self::MoveStart(p) => Self::MoveStart(p + offset),
+            Self::MoveEnd(p) => Self::MoveEnd(p + offset),
+            Self::Paste(p) => Self::Paste(p + offset),
+            Self::PlayMacro(i, p) => Self::PlayMacro(i, p + offset),
+            event => event,
+        }
+    }
+
+    /// Get the position this event happens at, if any
+    pub fn point(&self) -> Option<Point<i32>> {
+        match self {
+            Self::BrushStroke(p)
+            | Self::Erase(p)
+            | Self::Bucket(p)
+            | Self::LineStart(p)
+            | Self::LineEnd(p)
+            | Self::RectStart(p)
+            | Self::RectEnd(p)
+            | Self::StartSelection(p)
+            | Self::EndSelection(p)
+            | Self::MoveStart(p)
+            | Self::MoveEnd(p)
+            | Self::Paste(p) => Some(*p),
+            _ => None,
+        }
+    }
+
+    /// Whether this event draws on (or otherwise changes the image of) the
+    /// active layer, and therefore fails if it is locked
+    pub fn draws_on_canvas(&self) -> bool {
+        matches!(
+            self,
+            Self::ClearCanvas
+                | Self::BrushStart
+                | Self::BrushStroke(_)
+                | Self::EraseStart
+                | Self::Erase(_)
+                | Self::LineEnd(_)
+                | Self::RectEnd(_)
+                | Self::Bucket(_)
+                | Self::DeleteSelection
+                | Self::MoveStart(_)
+                | Self::SelectionToNewLayer
+                | Self::Paste(_)
+                | Self::OpenFile(_)
+                | Self::FlipHorizontal
+                | Self::FlipVertical
                 | Self::ApplyTransform(_)
         )
     }
@@ -234,6 +634,8 @@ impl Event {
                 | Self::SetTool(Tool::Line)
                 | Self::MoveLayerDown(_)
                 | Self::MoveLayerUp(_)
+                | Self::MoveGroupDown(_)
+                | Self::MoveGroupUp(_)
         )
     }
 
@@ -252,9 +654,14 @@ impl Event {
                 | Self::FlipHorizontal
                 | Self::FlipVertical
                 | Self::DeleteSelection
+                | Self::SelectionToNewLayer
                 | Self::MoveLayerDown(_)
                 | Self::MoveLayerUp(_)
+                | Self::MoveGroupDown(_)
+                | Self::MoveGroupUp(_)
                 | Self::ApplyTransform(_)
+                | Self::Redo
+                | Self::JumpToHistory(_)
         )
     }
 }
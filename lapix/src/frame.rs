@@ -0,0 +1,241 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// How long new frames are shown, in milliseconds
+pub const DEFAULT_FRAME_DURATION: u32 = 100;
+
+/// Identifies a [`Frame`] within its [`Layers`], no matter where it is in the
+/// animation. Ids of deleted frames are never given to new ones
+///
+/// [`Layers`]: crate::Layers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FrameId(pub(crate) usize);
+
+/// A frame of an animation. Every [`Layer`] has an image (a cel) for each
+/// frame, and the frames are shown one after the other in the order they are
+/// in, each for its own duration. A drawing that is not animated has a single
+/// frame.
+///
+/// [`Layer`]: crate::Layer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    id: FrameId,
+    duration: u32,
+}
+
+impl Frame {
+    pub(crate) fn new(id: FrameId) -> Self {
+        Self {
+            id,
+            duration: DEFAULT_FRAME_DURATION,
+        }
+    }
+
+    /// Get the id of this frame
+    pub fn id(&self) -> FrameId {
+        self.id
+    }
+
+    /// Get for how long this frame is shown, in milliseconds
+    pub fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    /// Set for how long this frame is shown, in milliseconds. Returns the
+    /// previous duration
+    pub(crate) fn set_duration(&mut self, duration: u32) -> u32 {
+        std::mem::replace(&mut self.duration, duration)
+    }
+}
+
+/// The order in which the frames of an [`AnimationTag`] are played
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnimationDirection {
+    /// From the first frame to the last
+    #[default]
+    Forward,
+    /// From the last frame to the first
+    Reverse,
+    /// From the first frame to the last, then back (without repeating the
+    /// frames at the ends)
+    PingPong,
+}
+
+/// A named range of frames (e.g. "idle" or "run") that is played as an
+/// animation of its own. The range is a range of positions, so it covers
+/// whatever frames are there: inserting a frame inside it makes it longer, and
+/// deleting one makes it shorter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnimationTag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: AnimationDirection,
+}
+
+impl AnimationTag {
+    /// Create a tag covering the frames from index `from` to index `to`
+    /// (inclusive)
+    pub fn new(
+        name: impl Into<String>,
+        from: usize,
+        to: usize,
+        direction: AnimationDirection,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            from,
+            to,
+            direction,
+        }
+    }
+
+    /// Get the name of the tag
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the indices of the frames covered by the tag
+    pub fn range(&self) -> RangeInclusive<usize> {
+        self.from..=self.to
+    }
+
+    /// Get the order in which the frames of the tag are played
+    pub fn direction(&self) -> AnimationDirection {
+        self.direction
+    }
+
+    /// Get the indices of the frames in the order they are played, for one
+    /// loop of the animation
+    pub fn sequence(&self) -> Vec<usize> {
+        match self.direction {
+            AnimationDirection::Forward => self.range().collect(),
+            AnimationDirection::Reverse => self.range().rev().collect(),
+            AnimationDirection::PingPong => {
+                self.range().chain((self.from + 1..self.to).rev()).collect()
+            }
+        }
+    }
+
+    /// Update the range after a frame is inserted at an index. Frames inserted
+    /// at the start of the range are left out of it
+    pub(crate) fn frame_inserted(&mut self, index: usize) {
+        if index <= self.from {
+            self.from += 1;
+        }
+        if index <= self.to {
+            self.to += 1;
+        }
+    }
+
+    /// Update the range after the frame at an index is deleted. Returns `false`
+    /// if it was the only frame of the tag, which should then be deleted
+    pub(crate) fn frame_deleted(&mut self, index: usize) -> bool {
+        if self.from == index && self.to == index {
+            return false;
+        }
+        if index < self.from {
+            self.from -= 1;
+        }
+        if index <= self.to {
+            self.to -= 1;
+        }
+
+        true
+    }
+}
+
+/// Settings for exporting an animation as an animated image (see
+/// [`Event::ExportAnimation`])
+///
+/// [`Event::ExportAnimation`]: crate::Event::ExportAnimation
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnimationExport {
+    /// Index of the [`AnimationTag`] to export, or `None` to export all frames
+    /// in order
+    pub tag: Option<usize>,
+    /// How many times the animation is repeated after playing once, or `None`
+    /// to loop forever
+    pub repeat: Option<u16>,
+    /// In formats that only support fully opaque or fully transparent pixels
+    /// (GIF), pixels with an alpha below this are made transparent and the
+    /// others opaque
+    pub alpha_threshold: u8,
+    /// How many times bigger than the canvas the frames are exported, each
+    /// pixel becoming a square of pixels. Must be at least 1
+    pub scale: u8,
+}
+
+impl Default for AnimationExport {
+    fn default() -> Self {
+        Self {
+            tag: None,
+            repeat: None,
+            alpha_threshold: 128,
+            scale: 1,
+        }
+    }
+}
+
+impl AnimationExport {
+    /// Check that the settings are valid
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.scale == 0 {
+            return Err(Error::InvalidExportScale(self.scale));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(AnimationDirection::Forward, vec![2, 3, 4])]
+    #[test_case(AnimationDirection::Reverse, vec![4, 3, 2])]
+    #[test_case(AnimationDirection::PingPong, vec![2, 3, 4, 3])]
+    fn sequence(direction: AnimationDirection, expected: Vec<usize>) {
+        let tag = AnimationTag::new("run", 2, 4, direction);
+
+        assert_eq!(tag.sequence(), expected);
+    }
+
+    #[test]
+    fn ping_pong_single_frame() {
+        let tag = AnimationTag::new("idle", 1, 1, AnimationDirection::PingPong);
+
+        assert_eq!(tag.sequence(), vec![1]);
+    }
+
+    #[test_case(0, 1..=3)]
+    #[test_case(2, 2..=3)]
+    #[test_case(4, 2..=3)]
+    #[test_case(5, 2..=4)]
+    fn frame_deleted(index: usize, expected: RangeInclusive<usize>) {
+        let mut tag = AnimationTag::new("run", 2, 4, AnimationDirection::Forward);
+
+        assert!(tag.frame_deleted(index));
+        assert_eq!(tag.range(), expected);
+    }
+
+    #[test_case(0, 3..=5)]
+    #[test_case(2, 3..=5)]
+    #[test_case(3, 2..=5)]
+    #[test_case(5, 2..=4)]
+    fn frame_inserted(index: usize, expected: RangeInclusive<usize>) {
+        let mut tag = AnimationTag::new("run", 2, 4, AnimationDirection::Forward);
+        tag.frame_inserted(index);
+
+        assert_eq!(tag.range(), expected);
+    }
+
+    #[test]
+    fn delete_only_frame() {
+        let mut tag = AnimationTag::new("idle", 1, 1, AnimationDirection::Forward);
+
+        assert!(!tag.frame_deleted(1));
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies a [`LayerGroup`] within its [`Layers`]
+///
+/// [`Layers`]: crate::Layers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GroupId(pub(crate) usize);
+
+/// A folder-like group of [`Layer`]s, which may contain other groups as well.
+/// The visibility and opacity of a group apply to everything inside it: its
+/// contents are composited on their own (so the blend modes of its layers only
+/// mix them with the rest of the group) and then blended with what is below
+/// it, with the group's opacity. The
+/// layers inside a group (including the ones in nested groups) are always next
+/// to each other in the layer stack, so a group takes a range of layer indices.
+///
+/// [`Layer`]: crate::Layer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerGroup {
+    id: GroupId,
+    parent: Option<GroupId>,
+    visible: bool,
+    opacity: u8,
+    collapsed: bool,
+}
+
+impl LayerGroup {
+    /// Create a new visible, opaque and expanded group inside another one (or
+    /// at the top level if `parent` is `None`)
+    pub(crate) fn new(id: GroupId, parent: Option<GroupId>) -> Self {
+        Self {
+            id,
+            parent,
+            visible: true,
+            opacity: 255,
+            collapsed: false,
+        }
+    }
+
+    /// Get the id of this group
+    pub fn id(&self) -> GroupId {
+        self.id
+    }
+
+    /// Get the id of the group that contains this one, if any
+    pub fn parent(&self) -> Option<GroupId> {
+        self.parent
+    }
+
+    /// Whether this group (and therefore everything inside it) is visible
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Get the opacity level (alpha) of this group, a value from 0-255
+    pub fn opacity(&self) -> u8 {
+        self.opacity
+    }
+
+    /// Whether this group is collapsed, i.e. its contents should be hidden in
+    /// the list of layers
+    pub fn collapsed(&self) -> bool {
+        self.collapsed
+    }
+
+    pub(crate) fn set_parent(&mut self, parent: Option<GroupId>) {
+        self.parent = parent;
+    }
+
+    pub(crate) fn set_visibility(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub(crate) fn set_opacity(&mut self, opacity: u8) {
+        self.opacity = opacity;
+    }
+
+    pub(crate) fn set_collapsed(&mut self, collapsed: bool) {
+        self.collapsed = collapsed;
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{Event, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+
+/// Records every [`Event`] executed by a [`State`] into a writer. A journal
+/// starts with a snapshot of the state at the moment the recording started
+/// (along with the last event executed before it, which some events depend
+/// on), followed by the events themselves, all encoded with `bincode`.
+///
+/// [`State`]: crate::State
+pub struct Journal(BufWriter<Box<dyn Write>>);
+
+impl Debug for Journal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str("Journal(BufWriter<Box<dyn Write>>)")
+    }
+}
+
+impl Journal {
+    /// Start a journal, writing the snapshot of the state to the writer
+    pub fn start<S: Serialize>(
+        writer: Box<dyn Write>,
+        state: &S,
+        last_event: Option<&Event>,
+    ) -> Result<Self> {
+        let mut writer = BufWriter::new(writer);
+        bincode::serialize_into(&mut writer, &(state, last_event))?;
+        writer.flush()?;
+
+        Ok(Self(writer))
+    }
+
+    /// Append an event to the journal. The writer is flushed right away (or,
+    /// in the middle of a brush stroke or erasing, at the end of it) so the
+    /// journal is still useful if the program crashes while executing it.
+    pub fn record(&mut self, event: &Event) -> Result<()> {
+        bincode::serialize_into(&mut self.0, event)?;
+        if !matches!(event, Event::BrushStroke(_) | Event::Erase(_)) {
+            self.0.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write whatever is still buffered
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.0.flush()?)
+    }
+
+    /// Read the snapshot at the start of a journal
+    pub fn read_snapshot<S, R>(reader: &mut R) -> Result<(S, Option<Event>)>
+    where
+        S: for<'de> Deserialize<'de>,
+        R: Read,
+    {
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    /// Read the next event of a journal, or `None` if the end of it was
+    /// reached
+    pub fn read_event<R: Read>(reader: &mut R) -> Result<Option<Event>> {
+        match bincode::deserialize_from(reader) {
+            Ok(event) => Ok(Some(event)),
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+                e => Err(Box::new(e).into()),
+            },
+        }
+    }
+}
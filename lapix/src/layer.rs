@@ -1,24 +1,66 @@
-use crate::color::TRANSPARENT;
-use crate::{Bitmap, Canvas, Color, Point, Rect, Size};
+use crate::color::{TRANSPARENT, WHITE};
+use crate::{
+    AnimationTag, Bitmap, BlendMode, Canvas, Color, Error, Frame, FrameId, GroupId, LayerGroup,
+    Point, Rect, Result, Size,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{Range, RangeInclusive};
 
-/// An ordered collection of [`Layer`]s. There is always one active layer.
+/// An ordered collection of [`Layer`]s, possibly organized in
+/// [`LayerGroup`]s, along with the [`Frame`]s of their animation. There is
+/// always one active layer and one active frame.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Layers<IMG> {
     inner: Vec<Layer<IMG>>,
+    size: Size<i32>,
     active: usize,
+    groups: Vec<LayerGroup>,
+    next_id: usize,
+    frames: Vec<Frame>,
+    active_frame: usize,
+    next_frame_id: usize,
+    tags: Vec<AnimationTag>,
+    #[serde(skip)]
+    editing_mask: bool,
 }
 
 impl<IMG: Bitmap> Layers<IMG> {
     /// Creates a new set of layers
     pub fn new(size: Size<i32>) -> Self {
+        let mut layer = Layer::new(size);
+        layer.set_name("Layer 1");
+        layer.id = LayerId(0);
+
         Self {
-            inner: vec![Layer::new(size)],
+            inner: vec![layer],
+            size,
             active: 0,
+            groups: Vec::new(),
+            next_id: 1,
+            frames: vec![Frame::new(FrameId(0))],
+            active_frame: 0,
+            next_frame_id: 1,
+            tags: Vec::new(),
+            editing_mask: false,
         }
     }
 
-    /// Get the active [`Layer`]
+    /// Get the index of the [`Layer`] with the specified id
+    pub fn index_of(&self, id: LayerId) -> Result<usize> {
+        self.inner
+            .iter()
+            .position(|layer| layer.id == id)
+            .ok_or(Error::MissingLayer(id))
+    }
+
+    /// Get the [`Layer`] with the specified id
+    pub fn by_id(&self, id: LayerId) -> Result<&Layer<IMG>> {
+        self.index_of(id).map(|i| self.get(i))
+    }
+
+    /// Get the active [`Layer`], which is never a reference layer (see
+    /// [`Layer::reference`])
     pub fn active(&self) -> &Layer<IMG> {
         &self.inner[self.active]
     }
@@ -28,6 +70,11 @@ impl<IMG: Bitmap> Layers<IMG> {
         self.active
     }
 
+    /// Get the id of the active [`Layer`]
+    pub fn active_id(&self) -> LayerId {
+        self.active().id
+    }
+
     /// Get the number of [`Layer`]s
     pub fn count(&self) -> usize {
         self.inner.len()
@@ -38,9 +85,26 @@ impl<IMG: Bitmap> Layers<IMG> {
         self.inner[index].canvas()
     }
 
-    /// Get the [`Canvas`] of the active [`Layer`]
+    /// Get the [`Canvas`] being drawn on: the one of the active [`Layer`], or
+    /// its mask if it is being edited (see [`Layers::editing_mask`])
     pub fn active_canvas(&self) -> &Canvas<IMG> {
-        self.canvas_at(self.active)
+        match self.active().mask() {
+            Some(mask) if self.editing_mask => mask,
+            _ => self.canvas_at(self.active),
+        }
+    }
+
+    /// Whether drawing targets the mask of the active [`Layer`] instead of its
+    /// pixels. This is only the case if mask editing is enabled and the active
+    /// layer has a mask.
+    pub fn editing_mask(&self) -> bool {
+        self.editing_mask && self.active().mask().is_some()
+    }
+
+    /// Set whether drawing should target the mask of the active [`Layer`] (for
+    /// layers that have one) instead of its pixels
+    pub fn set_editing_mask(&mut self, editing_mask: bool) {
+        self.editing_mask = editing_mask;
     }
 
     /// Get a [`Layer`] by its index
@@ -48,29 +112,96 @@ impl<IMG: Bitmap> Layers<IMG> {
         &self.inner[index]
     }
 
-    /// Get an image of all the [`Layer`]s blended together
+    /// Get the size of the canvas, i.e. of the blended image. The images of the
+    /// [`Layer`]s may be bigger or smaller than it, and be placed anywhere
+    /// relative to it (see [`Layer::offset`])
+    pub fn canvas_size(&self) -> Size<i32> {
+        self.size
+    }
+
+    /// Get the rectangle covered by the canvas, starting from the origin at
+    /// (0, 0)
+    pub fn canvas_rect(&self) -> Rect<i32> {
+        Rect::new(0, 0, self.size.x, self.size.y)
+    }
+
+    /// Get the smallest rectangle containing the canvas and the images of all
+    /// the [`Layer`]s in a range of indices that are not references
+    pub fn bounds(&self, range: Range<usize>) -> Rect<i32> {
+        range
+            .filter(|i| self.get(*i).reference().is_none())
+            .fold(self.canvas_rect(), |r, i| r.union(self.canvas_at(i).rect()))
+    }
+
+    /// Get an image of all the [`Layer`]s blended together (reference layers
+    /// are left out)
     pub fn blended(&self) -> IMG {
-        let w = self.canvas_at(0).width();
-        let h = self.canvas_at(0).height();
+        let size = self.canvas_size();
 
-        self.blended_area((0, 0, w, h).into())
+        self.blended_area((0, 0, size.x, size.y).into())
     }
 
-    /// Get an image of an area (determined by a rectangle) of all [`Layer`]s
-    /// blended together
-    pub fn blended_area(&self, r: Rect<i32>) -> IMG {
-        let mut result = IMG::new((r.w, r.h).into(), TRANSPARENT);
+    /// Same as [`Layers::blended`], but for the frame at the specified index
+    /// instead of the active one
+    pub fn blended_frame(&self, frame: usize) -> IMG {
+        self.composite_area(0..self.count(), None, frame, self.canvas_rect())
+    }
+
+    /// Get an image of the [`Layer`]s in a range of indices blended together,
+    /// as if there were no other layers. The settings of the groups that
+    /// contain the whole range (see [`Layers::common_group`]) are not applied.
+    pub fn blended_range(&self, range: Range<usize>) -> IMG {
+        self.blended_range_area(range, self.canvas_rect())
+    }
+
+    /// Same as [`Layers::blended_range`], but for an area (determined by a
+    /// rectangle), which may go beyond the canvas
+    pub fn blended_range_area(&self, range: Range<usize>, r: Rect<i32>) -> IMG {
+        self.blended_frame_range_area(self.active_frame, range, r)
+    }
+
+    /// Same as [`Layers::blended_range_area`], but for the frame at the
+    /// specified index instead of the active one
+    pub(crate) fn blended_frame_range_area(
+        &self,
+        frame: usize,
+        range: Range<usize>,
+        r: Rect<i32>,
+    ) -> IMG {
+        let outer = self.common_group(range.clone());
+
+        self.composite_area(range, outer, frame, r)
+    }
+
+    /// Get an image of an area (determined by a rectangle) of a frame, with the
+    /// colors given by [`Layers::composite_pixel`]
+    fn composite_area(
+        &self,
+        range: Range<usize>,
+        outer: Option<GroupId>,
+        frame: usize,
+        r: Rect<i32>,
+    ) -> IMG {
+        let mut result = IMG::new(r.size(), TRANSPARENT);
+        let stack = self.composite_stack(range, outer);
 
         for i in 0..r.w {
             for j in 0..r.h {
                 let ij = Point::new(i, j);
-                result.set_pixel(ij, self.visible_pixel(ij + r.pos()));
+                let color = self.composite_pixel(&stack, frame, ij + r.pos());
+                result.set_pixel(ij, color.unwrap_or(TRANSPARENT));
             }
         }
 
         result
     }
 
+    /// Get an image of an area (determined by a rectangle) of all [`Layer`]s
+    /// blended together
+    pub fn blended_area(&self, r: Rect<i32>) -> IMG {
+        self.composite_area(0..self.count(), None, self.active_frame, r)
+    }
+
     /// Get a mutable reference to a [`Layer`] by its index
     pub fn get_mut(&mut self, index: usize) -> &mut Layer<IMG> {
         &mut self.inner[index]
@@ -82,47 +213,322 @@ impl<IMG: Bitmap> Layers<IMG> {
         self.inner[index].canvas_mut()
     }
 
-    /// Get a mutable reference to the [`Canvas`] of the active [`Layer`]
+    /// Get a mutable reference to the mask of the [`Layer`] at a certain
+    /// index, if it has one
+    pub fn mask_at_mut(&mut self, index: usize) -> Option<&mut Canvas<IMG>> {
+        self.inner[index].mask_mut()
+    }
+
+    /// Get a mutable reference to the [`Canvas`] being drawn on (see
+    /// [`Layers::active_canvas`])
     pub fn active_canvas_mut(&mut self) -> &mut Canvas<IMG> {
-        self.inner[self.active].canvas_mut()
+        let layer = &mut self.inner[self.active];
+        match layer.mask.as_mut() {
+            Some(mask) if self.editing_mask => mask,
+            _ => &mut layer.cels[layer.frame],
+        }
+    }
+
+    /// Set the size of the canvas, returning the previous one. The [`Layer`]s
+    /// are left as they are, so nothing is cropped (see [`Layer::cover`])
+    pub(crate) fn set_canvas_size(&mut self, size: Size<i32>) -> Size<i32> {
+        std::mem::replace(&mut self.size, size)
+    }
+
+    /// Move the image (and mask) of the [`Layer`] at the specified index so
+    /// that its top left corner is at a certain position of the canvas,
+    /// returning the previous one
+    pub(crate) fn set_offset(&mut self, index: usize, offset: Point<i32>) -> Point<i32> {
+        self.inner[index].set_offset(offset)
+    }
+
+    /// Get the [`Frame`]s, in the order they are shown. Each layer has an
+    /// image (a cel) for every frame, see [`Layer::cel`]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Get the number of [`Frame`]s
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Get the index of the active [`Frame`], whose cels are the ones drawn on
+    pub fn active_frame(&self) -> usize {
+        self.active_frame
+    }
+
+    /// Get the id of the active [`Frame`]
+    pub fn active_frame_id(&self) -> FrameId {
+        self.frames[self.active_frame].id()
+    }
+
+    /// Get the index of the [`Frame`] with the specified id
+    pub fn frame_index_of(&self, id: FrameId) -> Result<usize> {
+        self.frames
+            .iter()
+            .position(|frame| frame.id() == id)
+            .ok_or(Error::MissingFrame(id))
+    }
+
+    /// Set for how long the [`Frame`] at an index is shown, in milliseconds.
+    /// Returns the previous duration
+    pub(crate) fn set_frame_duration(&mut self, index: usize, duration: u32) -> u32 {
+        self.frames[index].set_duration(duration)
     }
 
-    /// Resize all [`Layer`]s, returning the images that were there before the
-    /// resizing (used for undoing)
-    pub fn resize_all(&mut self, size: Size<i32>) -> Vec<IMG> {
-        let mut imgs = Vec::new();
+    /// Get the [`AnimationTag`]s, ranges of frames that can be played on their
+    /// own
+    pub fn tags(&self) -> &[AnimationTag] {
+        &self.tags
+    }
+
+    /// Replace all the [`AnimationTag`]s, returning the previous ones
+    pub(crate) fn set_tags(&mut self, tags: Vec<AnimationTag>) -> Vec<AnimationTag> {
+        std::mem::replace(&mut self.tags, tags)
+    }
+
+    /// Check that an [`AnimationTag`] covers a valid range of frames
+    pub(crate) fn check_tag(&self, tag: &AnimationTag) -> Result<()> {
+        let (from, to) = (*tag.range().start(), *tag.range().end());
+        if from > to || to >= self.frame_count() {
+            return Err(Error::InvalidAnimationTagRange(
+                from,
+                to,
+                self.frame_count(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Get the indices of the [`Frame`]s in the order they are played, for one
+    /// loop of an animation: the one of an [`AnimationTag`], or all frames
+    /// from first to last if there is no tag
+    pub fn playback(&self, tag: Option<&AnimationTag>) -> Vec<usize> {
+        match tag {
+            Some(tag) => tag
+                .sequence()
+                .into_iter()
+                .filter(|i| *i < self.frame_count())
+                .collect(),
+            None => (0..self.frame_count()).collect(),
+        }
+    }
+
+    /// Get the index of the [`Frame`] shown at some time (in milliseconds)
+    /// since an animation started, looping it (see [`Layers::playback`])
+    pub fn frame_at_time(&self, tag: Option<&AnimationTag>, time: u64) -> usize {
+        let playback = self.playback(tag);
+        let total: u64 = playback
+            .iter()
+            .map(|i| self.frames[*i].duration() as u64)
+            .sum();
+        let mut time = time % total.max(1);
+
+        for i in &playback {
+            let duration = self.frames[*i].duration() as u64;
+            if time < duration {
+                return *i;
+            }
+            time -= duration;
+        }
+
+        playback.first().copied().unwrap_or(self.active_frame)
+    }
+
+    /// Set the active [`Frame`] to the specified index
+    pub fn switch_frame(&mut self, index: usize) {
+        self.active_frame = index;
+        for layer in self.inner.iter_mut() {
+            layer.frame = index;
+        }
+    }
+
+    /// Add a new [`Frame`] at the specified index, with empty cels, returning
+    /// its id. Reference layers get a copy of their image instead, so the
+    /// reference is still there in the new frame. The active frame stays the
+    /// same
+    pub(crate) fn new_frame_at(&mut self, index: usize) -> FrameId {
+        let imgs = self
+            .inner
+            .iter()
+            .map(|layer| match layer.reference() {
+                Some(_) => layer.canvas().inner().clone(),
+                None => IMG::new(layer.canvas().size(), TRANSPARENT),
+            })
+            .collect();
+
+        self.add_frame_at(index, imgs)
+    }
+
+    /// Add a copy of the [`Frame`] at the specified index (with the same cels)
+    /// right after it, returning its id. The active frame stays the same
+    pub(crate) fn duplicate_frame(&mut self, index: usize) -> FrameId {
+        let imgs = self
+            .inner
+            .iter()
+            .map(|layer| layer.cel(index).inner().clone())
+            .collect();
+        let duration = self.frames[index].duration();
+        let id = self.add_frame_at(index + 1, imgs);
+        self.frames[index + 1].set_duration(duration);
+
+        id
+    }
+
+    fn add_frame_at(&mut self, index: usize, imgs: Vec<IMG>) -> FrameId {
+        let frame = Frame::new(FrameId(self.next_frame_id));
+        self.next_frame_id += 1;
+        let id = frame.id();
+        self.restore_frame(index, frame, imgs);
+
+        id
+    }
+
+    /// Add a [`Frame`] that was deleted back at the specified index, keeping
+    /// its id, with the images of its cels (one for each layer, from the
+    /// bottom up). The active frame stays the same, and the [`AnimationTag`]s
+    /// are adjusted (see [`AnimationTag`])
+    pub(crate) fn restore_frame(&mut self, index: usize, frame: Frame, imgs: Vec<IMG>) {
+        let active = self.active_frame_id();
+        self.frames.insert(index, frame);
+        for (layer, img) in self.inner.iter_mut().zip(imgs) {
+            layer.insert_cel(index, img);
+        }
+        for tag in self.tags.iter_mut() {
+            tag.frame_inserted(index);
+        }
+        self.fix_active_frame(active);
+    }
+
+    /// Delete the [`Frame`] at the specified index, returning it along with the
+    /// images of its cels (one for each layer, from the bottom up). If it was
+    /// the active frame, the one that takes its place becomes active (or the
+    /// one before it, if it was the last frame). [`AnimationTag`]s are
+    /// adjusted, and deleted if it was their only frame
+    pub(crate) fn delete_frame(&mut self, index: usize) -> (Frame, Vec<IMG>) {
+        let active = self.active_frame_id();
+        let frame = self.frames.remove(index);
+        let imgs = self
+            .inner
+            .iter_mut()
+            .map(|layer| layer.remove_cel(index))
+            .collect();
+        self.tags.retain_mut(|tag| tag.frame_deleted(index));
+        self.fix_active_frame(active);
+
+        (frame, imgs)
+    }
+
+    /// Move the [`Frame`] at an index to another one, shifting the frames in
+    /// between. The active frame stays the same, and so do the ranges of the
+    /// [`AnimationTag`]s
+    pub(crate) fn move_frame(&mut self, from: usize, to: usize) {
+        let active = self.active_frame_id();
+        let frame = self.frames.remove(from);
+        self.frames.insert(to, frame);
         for layer in self.inner.iter_mut() {
-            let img = layer.resize(size);
-            imgs.push(img);
+            let cel = layer.cels.remove(from);
+            layer.cels.insert(to, cel);
         }
+        self.fix_active_frame(active);
+    }
 
-        imgs
+    /// Make the [`Frame`] with the specified id the active one again after the
+    /// frames changed, or the closest one to its previous index if it was
+    /// deleted
+    fn fix_active_frame(&mut self, active: FrameId) {
+        let index = self
+            .frame_index_of(active)
+            .unwrap_or_else(|_| self.active_frame.min(self.frame_count() - 1));
+        self.switch_frame(index);
     }
 
-    /// Set the active [`Layer`] to the specified index
+    /// Set the active [`Layer`] to the specified index. Reference layers can't
+    /// be active, so switching to one does nothing
     pub fn switch_to(&mut self, index: usize) {
-        self.active = index;
+        if self.inner[index].reference().is_none() {
+            self.active = index;
+        }
     }
 
-    /// Add a new [`Layer`] above all layers
-    pub fn add_new_above(&mut self) {
-        let layer = Layer::new(self.active_canvas().size());
-        self.inner.push(layer);
+    /// If the active index points to a reference layer (e.g. after adding or
+    /// removing layers), make the closest layer below (or else above) it that
+    /// is not a reference the active one
+    fn fix_active(&mut self) {
+        let is_normal = |i: &usize| self.inner[*i].reference().is_none();
+        if let Some(i) = (0..=self.active)
+            .rev()
+            .find(is_normal)
+            .or_else(|| (self.active..self.count()).find(is_normal))
+        {
+            self.active = i;
+        }
     }
 
-    /// Add a new [`Layer`] at the specified index
-    pub fn add_at(&mut self, index: usize, layer: Layer<IMG>) {
+    /// Add a new [`Layer`] above all layers, returning its id
+    pub fn add_new_above(&mut self) -> LayerId {
+        let layer = self.new_layer();
+        self.add_at(self.count(), layer)
+    }
+
+    /// Create a new empty [`Layer`] of the same size as the others, named
+    /// after the position it would have on top of them (without adding it)
+    pub(crate) fn new_layer(&self) -> Layer<IMG> {
+        let mut layer = Layer::new(self.canvas_size());
+        layer.set_name(format!("Layer {}", self.count() + 1));
+
+        layer
+    }
+
+    /// Add a new [`Layer`] at the specified index, giving it a new id, which is
+    /// returned. If the layer has fewer cels than there are frames (e.g. a new
+    /// layer with a single image), its first cel is copied to the frames
+    /// missing one
+    pub fn add_at(&mut self, index: usize, mut layer: Layer<IMG>) -> LayerId {
+        layer.id = LayerId(self.next_id);
+        self.next_id += 1;
+        while layer.cels.len() < self.frame_count() {
+            let img = layer.cels[0].inner().clone();
+            layer.insert_cel(layer.cels.len(), img);
+        }
+        self.restore_at(index, layer);
+
+        self.inner[index].id
+    }
+
+    /// Add a [`Layer`] that was deleted back at the specified index, keeping its
+    /// id
+    pub(crate) fn restore_at(&mut self, index: usize, mut layer: Layer<IMG>) {
+        layer.frame = self.active_frame;
         self.inner.insert(index, layer);
+        self.fix_active();
     }
 
     /// Delete the [`Layer`] at the specified index
     pub fn delete(&mut self, index: usize) -> Layer<IMG> {
         let layer = self.inner.remove(index);
         self.active = self.active.clamp(0, self.count() - 1);
+        self.fix_active();
 
         layer
     }
 
+    /// Set the placement of the reference [`Layer`] at the specified index,
+    /// returning the previous one
+    pub(crate) fn set_reference(
+        &mut self,
+        index: usize,
+        reference: Reference,
+    ) -> Result<Reference> {
+        self.inner[index]
+            .reference
+            .as_mut()
+            .map(|old| std::mem::replace(old, reference))
+            .ok_or(Error::NotAReferenceLayer(self.inner[index].id))
+    }
+
     /// Set whether the [`Layer`] at the specified index is visible or not
     pub fn set_visibility(&mut self, index: usize, visible: bool) {
         self.inner[index].set_visibility(visible);
@@ -133,70 +539,685 @@ impl<IMG: Bitmap> Layers<IMG> {
         self.inner[index].set_opacity(opacity);
     }
 
+    /// Set whether the [`Layer`] at the specified index is locked
+    pub fn set_locked(&mut self, index: usize, locked: bool) {
+        self.inner[index].set_locked(locked);
+    }
+
+    /// Set whether the alpha of the [`Layer`] at the specified index is locked
+    pub fn set_alpha_locked(&mut self, index: usize, alpha_locked: bool) {
+        self.inner[index].set_alpha_locked(alpha_locked);
+    }
+
+    /// Set the name of the [`Layer`] at the specified index, returning the
+    /// previous one
+    pub fn rename(&mut self, index: usize, name: impl Into<String>) -> String {
+        self.inner[index].set_name(name)
+    }
+
+    /// Set (or remove, if `value` is `None`) a metadata entry of the [`Layer`]
+    /// at the specified index, returning the previous value
+    pub fn set_metadata(
+        &mut self,
+        index: usize,
+        key: impl Into<String>,
+        value: Option<String>,
+    ) -> Result<Option<String>> {
+        self.inner[index].set_metadata(key, value)
+    }
+
+    /// Set (or remove, if `mask` is `None`) the mask of the [`Layer`] at the
+    /// specified index, returning the previous one
+    pub(crate) fn set_mask(&mut self, index: usize, mask: Option<IMG>) -> Option<IMG> {
+        self.inner[index].set_mask(mask)
+    }
+
+    /// Invert the mask of the [`Layer`] at the specified index, so that what
+    /// was hidden becomes visible and vice-versa
+    pub fn invert_mask(&mut self, index: usize) -> Result<()> {
+        self.inner[index]
+            .invert_mask()
+            .ok_or(Error::MissingLayerMask(self.inner[index].id))
+    }
+
+    /// Set the [`BlendMode`] of the [`Layer`] at the specified index,
+    /// returning the previous one
+    pub fn set_blend_mode(&mut self, index: usize, mode: BlendMode) -> BlendMode {
+        self.inner[index].set_blend_mode(mode)
+    }
+
     /// Swap the positions of two [`Layer`]s
     pub fn swap(&mut self, first: usize, second: usize) {
         self.inner.swap(first, second);
     }
 
+    /// Get all [`LayerGroup`]s, in no particular order
+    pub fn groups(&self) -> &[LayerGroup] {
+        &self.groups
+    }
+
+    /// Get a [`LayerGroup`] by its id
+    pub fn group(&self, id: GroupId) -> Result<&LayerGroup> {
+        self.find_group(Some(id))
+            .ok_or(Error::MissingLayerGroup(id))
+    }
+
+    fn group_mut(&mut self, id: GroupId) -> Result<&mut LayerGroup> {
+        self.groups
+            .iter_mut()
+            .find(|group| group.id() == id)
+            .ok_or(Error::MissingLayerGroup(id))
+    }
+
+    fn find_group(&self, id: Option<GroupId>) -> Option<&LayerGroup> {
+        self.groups.iter().find(|group| Some(group.id()) == id)
+    }
+
+    /// Get the ids of the groups containing the [`Layer`] at the specified
+    /// index, from the innermost to the outermost
+    pub fn ancestors(&self, index: usize) -> Vec<GroupId> {
+        self.group_chain(self.inner[index].group())
+    }
+
+    /// Get the id of a group followed by the ids of the groups containing it,
+    /// from the innermost to the outermost
+    fn group_chain(&self, id: Option<GroupId>) -> Vec<GroupId> {
+        std::iter::successors(self.find_group(id), |group| self.find_group(group.parent()))
+            .map(|group| group.id())
+            .collect()
+    }
+
+    /// Get the range of indices of the [`Layer`]s inside a group (including
+    /// the ones in nested groups), or `None` if it is empty
+    pub fn group_range(&self, id: GroupId) -> Option<RangeInclusive<usize>> {
+        let mut indices = (0..self.count()).filter(|i| self.ancestors(*i).contains(&id));
+        let first = indices.next()?;
+        let last = indices.next_back().unwrap_or(first);
+
+        Some(first..=last)
+    }
+
+    /// Get the innermost group that contains all [`Layer`]s in a range of
+    /// indices, if any
+    pub fn common_group(&self, range: Range<usize>) -> Option<GroupId> {
+        self.ancestors(range.start)
+            .into_iter()
+            .find(|id| range.clone().all(|i| self.ancestors(i).contains(id)))
+    }
+
+    /// Whether the [`Layer`] at the specified index is visible, considering
+    /// the visibility of the groups that contain it
+    pub fn effective_visibility(&self, index: usize) -> bool {
+        self.inner[index].visible()
+            && self
+                .ancestors(index)
+                .into_iter()
+                .all(|id| self.find_group(Some(id)).is_some_and(|g| g.visible()))
+    }
+
+    /// Get the opacity of the [`Layer`] at the specified index multiplied by
+    /// the opacity of the groups that contain it. This is how opaque the layer
+    /// looks on its own: when compositing, the opacity of a group is applied
+    /// once to all of its layers composited together
+    pub fn effective_opacity(&self, index: usize) -> u8 {
+        self.ancestors(index)
+            .into_iter()
+            .filter_map(|id| self.find_group(Some(id)))
+            .fold(self.inner[index].opacity(), |opacity, group| {
+                multiply_alpha(opacity, group.opacity())
+            })
+    }
+
+    /// Replace all [`LayerGroup`]s, returning the previous ones. The groups
+    /// the layers belong to are not changed
+    pub(crate) fn set_groups(&mut self, groups: Vec<LayerGroup>) -> Vec<LayerGroup> {
+        std::mem::replace(&mut self.groups, groups)
+    }
+
+    /// Check that the parents of some [`LayerGroup`]s are among them, and
+    /// that no group ends up inside itself
+    pub(crate) fn check_groups(&self, groups: &[LayerGroup]) -> Result<()> {
+        for group in groups {
+            // A chain of parents longer than the number of groups has a cycle
+            let mut parent = group.parent();
+            for _ in 0..groups.len() {
+                parent = match parent {
+                    Some(id) => groups
+                        .iter()
+                        .find(|group| group.id() == id)
+                        .ok_or(Error::MissingLayerGroup(id))?
+                        .parent(),
+                    None => break,
+                };
+            }
+            if parent.is_some() {
+                return Err(Error::LayerGroupCycle(group.id()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the group the [`Layer`] at the specified index belongs to,
+    /// returning the previous one
+    pub(crate) fn set_layer_group(
+        &mut self,
+        index: usize,
+        group: Option<GroupId>,
+    ) -> Option<GroupId> {
+        self.inner[index].set_group(group)
+    }
+
+    /// Remove the groups that have no [`Layer`]s inside them, returning
+    /// whether any group was removed
+    pub(crate) fn prune_groups(&mut self) -> bool {
+        let empty: Vec<_> = self
+            .groups
+            .iter()
+            .map(|group| group.id())
+            .filter(|id| self.group_range(*id).is_none())
+            .collect();
+        self.groups.retain(|group| !empty.contains(&group.id()));
+
+        !empty.is_empty()
+    }
+
+    /// Put the [`Layer`]s in a range of indices into a new group, returning
+    /// its id. The new group is created inside the innermost group containing
+    /// the whole range, and the range must not contain only part of any group
+    /// inside that one
+    pub fn group_layers(&mut self, range: RangeInclusive<usize>) -> Result<GroupId> {
+        let (first, last) = (*range.start(), *range.end());
+        if first > last || last >= self.count() {
+            return Err(Error::InvalidLayerGroupRange(first, last));
+        }
+
+        let parent = self.common_group(first..last + 1);
+        let mut children = Vec::new();
+        for i in range.clone() {
+            // The outermost group containing this layer inside the parent, if
+            // it isn't directly inside the parent
+            let child = self
+                .ancestors(i)
+                .into_iter()
+                .take_while(|id| Some(*id) != parent)
+                .last();
+
+            if let Some(child) = child {
+                let child_range = self.group_range(child).expect("group contains layer");
+                if !range.contains(child_range.start()) || !range.contains(child_range.end()) {
+                    return Err(Error::InvalidLayerGroupRange(first, last));
+                }
+            }
+            children.push((i, child));
+        }
+
+        let id = GroupId(self.groups.iter().map(|g| g.id().0 + 1).max().unwrap_or(0));
+        self.groups.push(LayerGroup::new(id, parent));
+        for (i, child) in children {
+            match child {
+                Some(child) => self.group_mut(child)?.set_parent(Some(id)),
+                None => {
+                    self.inner[i].set_group(Some(id));
+                }
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Remove a group, moving everything inside it to the group that contained
+    /// it (if any)
+    pub fn ungroup(&mut self, id: GroupId) -> Result<()> {
+        let parent = self.group(id)?.parent();
+
+        for layer in self.inner.iter_mut().filter(|l| l.group() == Some(id)) {
+            layer.set_group(parent);
+        }
+        for group in self.groups.iter_mut().filter(|g| g.parent() == Some(id)) {
+            group.set_parent(parent);
+        }
+        self.groups.retain(|group| group.id() != id);
+
+        Ok(())
+    }
+
+    /// Set whether a group (and therefore everything inside it) is visible
+    pub fn set_group_visibility(&mut self, id: GroupId, visible: bool) -> Result<()> {
+        self.group_mut(id)?.set_visibility(visible);
+        Ok(())
+    }
+
+    /// Set the opacity (alpha) of a group
+    pub fn set_group_opacity(&mut self, id: GroupId, opacity: u8) -> Result<()> {
+        self.group_mut(id)?.set_opacity(opacity);
+        Ok(())
+    }
+
+    /// Set whether a group is collapsed in the list of layers
+    pub fn set_group_collapsed(&mut self, id: GroupId, collapsed: bool) -> Result<()> {
+        self.group_mut(id)?.set_collapsed(collapsed);
+        Ok(())
+    }
+
+    /// Move the [`Layer`] at the specified index up. If the layer above it is
+    /// in the same group they swap positions, if it is in a group next to this
+    /// layer the layer enters that group, and if this layer is at the top of
+    /// its group it leaves the group.
+    pub fn move_layer_up(&mut self, index: usize) {
+        self.move_layer(index, index + 1);
+    }
+
+    /// Move the [`Layer`] at the specified index down, the same way as
+    /// [`Layers::move_layer_up`] moves it up
+    pub fn move_layer_down(&mut self, index: usize) {
+        if let Some(neighbor) = index.checked_sub(1) {
+            self.move_layer(index, neighbor);
+        } else {
+            self.leave_group(index);
+        }
+    }
+
+    fn move_layer(&mut self, index: usize, neighbor: usize) {
+        if neighbor >= self.count() {
+            self.leave_group(index);
+            return;
+        }
+
+        let group = self.inner[index].group();
+        match self.sibling_group(group, neighbor) {
+            Some(None) => {
+                self.swap(index, neighbor);
+                if self.active == index {
+                    self.active = neighbor;
+                } else if self.active == neighbor {
+                    self.active = index;
+                }
+            }
+            Some(sibling) => {
+                self.inner[index].set_group(sibling);
+            }
+            None => self.leave_group(index),
+        }
+    }
+
+    fn leave_group(&mut self, index: usize) {
+        if let Some(group) = self.find_group(self.inner[index].group()) {
+            let parent = group.parent();
+            self.inner[index].set_group(parent);
+        }
+    }
+
+    /// Find out where the [`Layer`] at index `neighbor` is relative to a
+    /// `group`: `None` if it's not inside the group, `Some(None)` if it's
+    /// directly inside it, or `Some(Some(id))` if it's inside a group (with
+    /// that id) directly inside it
+    fn sibling_group(&self, group: Option<GroupId>, neighbor: usize) -> Option<Option<GroupId>> {
+        let chain = self.ancestors(neighbor);
+
+        match group {
+            Some(group) => {
+                let pos = chain.iter().position(|id| *id == group)?;
+                Some(pos.checked_sub(1).map(|i| chain[i]))
+            }
+            None => Some(chain.last().copied()),
+        }
+    }
+
+    /// Move a whole group up, the same way as [`Layers::move_layer_up`] moves a
+    /// single layer: it swaps positions with the layer above it, enters the
+    /// group above it, or leaves its parent group
+    pub fn move_group_up(&mut self, id: GroupId) -> Result<()> {
+        let range = match self.group_range(id) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+
+        self.move_group(id, range.clone(), range.end() + 1)
+    }
+
+    /// Move a whole group down, the same way as [`Layers::move_group_up`]
+    /// moves it up
+    pub fn move_group_down(&mut self, id: GroupId) -> Result<()> {
+        let range = match self.group_range(id) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+
+        match range.start().checked_sub(1) {
+            Some(neighbor) => self.move_group(id, range, neighbor),
+            None => self.leave_parent_group(id),
+        }
+    }
+
+    fn move_group(
+        &mut self,
+        id: GroupId,
+        range: RangeInclusive<usize>,
+        neighbor: usize,
+    ) -> Result<()> {
+        if neighbor >= self.count() {
+            return self.leave_parent_group(id);
+        }
+
+        let parent = self.group(id)?.parent();
+        let (first, last) = (*range.start(), *range.end());
+        match self.sibling_group(parent, neighbor) {
+            // The neighbor layer goes to the other side of the group
+            Some(None) if neighbor > last => {
+                self.inner[first..=neighbor].rotate_right(1);
+                self.active = match self.active {
+                    a if a == neighbor => first,
+                    a if range.contains(&a) => a + 1,
+                    a => a,
+                };
+            }
+            Some(None) => {
+                self.inner[neighbor..=last].rotate_left(1);
+                self.active = match self.active {
+                    a if a == neighbor => last,
+                    a if range.contains(&a) => a - 1,
+                    a => a,
+                };
+            }
+            Some(sibling) => self.group_mut(id)?.set_parent(sibling),
+            None => self.leave_parent_group(id)?,
+        }
+
+        Ok(())
+    }
+
+    fn leave_parent_group(&mut self, id: GroupId) -> Result<()> {
+        if let Some(parent) = self.group(id)?.parent() {
+            let grandparent = self.group(parent)?.parent();
+            self.group_mut(id)?.set_parent(grandparent);
+        }
+
+        Ok(())
+    }
+
     // TODO: maybe Canvas is a better name for Layers than for that type, since
     // the canvas is a combination of all layers, not a single layer's image
     /// Get the color of the visible pixel at a certain [`Point`] in the canvas,
-    /// considering the blended result of all layers with their visibility and
-    /// opacity settings
+    /// considering the blended result of all layers with their visibility,
+    /// opacity and blend mode settings
     pub fn visible_pixel(&self, p: Point<i32>) -> Color {
-        let mut result = if self.inner[0].visible() {
-            self.canvas_at(0)
-                .pixel(p)
-                .with_multiplied_alpha(self.get(0).opacity())
-        } else {
-            TRANSPARENT
-        };
+        let stack = self.composite_stack(0..self.count(), None);
+
+        self.composite_pixel(&stack, self.active_frame, p)
+            .unwrap_or(TRANSPARENT)
+    }
+
+    /// Arrange the visible [`Layer`]s in a range of indices (leaving reference
+    /// layers out) into a tree of the groups inside `outer` that contain them,
+    /// so that they are composited group by group (see
+    /// [`Layers::composite_pixel`])
+    fn composite_stack(&self, range: Range<usize>, outer: Option<GroupId>) -> Vec<Composite> {
+        let mut stack = Vec::new();
 
-        for i in 1..self.count() {
-            if !self.get(i).visible() {
+        'layers: for i in range {
+            let layer = self.get(i);
+            if !layer.visible() || layer.reference().is_some() {
                 continue;
             }
 
-            let color = self
-                .canvas_at(i)
-                .pixel(p)
-                .with_multiplied_alpha(self.get(i).opacity());
-            result = color.blend_over(result);
+            let mut groups = Vec::new();
+            for id in self.ancestors(i) {
+                if Some(id) == outer {
+                    break;
+                }
+                match self.find_group(Some(id)) {
+                    Some(group) if group.visible() => groups.push(group),
+                    _ => continue 'layers,
+                }
+            }
+
+            // The layers of a group are next to each other, so if the group is
+            // already in the stack, it's the last element at its level
+            let mut level = &mut stack;
+            for group in groups.into_iter().rev() {
+                if !matches!(level.last(), Some(Composite::Group(id, _, _)) if *id == group.id()) {
+                    level.push(Composite::Group(group.id(), group.opacity(), Vec::new()));
+                }
+                level = match level.last_mut() {
+                    Some(Composite::Group(_, _, children)) => children,
+                    _ => unreachable!("a group was just found or pushed"),
+                };
+            }
+            level.push(Composite::Layer(i));
+        }
+
+        stack
+    }
+
+    /// Get the color of a pixel of a frame with the layers (and groups) of a
+    /// stack composited from the bottom up, or `None` if the stack is empty.
+    /// The layers of a group are composited on their own first, and then the
+    /// result is blended with what is below the group, with the group's
+    /// opacity
+    fn composite_pixel(&self, stack: &[Composite], frame: usize, p: Point<i32>) -> Option<Color> {
+        // The bottom element is not blended with anything
+        let mut result = None;
+
+        for element in stack {
+            let (color, mode) = match element {
+                Composite::Layer(i) => {
+                    let layer = self.get(*i);
+                    let color = layer.masked_cel_pixel(frame, p);
+                    (
+                        color.with_multiplied_alpha(layer.opacity()),
+                        layer.blend_mode(),
+                    )
+                }
+                Composite::Group(_, opacity, children) => {
+                    let color = self.composite_pixel(children, frame, p);
+                    let color = color.unwrap_or(TRANSPARENT);
+                    (color.with_multiplied_alpha(*opacity), BlendMode::Normal)
+                }
+            };
+
+            result = Some(match result {
+                None => color,
+                Some(below) => color.blend_over_with(below, mode),
+            });
         }
 
         result
     }
 }
 
+/// A [`Layer`] (by its index) or a [`LayerGroup`] (with its opacity and
+/// contents) to be composited
+enum Composite {
+    Layer(usize),
+    Group(GroupId, u8, Vec<Composite>),
+}
+
 /// Represents a layer of the canvas. Layers are stacked on top of each other to
 /// make a final image, blending colors with transparency. Layers can be moved
 /// up or down relative to each other, can be made invisible or have a level of
-/// transparency (opacity).
+/// transparency (opacity). A layer has an image (a cel) for each [`Frame`].
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Layer<IMG> {
-    canvas: Canvas<IMG>,
+    id: LayerId,
+    cels: Vec<Canvas<IMG>>,
+    frame: usize,
+    mask: Option<Canvas<IMG>>,
+    reference: Option<Reference>,
     visible: bool,
     opacity: u8,
+    blend_mode: BlendMode,
+    locked: bool,
+    alpha_locked: bool,
+    group: Option<GroupId>,
+    name: String,
+    metadata: BTreeMap<String, String>,
 }
 
 impl<IMG: Bitmap> Layer<IMG> {
     /// Create a new layer with a specified size
     pub fn new(size: Size<i32>) -> Self {
         Self {
-            canvas: Canvas::new(size),
+            id: LayerId(0),
+            cels: vec![Canvas::new(size)],
+            frame: 0,
+            mask: None,
+            reference: None,
             visible: true,
             opacity: 255,
+            blend_mode: BlendMode::Normal,
+            locked: false,
+            alpha_locked: false,
+            group: None,
+            name: String::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Create a new layer with an image
+    pub fn from_img(img: IMG) -> Self {
+        let mut layer = Self::new(Size::ZERO);
+        layer.canvas_mut().set_img(img);
+
+        layer
+    }
+
+    /// Create a new layer with an image for each frame (its cels). The images
+    /// must all have the same size
+    pub(crate) fn from_cels(imgs: Vec<IMG>) -> Self {
+        let mut layer = Self::new(Size::ZERO);
+        layer.cels.clear();
+        for img in imgs {
+            layer.insert_cel(layer.cels.len(), img);
         }
+
+        layer
+    }
+
+    /// Create a reference layer with an image of any size, placed at the top
+    /// left corner of the canvas with its original size
+    pub fn new_reference(img: IMG) -> Self {
+        let mut layer = Self::from_img(img);
+        layer.reference = Some(Reference::default());
+
+        layer
+    }
+
+    /// Create a copy of this layer, with the same image and settings, named
+    /// after it
+    pub fn duplicate(&self) -> Self {
+        let mut layer = Self::from_cels(self.cels.iter().map(|cel| cel.inner().clone()).collect());
+        layer.frame = self.frame;
+        layer.set_mask(self.mask.as_ref().map(|mask| mask.inner().clone()));
+        layer.set_offset(self.offset());
+        layer.reference = self.reference;
+        layer.visible = self.visible;
+        layer.opacity = self.opacity;
+        layer.blend_mode = self.blend_mode;
+        layer.locked = self.locked;
+        layer.alpha_locked = self.alpha_locked;
+        layer.group = self.group;
+        layer.name = format!("{} copy", self.name);
+        layer.metadata = self.metadata.clone();
+
+        layer
     }
 
-    /// Get the [`Canvas`] of this layer
+    /// Get the id of this layer, which is given to it when it is added to
+    /// [`Layers`] and never changes (not even if it is moved)
+    pub fn id(&self) -> LayerId {
+        self.id
+    }
+
+    /// Get the [`Canvas`] of this layer, i.e. its cel in the active frame
     pub fn canvas(&self) -> &Canvas<IMG> {
-        &self.canvas
+        &self.cels[self.frame]
     }
 
     /// Get a mutable reference to the [`Canvas`] of this layer
     pub fn canvas_mut(&mut self) -> &mut Canvas<IMG> {
-        &mut self.canvas
+        &mut self.cels[self.frame]
+    }
+
+    /// Get the cel (the [`Canvas`]) of this layer in the frame at the
+    /// specified index. All cels of a layer have the same size and offset,
+    /// and share its mask. Unless stated otherwise, the methods of a layer work
+    /// on the cel of the active frame
+    pub fn cel(&self, frame: usize) -> &Canvas<IMG> {
+        &self.cels[frame]
+    }
+
+    /// Get the number of cels of this layer (one for each frame)
+    pub fn cel_count(&self) -> usize {
+        self.cels.len()
+    }
+
+    /// Get the mask of this layer, if it has one. A mask is a grayscale image
+    /// that hides the parts of the layer where it is dark or transparent,
+    /// without changing its pixels
+    pub fn mask(&self) -> Option<&Canvas<IMG>> {
+        self.mask.as_ref()
+    }
+
+    /// Get a mutable reference to the mask of this layer, if it has one
+    pub fn mask_mut(&mut self) -> Option<&mut Canvas<IMG>> {
+        self.mask.as_mut()
+    }
+
+    /// Get the color of a pixel of this layer with its mask applied, i.e. with
+    /// its alpha multiplied by the brightness and alpha of the mask there
+    pub fn masked_pixel(&self, p: Point<i32>) -> Color {
+        self.masked_cel_pixel(self.frame, p)
+    }
+
+    /// Same as [`Layer::masked_pixel`], but in the cel of the frame at the
+    /// specified index
+    fn masked_cel_pixel(&self, frame: usize, p: Point<i32>) -> Color {
+        let color = self.cels[frame].pixel(p);
+        match self.mask.as_ref() {
+            Some(mask) => color.with_multiplied_alpha(mask_value(mask.pixel(p))),
+            None => color,
+        }
+    }
+
+    /// Get the image of this layer with its mask applied (see
+    /// [`Layer::masked_pixel`])
+    pub fn masked_img(&self) -> IMG {
+        self.masked_area(self.canvas().rect())
+    }
+
+    /// Get an image of an area (determined by a rectangle) of the canvas with
+    /// this layer's mask applied
+    pub fn masked_area(&self, r: Rect<i32>) -> IMG {
+        if self.mask.is_none() {
+            return self.canvas().img_from_area(r);
+        }
+
+        let mut img = IMG::new(r.size(), TRANSPARENT);
+        for i in 0..r.w {
+            for j in 0..r.h {
+                let ij = Point::new(i, j);
+                img.set_pixel(ij, self.masked_pixel(ij + r.pos()));
+            }
+        }
+
+        img
+    }
+
+    /// Get the position of the top left corner of this layer's image in the
+    /// canvas. The image may be bigger or smaller than the canvas and be
+    /// anywhere over it, so that content moved out of the canvas is kept.
+    /// Reference layers are placed by their [`Reference`] instead
+    pub fn offset(&self) -> Point<i32> {
+        self.canvas().offset()
+    }
+
+    /// Get the placement of this layer's image over the canvas, if it is a
+    /// reference layer. A reference layer holds an image (e.g. a photo used for
+    /// tracing) that is shown at its own scale and offset, but is not part of
+    /// the drawing: it can't be drawn on and is left out of exported images
+    pub fn reference(&self) -> Option<Reference> {
+        self.reference
     }
 
     /// Whether this layer is visible
@@ -209,16 +1230,111 @@ impl<IMG: Bitmap> Layer<IMG> {
         self.opacity
     }
 
+    /// Get the [`BlendMode`] of this layer, which defines how it is combined
+    /// with the layers below
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Whether this layer is locked, i.e. it can't be drawn on
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether the alpha of this layer is locked, i.e. drawing on it can only
+    /// change pixels that are not fully transparent
+    pub fn alpha_locked(&self) -> bool {
+        self.alpha_locked
+    }
+
+    /// Get the name of this layer
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the metadata of this layer (free-form key/value pairs), sorted by
+    /// key. It is kept in project files and layered exports
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Get the id of the [`LayerGroup`] this layer belongs to directly, if any
+    pub fn group(&self) -> Option<GroupId> {
+        self.group
+    }
+
     /// Take the image of this layer's [`Canvas`], leaving a dummy empty one in
     /// its place
     pub fn take_img(&mut self) -> IMG {
-        self.canvas.take_inner()
+        self.canvas_mut().take_inner()
     }
 
-    /// Resize this layer, returning the previous image (the image before the
-    /// resizing)
-    pub fn resize(&mut self, size: Size<i32>) -> IMG {
-        self.canvas.resize(size)
+    /// Grow the cels of this layer so that they cover an area of the canvas,
+    /// keeping their content where it is. The mask grows too, revealing any
+    /// new area. If anything changed, return the previous offset, cel images
+    /// and mask (used for undoing). Reference layers are left as they are.
+    pub(crate) fn cover(&mut self, area: Rect<i32>) -> Option<(Point<i32>, Vec<IMG>, Option<IMG>)> {
+        let rect = self.canvas().rect();
+        let bounds = rect.union(area);
+        if self.reference.is_some() || bounds == rect {
+            return None;
+        }
+
+        let mask = self
+            .mask
+            .as_mut()
+            .map(|mask| extend_canvas(mask, bounds, WHITE));
+        let imgs = self
+            .cels
+            .iter_mut()
+            .map(|cel| extend_canvas(cel, bounds, TRANSPARENT))
+            .collect();
+
+        Some((rect.pos(), imgs, mask))
+    }
+
+    /// Move the cels (and mask) of this layer so that their top left corner is
+    /// at a certain position of the canvas, returning the previous one
+    pub(crate) fn set_offset(&mut self, offset: Point<i32>) -> Point<i32> {
+        let old = self.offset();
+        if let Some(mask) = self.mask.as_mut() {
+            mask.set_offset(offset);
+        }
+        for cel in self.cels.iter_mut() {
+            cel.set_offset(offset);
+        }
+
+        old
+    }
+
+    /// Replace the images of all cels of this layer, returning the previous
+    /// ones
+    pub(crate) fn set_cels(&mut self, imgs: Vec<IMG>) -> Vec<IMG> {
+        self.cels
+            .iter_mut()
+            .zip(imgs)
+            .map(|(cel, img)| {
+                let old = cel.take_inner();
+                cel.set_img(img);
+                old
+            })
+            .collect()
+    }
+
+    /// Add a cel with an image at the specified frame index, placed where the
+    /// other cels are
+    fn insert_cel(&mut self, index: usize, img: IMG) {
+        let offset = self.cels.first().map_or(Point::ZERO, |cel| cel.offset());
+        let mut cel = Canvas::new(Size::ZERO);
+        cel.set_img(img);
+        cel.set_offset(offset);
+        self.cels.insert(index, cel);
+    }
+
+    /// Remove the cel at the specified frame index, returning its image
+    fn remove_cel(&mut self, index: usize) -> IMG {
+        let mut cel = self.cels.remove(index);
+        cel.take_inner()
     }
 
     /// Set whether this layer is visible
@@ -230,6 +1346,147 @@ impl<IMG: Bitmap> Layer<IMG> {
     pub fn set_opacity(&mut self, opacity: u8) {
         self.opacity = opacity;
     }
+
+    /// Set the [`BlendMode`] of this layer, returning the previous one
+    pub fn set_blend_mode(&mut self, mode: BlendMode) -> BlendMode {
+        std::mem::replace(&mut self.blend_mode, mode)
+    }
+
+    /// Set whether this layer is locked
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// Set whether the alpha of this layer is locked
+    pub fn set_alpha_locked(&mut self, alpha_locked: bool) {
+        self.alpha_locked = alpha_locked;
+    }
+
+    /// Set the name of this layer, returning the previous one
+    pub fn set_name(&mut self, name: impl Into<String>) -> String {
+        std::mem::replace(&mut self.name, name.into())
+    }
+
+    /// Set (or remove, if `value` is `None`) a metadata entry of this layer,
+    /// returning the previous value. Keys must have from 1 to 79 characters
+    /// from the Latin-1 character set, without control characters or spaces
+    /// at the start or end, so that they can be stored in exported images
+    pub fn set_metadata(
+        &mut self,
+        key: impl Into<String>,
+        value: Option<String>,
+    ) -> Result<Option<String>> {
+        let key = key.into();
+        let valid = (1..=79).contains(&key.chars().count())
+            && key.chars().all(|c| c <= '\u{ff}' && !c.is_control())
+            && key.trim() == key;
+        if !valid {
+            return Err(Error::InvalidMetadataKey(key));
+        }
+
+        Ok(match value {
+            Some(value) => self.metadata.insert(key, value),
+            None => self.metadata.remove(&key),
+        })
+    }
+
+    /// Set (or remove, if `mask` is `None`) the mask of this layer, returning
+    /// the previous one
+    pub(crate) fn set_mask(&mut self, mask: Option<IMG>) -> Option<IMG> {
+        let old = self.mask.take().map(|mut mask| mask.take_inner());
+        self.mask = mask.map(|img| {
+            let mut mask = Canvas::new(Size::ZERO);
+            mask.set_img(img);
+            mask.set_offset(self.offset());
+            mask
+        });
+
+        old
+    }
+
+    /// Invert the mask of this layer, if it has one, making it opaque and
+    /// grayscale
+    pub(crate) fn invert_mask(&mut self) -> Option<()> {
+        let mask = self.mask.as_mut()?;
+        let mut img = mask.take_inner();
+        for i in 0..img.width() {
+            for j in 0..img.height() {
+                let p = Point::new(i, j);
+                let value = 255 - mask_value(img.pixel(p));
+                img.set_pixel(p, Color::new(value, value, value, 255));
+            }
+        }
+        mask.set_img(img);
+
+        Some(())
+    }
+
+    /// Set the [`LayerGroup`] this layer belongs to, returning the previous one
+    pub(crate) fn set_group(&mut self, group: Option<GroupId>) -> Option<GroupId> {
+        std::mem::replace(&mut self.group, group)
+    }
+}
+
+/// Identifies a [`Layer`] within its [`Layers`], no matter where it is in the
+/// stack. Ids of deleted layers are never given to new ones
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LayerId(pub(crate) usize);
+
+/// Where the image of a reference [`Layer`] is shown over the canvas: it is
+/// scaled by a factor and then moved by an offset (in canvas pixels)
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reference {
+    offset: Point<i32>,
+    scale: f32,
+}
+
+impl Default for Reference {
+    fn default() -> Self {
+        Self::new(Point::ZERO, 1.)
+    }
+}
+
+impl Reference {
+    /// Create a new placement for a reference image. The scale must be
+    /// positive
+    pub fn new(offset: Point<i32>, scale: f32) -> Self {
+        Self { offset, scale }
+    }
+
+    /// Get the position of the top left corner of the image in the canvas
+    pub fn offset(&self) -> Point<i32> {
+        self.offset
+    }
+
+    /// Get the factor by which the image is scaled
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+}
+
+/// Replace the image of a [`Canvas`] by one covering some bounds, filled with
+/// a color except where the previous image was, returning the previous image
+fn extend_canvas<IMG: Bitmap>(canvas: &mut Canvas<IMG>, bounds: Rect<i32>, fill: Color) -> IMG {
+    let pos = canvas.offset();
+    let old = canvas.take_inner();
+    canvas.set_img(IMG::new(bounds.size(), fill));
+    canvas.set_offset(bounds.pos());
+    canvas.replace_area(pos, &old);
+
+    old
+}
+
+/// Get how much of a layer a color of its mask reveals, from 0 (nothing) to
+/// 255 (everything): its brightness multiplied by its alpha
+fn mask_value(color: Color) -> u8 {
+    let brightness = (color.r as u16 + color.g as u16 + color.b as u16) / 3;
+    multiply_alpha(brightness as u8, color.a)
+}
+
+/// Multiply two alpha values, the same way [`Color::with_multiplied_alpha`]
+/// does
+fn multiply_alpha(a: u8, b: u8) -> u8 {
+    Color::new(0, 0, 0, a).with_multiplied_alpha(b).a
 }
 
 #[cfg(test)]
@@ -264,4 +1521,283 @@ mod tests {
 
         assert_eq!(pixel, res.into());
     }
+
+    #[test_case(BlendMode::Normal, (255, 0, 0, 255))]
+    #[test_case(BlendMode::Multiply, (100, 0, 0, 255))]
+    #[test_case(BlendMode::Lighten, (255, 100, 100, 255))]
+    fn visible_pixel_blend_mode<C: Into<Color>>(mode: BlendMode, res: C) {
+        let mut layers = get_three_layer_canvas();
+        let p = Point::new(0, 1);
+        layers
+            .canvas_at_mut(0)
+            .set_pixel(p, Color::new(100, 100, 100, 255));
+        layers
+            .canvas_at_mut(2)
+            .set_pixel(p, Color::new(255, 0, 0, 255));
+        layers.set_blend_mode(2, mode);
+        let res = res.into();
+
+        assert_eq!(layers.visible_pixel(p), res);
+        assert_eq!(layers.blended().pixel(p), res);
+    }
+
+    #[test]
+    fn blended_range() {
+        let mut layers = get_three_layer_canvas();
+        let p = Point::new(1, 0);
+        let red = Color::new(255, 0, 0, 255);
+        layers.canvas_at_mut(1).set_pixel(p, red);
+        layers
+            .canvas_at_mut(2)
+            .set_pixel(p, Color::new(0, 0, 255, 127));
+        layers.set_opacity(1, 127);
+
+        assert_eq!(
+            layers.blended_range(0..2).pixel(p),
+            red.with_multiplied_alpha(127)
+        );
+        assert_eq!(layers.blended_range(1..3).pixel(p), layers.visible_pixel(p));
+    }
+
+    #[test_case(true, 255, (255, 0, 0, 127))]
+    #[test_case(true, 127, (255, 0, 0, 63))]
+    #[test_case(false, 255, (0, 0, 0, 0))]
+    fn visible_pixel_group<C: Into<Color>>(visible: bool, opacity: u8, res: C) {
+        let mut layers = get_three_layer_canvas();
+        let p = Point::new(1, 1);
+        layers
+            .canvas_at_mut(1)
+            .set_pixel(p, Color::new(255, 0, 0, 255));
+        layers.set_opacity(1, 127);
+        let inner = layers.group_layers(1..=1).unwrap();
+        let outer = layers.group_layers(1..=2).unwrap();
+        layers.set_group_visibility(inner, visible).unwrap();
+        layers.set_group_opacity(outer, opacity).unwrap();
+        let res = res.into();
+
+        assert_eq!(layers.ancestors(1), vec![inner, outer]);
+        assert_eq!(layers.effective_visibility(1), visible);
+        assert_eq!(layers.visible_pixel(p), res);
+        assert_eq!(layers.blended().pixel(p), res);
+        // The settings of the group containing the whole range are left out
+        assert_eq!(
+            layers.blended_range(1..3).pixel(p).a,
+            if visible { 127 } else { 0 }
+        );
+    }
+
+    #[test]
+    fn visible_pixel_translucent_group() {
+        let mut layers = get_three_layer_canvas();
+        let p = Point::new(1, 1);
+        let red = Color::new(255, 0, 0, 255);
+        layers
+            .canvas_at_mut(1)
+            .set_pixel(p, Color::new(0, 0, 255, 255));
+        layers.canvas_at_mut(2).set_pixel(p, red);
+        let group = layers.group_layers(1..=2).unwrap();
+        layers.set_group_opacity(group, 127).unwrap();
+
+        // The layer below doesn't show through the one above it in the group
+        assert_eq!(layers.visible_pixel(p), red.with_multiplied_alpha(127));
+        assert_eq!(layers.blended().pixel(p), red.with_multiplied_alpha(127));
+        assert_eq!(layers.effective_opacity(2), 127);
+    }
+
+    #[test_case(0..=1, false)]
+    #[test_case(0..=2, true)]
+    #[test_case(1..=2, true)]
+    #[test_case(2..=3, false)]
+    #[test_case(1..=3, true)]
+    #[test_case(2..=4, false)]
+    fn group_layers(range: RangeInclusive<usize>, ok: bool) {
+        let mut layers = get_three_layer_canvas();
+        layers.add_new_above();
+        layers.add_new_above();
+        layers.group_layers(1..=2).unwrap();
+
+        assert_eq!(layers.group_layers(range).is_ok(), ok);
+    }
+
+    #[test_case("author", true)]
+    #[test_case("Título", true)]
+    #[test_case("", false)]
+    #[test_case(" author", false)]
+    #[test_case("a\tb", false)]
+    #[test_case("作者", false)]
+    fn set_metadata(key: &str, ok: bool) {
+        let mut layer = Layer::<TestImage>::new(Size::new(1, 1));
+
+        assert_eq!(layer.set_metadata(key, Some("x".to_owned())).is_ok(), ok);
+        assert_eq!(layer.metadata().contains_key(key), ok);
+    }
+
+    #[test]
+    fn move_layer_through_group() {
+        let mut layers = get_three_layer_canvas();
+        let id = layers.group_layers(1..=1).unwrap();
+
+        layers.move_layer_up(0);
+        assert_eq!(layers.get(0).group(), Some(id));
+        assert_eq!(layers.group_range(id), Some(0..=1));
+
+        layers.switch_to(0);
+        layers.move_layer_up(0);
+        assert_eq!(layers.active_index(), 1);
+        assert_eq!(layers.get(1).group(), Some(id));
+
+        layers.move_layer_up(1);
+        layers.move_layer_up(1);
+        assert_eq!(layers.get(1).group(), None);
+        assert_eq!(layers.group_range(id), Some(0..=0));
+    }
+
+    #[test]
+    fn move_group() {
+        let mut layers = get_three_layer_canvas();
+        layers.add_new_above();
+        layers
+            .canvas_at_mut(0)
+            .set_pixel(Point::ZERO, Color::new(1, 1, 1, 1));
+        let id = layers.group_layers(0..=1).unwrap();
+
+        layers.move_group_up(id).unwrap();
+        assert_eq!(layers.group_range(id), Some(1..=2));
+        assert_eq!(
+            layers.canvas_at(1).pixel(Point::ZERO),
+            Color::new(1, 1, 1, 1)
+        );
+
+        let other = layers.group_layers(3..=3).unwrap();
+        layers.move_group_up(id).unwrap();
+        assert_eq!(layers.group(id).unwrap().parent(), Some(other));
+        assert_eq!(layers.group_range(other), Some(1..=3));
+
+        layers.move_group_down(id).unwrap();
+        assert_eq!(layers.group(id).unwrap().parent(), None);
+        layers.move_group_down(id).unwrap();
+        assert_eq!(layers.group_range(id), Some(0..=1));
+    }
+
+    #[test]
+    fn layer_locks() {
+        let mut layers = get_three_layer_canvas();
+        layers.set_locked(1, true);
+        layers.set_alpha_locked(2, true);
+
+        assert!(layers.get(1).locked() && !layers.get(1).alpha_locked());
+        assert!(!layers.get(2).locked() && layers.get(2).alpha_locked());
+    }
+
+    #[test_case(color::WHITE, 255)]
+    #[test_case(color::BLACK, 0)]
+    #[test_case(TRANSPARENT, 0)]
+    #[test_case([255, 255, 255, 102], 102)]
+    #[test_case([102, 102, 102, 255], 102)]
+    fn masked_pixel<C: Into<Color>>(mask_color: C, alpha: u8) {
+        let p = Point::ZERO;
+        let mut layers = Layers::<TestImage>::new(Size::new(1, 1));
+        layers.canvas_at_mut(0).set_pixel(p, color::BLACK);
+        layers.set_mask(0, Some(TestImage::new(Size::new(1, 1), mask_color.into())));
+
+        assert_eq!(layers.visible_pixel(p).a, alpha);
+        assert_eq!(layers.get(0).masked_img().pixel(p).a, alpha);
+        assert_eq!(layers.canvas_at(0).pixel(p), color::BLACK);
+    }
+
+    #[test]
+    fn reference_layer() {
+        let p = Point::ZERO;
+        let mut layers = Layers::<TestImage>::new(Size::new(2, 2));
+        let reference = Layer::new_reference(TestImage::new(Size::new(3, 1), color::BLACK));
+        layers.add_at(0, reference);
+
+        assert_eq!(layers.active_index(), 1);
+        assert_eq!(layers.canvas_size(), Size::new(2, 2));
+        assert_eq!(layers.visible_pixel(p), TRANSPARENT);
+        assert_eq!(layers.blended().pixel(p), TRANSPARENT);
+
+        layers.switch_to(0);
+        assert_eq!(layers.active_index(), 1);
+        layers.add_new_above();
+        layers.switch_to(2);
+        layers.delete(2);
+        assert_eq!(layers.active_index(), 1);
+    }
+
+    #[test]
+    fn edit_mask() {
+        let mut layers = Layers::<TestImage>::new(Size::new(1, 1));
+        layers.set_editing_mask(true);
+        assert!(!layers.editing_mask());
+
+        layers.set_mask(0, Some(TestImage::new(Size::new(1, 1), color::WHITE)));
+        assert!(layers.editing_mask());
+        layers
+            .active_canvas_mut()
+            .set_pixel(Point::ZERO, color::BLACK);
+        assert_eq!(layers.canvas_at(0).pixel(Point::ZERO), TRANSPARENT);
+
+        layers.invert_mask(0).unwrap();
+        assert_eq!(layers.active_canvas().pixel(Point::ZERO), color::WHITE);
+    }
+
+    #[test]
+    fn layer_offset() {
+        let mut layers = Layers::<TestImage>::new(Size::new(2, 2));
+        layers.canvas_at_mut(0).set_pixel(Point::ZERO, color::BLACK);
+        layers.set_mask(0, Some(TestImage::new(Size::new(2, 2), color::WHITE)));
+        layers.set_offset(0, Point::new(-1, 0));
+
+        assert_eq!(layers.visible_pixel(Point::ZERO), TRANSPARENT);
+        assert_eq!(layers.bounds(0..1), Rect::new(-1, 0, 3, 2));
+        assert_eq!(layers.get(0).mask().unwrap().offset(), Point::new(-1, 0));
+
+        let area = layers.canvas_rect();
+        let (offset, imgs, mask) = layers.get_mut(0).cover(area).unwrap();
+        assert_eq!(offset, Point::new(-1, 0));
+        assert_eq!(imgs.len(), 1);
+        assert_eq!(imgs[0].size(), Size::new(2, 2));
+        assert!(mask.is_some());
+        assert_eq!(layers.canvas_at(0).rect(), Rect::new(-1, 0, 3, 2));
+        assert_eq!(layers.get(0).masked_pixel(Point::new(-1, 0)), color::BLACK);
+        assert_eq!(
+            layers.get(0).mask().unwrap().pixel(Point::new(1, 1)),
+            color::WHITE
+        );
+        assert!(layers.get_mut(0).cover(area).is_none());
+    }
+
+    #[test]
+    fn frames() {
+        let mut layers = Layers::<TestImage>::new(Size::new(2, 2));
+        layers.canvas_at_mut(0).set_pixel(Point::ZERO, color::BLACK);
+        let first = layers.active_frame_id();
+        let second = layers.duplicate_frame(0);
+        let third = layers.new_frame_at(2);
+        layers.add_at(1, Layer::new(Size::new(2, 2)));
+
+        assert_eq!(layers.frame_count(), 3);
+        assert_eq!(layers.active_frame(), 0);
+        assert_eq!(layers.get(1).cels.len(), 3);
+        assert_eq!(layers.blended_frame(1).pixel(Point::ZERO), color::BLACK);
+        assert_eq!(layers.blended_frame(2).pixel(Point::ZERO), TRANSPARENT);
+
+        layers.switch_frame(2);
+        layers.canvas_at_mut(1).set_pixel(Point::ZERO, color::WHITE);
+        assert_eq!(layers.get(1).cel(0).pixel(Point::ZERO), TRANSPARENT);
+        assert_eq!(layers.visible_pixel(Point::ZERO), color::WHITE);
+
+        layers.move_frame(2, 0);
+        assert_eq!(layers.active_frame(), 0);
+        assert_eq!(layers.frame_index_of(first).unwrap(), 1);
+
+        let (frame, imgs) = layers.delete_frame(0);
+        assert_eq!(frame.id(), third);
+        assert_eq!(layers.active_frame_id(), first);
+        layers.restore_frame(2, frame, imgs);
+        assert_eq!(layers.frame_index_of(third).unwrap(), 2);
+        assert_eq!(layers.frame_index_of(second).unwrap(), 1);
+        assert_eq!(layers.blended_frame(2).pixel(Point::ZERO), color::WHITE);
+    }
 }
@@ -8,10 +8,15 @@ pub mod color;
 mod error;
 mod event;
 mod floating;
+mod frame;
 pub mod graphics;
+mod group;
+mod journal;
 mod layer;
+mod onion_skin;
 mod palette;
 pub mod primitives;
+mod project;
 mod state;
 mod tool;
 mod transform;
@@ -20,14 +25,22 @@ mod util;
 use action::{Action, AtomicAction};
 pub use bitmap::Bitmap;
 pub use canvas::{Canvas, CanvasEffect};
-pub use color::Color;
+pub use color::{BlendMode, Color};
 pub use error::{Error, Result};
 pub use event::Event;
 pub use floating::FreeImage;
-pub use layer::{Layer, Layers};
+pub use frame::{
+    AnimationDirection, AnimationExport, AnimationTag, Frame, FrameId, DEFAULT_FRAME_DURATION,
+};
+pub use group::{GroupId, LayerGroup};
+pub use layer::{Layer, LayerId, Layers, Reference};
+pub use onion_skin::{Ghost, OnionSkin};
 use palette::Palette;
 pub use primitives::*;
-pub use state::{Selection, State};
+pub use project::{MAGIC, PROJECT_VERSION};
+pub use state::{
+    FrameMetadata, HistoryEntry, Selection, SpritesheetMetadata, State, DEFAULT_HISTORY_LIMIT,
+};
 pub use tool::Tool;
 pub use transform::Transform;
 pub use util::{LoadProject, SaveProject};
@@ -0,0 +1,146 @@
+use crate::{Color, Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Settings of onion skinning: showing the frames around the active one as
+/// tinted, translucent "ghosts" while drawing, to help with frame-by-frame
+/// animation. The ghosts are only meant to be displayed, and are never part of
+/// the image
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OnionSkin {
+    /// Whether the ghosts are shown
+    pub enabled: bool,
+    /// How many frames before the active one are shown
+    pub before: u8,
+    /// How many frames after the active one are shown
+    pub after: u8,
+    /// Color that the frames before the active one are multiplied by
+    pub before_tint: Color,
+    /// Color that the frames after the active one are multiplied by
+    pub after_tint: Color,
+    /// Opacity of the frames right next to the active one
+    pub opacity: u8,
+    /// How much of the opacity is kept from a ghost to the next one further
+    /// from the active frame, between 0 and 1
+    pub falloff: f32,
+}
+
+impl Default for OnionSkin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            before: 1,
+            after: 1,
+            before_tint: Color::new(255, 64, 64, 255),
+            after_tint: Color::new(64, 128, 255, 255),
+            opacity: 128,
+            falloff: 0.5,
+        }
+    }
+}
+
+/// A frame shown as a ghost by onion skinning (see [`OnionSkin`])
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ghost {
+    /// Index of the frame
+    pub frame: usize,
+    /// Color that the frame is multiplied by
+    pub tint: Color,
+    /// Opacity of the ghost
+    pub opacity: u8,
+}
+
+impl OnionSkin {
+    /// Check that the settings are valid
+    pub(crate) fn check(&self) -> Result<()> {
+        if !(0. ..=1.).contains(&self.falloff) {
+            return Err(Error::InvalidOnionSkinFalloff(self.falloff));
+        }
+
+        Ok(())
+    }
+
+    /// Get the ghosts shown around the active frame (at an index) in an
+    /// animation with a number of frames, in the order they should be drawn:
+    /// from the furthest from the active frame to the closest. There are none
+    /// if onion skinning is disabled, and it doesn't wrap around the ends of
+    /// the animation
+    pub fn ghosts(&self, active: usize, frame_count: usize) -> Vec<Ghost> {
+        if !self.enabled {
+            return Vec::new();
+        }
+
+        let before = (1..=self.before as usize)
+            .filter(|distance| *distance <= active)
+            .map(|distance| (active - distance, distance, self.before_tint));
+        let after = (1..=self.after as usize)
+            .filter(|distance| active + distance < frame_count)
+            .map(|distance| (active + distance, distance, self.after_tint));
+        let mut ghosts: Vec<_> = before
+            .chain(after)
+            .map(|(frame, distance, tint)| Ghost {
+                frame,
+                tint,
+                opacity: self.opacity_at(distance),
+            })
+            .collect();
+        ghosts.sort_by_key(|ghost| std::cmp::Reverse(ghost.frame.abs_diff(active)));
+
+        ghosts
+    }
+
+    /// Opacity of a ghost at a distance (in frames) from the active frame
+    fn opacity_at(&self, distance: usize) -> u8 {
+        let factor = self.falloff.powi(distance as i32 - 1);
+
+        (self.opacity as f32 * factor).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn onion_skin(before: u8, after: u8) -> OnionSkin {
+        OnionSkin {
+            enabled: true,
+            before,
+            after,
+            opacity: 200,
+            ..Default::default()
+        }
+    }
+
+    #[test_case(0, 5, vec![2, 1])]
+    #[test_case(2, 5, vec![0, 4, 1, 3])]
+    #[test_case(4, 5, vec![2, 3])]
+    #[test_case(0, 1, vec![])]
+    fn ghost_frames(active: usize, count: usize, expected: Vec<usize>) {
+        let ghosts = onion_skin(2, 2).ghosts(active, count);
+        let frames: Vec<_> = ghosts.iter().map(|ghost| ghost.frame).collect();
+
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn falloff() {
+        let settings = onion_skin(3, 0);
+        let ghosts = settings.ghosts(3, 4);
+        let opacities: Vec<_> = ghosts.iter().map(|ghost| ghost.opacity).collect();
+
+        assert_eq!(opacities, vec![50, 100, 200]);
+        assert!(ghosts
+            .iter()
+            .all(|ghost| ghost.tint == settings.before_tint));
+    }
+
+    #[test]
+    fn disabled() {
+        let settings = OnionSkin {
+            enabled: false,
+            ..onion_skin(2, 2)
+        };
+
+        assert!(settings.ghosts(2, 5).is_empty());
+    }
+}
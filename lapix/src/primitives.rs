@@ -194,6 +194,21 @@ impl<T: Number + Ord> Rect<T> {
             h: y1 - y,
         }
     }
+
+    /// Get the smallest rectangle containing both this one and another
+    pub fn union(self, other: Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let x1 = (self.x + self.w).max(other.x + other.w);
+        let y1 = (self.y + self.h).max(other.y + other.h);
+
+        Self {
+            x,
+            y,
+            w: x1 - x,
+            h: y1 - y,
+        }
+    }
 }
 
 impl From<Rect<u16>> for Rect<i32> {
@@ -269,6 +284,13 @@ mod tests {
         assert_eq!(r.into().clip_to(clip.into()), res.into());
     }
 
+    #[test_case((0, 0, 10, 10), (2, 2, 4, 4), (0, 0, 10, 10))]
+    #[test_case((0, 0, 2, 2), (-2, 1, 1, 3), (-2, 0, 4, 4))]
+    #[test_case((1, 1, 1, 1), (3, 0, 1, 1), (1, 0, 3, 2))]
+    fn rect_union<R: Into<Rect<i32>>>(r: R, other: R, res: R) {
+        assert_eq!(r.into().union(other.into()), res.into());
+    }
+
     #[test_case((0, 0, 1, 1), (0, 0), true)]
     #[test_case((0, 0, 2, 2), (1, 1), true)]
     #[test_case((0, 0, 1, 1), (0, 1), true)]
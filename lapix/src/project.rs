@@ -0,0 +1,263 @@
+//! The project file format. A project file consists of an optional prefix (e.g.
+//! the name and version of the program that created it), followed by
+//! [`MAGIC`], a version tag with the schema version of the project, and the
+//! project data encoded with `bincode`.
+//!
+//! Files created by Tarsila 0.1.0 have no version tag, and are considered to be
+//! version 1.
+
+use crate::{
+    Bitmap, Canvas, Color, Error, FreeImage, Layer, Layers, Palette, Rect, Result, Selection, Size,
+    State, Tool,
+};
+use serde::{Deserialize, Serialize};
+
+/// Marks the start of the project data in a project file
+pub const MAGIC: [u8; 5] = [0xfa, 0x1a, 0xfe, 0x1b, 0xee];
+
+/// Current schema version of project files. This only needs to be bumped (along
+/// with a migration from the previous layout) when the layout of a released
+/// version changes.
+pub const PROJECT_VERSION: u32 = 2;
+
+const VERSION_TAG: [u8; 4] = *b"LPXV";
+
+/// Encode a project, with its header
+pub(crate) fn encode<IMG: Serialize>(state: &State<IMG>) -> Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION_TAG);
+    bytes.extend_from_slice(&PROJECT_VERSION.to_le_bytes());
+    bytes.append(&mut bincode::serialize(state)?);
+
+    Ok(bytes)
+}
+
+/// Decode a project file, migrating it to the current version if needed
+pub(crate) fn decode<IMG>(bytes: &[u8]) -> Result<State<IMG>>
+where
+    IMG: Bitmap + Serialize + for<'de> Deserialize<'de>,
+{
+    let (version, data) = split_header(bytes)?;
+
+    match version {
+        1 => Ok(bincode::deserialize::<StateV1<IMG>>(data)?.into()),
+        PROJECT_VERSION => Ok(bincode::deserialize(data)?),
+        v => Err(Error::UnsupportedProjectVersion(v)),
+    }
+}
+
+/// Get the schema version of a project file, and the data that follows the
+/// header
+fn split_header(bytes: &[u8]) -> Result<(u32, &[u8])> {
+    let i = bytes
+        .windows(MAGIC.len())
+        .position(|win| win == MAGIC)
+        .ok_or(Error::CorruptProjectHeader)?;
+    let data = &bytes[i + MAGIC.len()..];
+
+    match data.strip_prefix(VERSION_TAG.as_slice()) {
+        Some(data) if data.len() >= 4 => {
+            let (version, data) = data.split_at(4);
+            let version = u32::from_le_bytes(version.try_into().expect("slice has 4 bytes"));
+
+            Ok((version, data))
+        }
+        Some(_) => Err(Error::CorruptProjectHeader),
+        None => Ok((1, data)),
+    }
+}
+
+// Frozen layouts of older versions of the project file. These must never change
+// (other types used here, such as `Palette` or `FreeImage`, must be frozen here
+// as well before a change to their layout).
+
+/// Layout of [`State`] in version 1
+#[derive(Deserialize)]
+pub(crate) struct StateV1<IMG> {
+    pub layers: LayersV1<IMG>,
+    pub tool: Tool,
+    pub main_color: Color,
+    pub spritesheet: Size<u8>,
+    pub palette: Palette,
+    pub selection: Option<Selection>,
+    pub free_image: Option<FreeImage<IMG>>,
+    pub clipboard: Option<IMG>,
+}
+
+/// Layout of [`Layers`] in version 1
+#[derive(Deserialize)]
+pub(crate) struct LayersV1<IMG> {
+    inner: Vec<LayerV1<IMG>>,
+    active: usize,
+}
+
+/// Layout of [`Layer`] in version 1
+#[derive(Deserialize)]
+struct LayerV1<IMG> {
+    canvas: CanvasV1<IMG>,
+    visible: bool,
+    opacity: u8,
+}
+
+/// Layout of [`Canvas`] in version 1
+///
+/// [`Canvas`]: crate::Canvas
+#[derive(Deserialize)]
+struct CanvasV1<IMG> {
+    inner: IMG,
+}
+
+impl<IMG: Bitmap> LayersV1<IMG> {
+    /// Convert the layers to the current layout. In version 1 the frames of an
+    /// animation were laid out in a grid in the image of each layer (the
+    /// spritesheet), so each cell becomes a frame (from left to right and top
+    /// to bottom), and the size of a cell becomes the canvas size
+    pub(crate) fn into_layers(self, spritesheet: Size<u8>) -> Layers<IMG> {
+        // All layers had the size of the canvas
+        let size = self
+            .inner
+            .first()
+            .map_or(Size::ZERO, |layer| layer.canvas.inner.size());
+        let (columns, rows) = (spritesheet.x.max(1) as i32, spritesheet.y.max(1) as i32);
+        let cell = Size::new(size.x / columns, size.y / rows);
+        let mut layers = Layers::new(cell);
+        for i in 1..columns * rows {
+            layers.new_frame_at(i as usize);
+        }
+
+        for (i, layer_v1) in self.inner.into_iter().enumerate() {
+            let mut canvas = Canvas::new(Size::ZERO);
+            canvas.set_img(layer_v1.canvas.inner);
+            let cels = (0..rows)
+                .flat_map(|y| (0..columns).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    canvas.img_from_area(Rect::new(x * cell.x, y * cell.y, cell.x, cell.y))
+                })
+                .collect();
+
+            let mut layer = Layer::from_cels(cels);
+            layer.set_visibility(layer_v1.visible);
+            layer.set_opacity(layer_v1.opacity);
+            layer.set_name(format!("Layer {}", i + 1));
+            layers.add_at(i, layer);
+        }
+
+        // Remove the placeholder layer created along with `Layers`
+        layers.delete(layers.count() - 1);
+        layers.switch_to(self.active);
+
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::TestImage;
+    use crate::{Event, Point};
+
+    fn legacy_file(layers: Vec<(Color, bool, u8)>, active: usize) -> Vec<u8> {
+        #[derive(Serialize)]
+        struct Legacy<'a> {
+            layers: (Vec<(TestImage, bool, u8)>, usize),
+            tool: Tool,
+            main_color: Color,
+            spritesheet: Size<u8>,
+            palette: &'a [Color],
+            selection: Option<Selection>,
+            free_image: Option<()>,
+            clipboard: Option<TestImage>,
+        }
+
+        let legacy = Legacy {
+            layers: (
+                layers
+                    .into_iter()
+                    .map(|(c, vis, alpha)| {
+                        // Paint the second frame of the spritesheet black
+                        let mut img = TestImage::new(Size::new(2, 2), c);
+                        img.set_pixel(Point::new(1, 1), crate::color::BLACK);
+                        (img, vis, alpha)
+                    })
+                    .collect(),
+                active,
+            ),
+            tool: Tool::Bucket,
+            main_color: Color::new(1, 2, 3, 4),
+            spritesheet: Size::new(2, 1),
+            palette: &[Color::new(5, 6, 7, 8)],
+            selection: None,
+            free_image: None,
+            clipboard: None,
+        };
+
+        let mut bytes = b"tarsila 0.1.0 ".to_vec();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.append(&mut bincode::serialize(&legacy).unwrap());
+
+        bytes
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let red = Color::new(255, 0, 0, 255);
+        let blue = Color::new(0, 0, 255, 255);
+        let bytes = legacy_file(vec![(red, true, 255), (blue, false, 100)], 1);
+        let state: State<TestImage> = decode(&bytes).unwrap();
+
+        assert_eq!(state.layers().count(), 2);
+        assert_eq!(state.layers().active_index(), 1);
+        assert_eq!(state.layers().frame_count(), 2);
+        assert_eq!(state.layers().canvas_size(), Size::new(1, 2));
+        assert_eq!(state.layers().canvas_at(0).pixel(Point::new(0, 1)), red);
+        assert_eq!(state.layers().canvas_at(1).pixel(Point::new(0, 1)), blue);
+        let cel = state.layers().get(1).cel(1);
+        assert_eq!(cel.pixel(Point::new(0, 0)), blue);
+        assert_eq!(cel.pixel(Point::new(0, 1)), crate::color::BLACK);
+        assert!(state.layers().get(0).visible());
+        assert!(!state.layers().get(1).visible());
+        assert_eq!(state.layers().get(1).opacity(), 100);
+        assert_eq!(state.layers().get(1).name(), "Layer 2");
+        assert_eq!(state.selected_tool(), Tool::Bucket);
+        assert_eq!(state.main_color(), Color::new(1, 2, 3, 4));
+        assert_eq!(state.spritesheet(), Size::new(2, 1));
+        assert_eq!(state.palette(), &[Color::new(5, 6, 7, 8)]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut state = State::<TestImage>::new(Size::new(3, 3), None, None);
+        state.execute(Event::Bucket(Point::ZERO)).unwrap();
+        let bytes = encode(&state).unwrap();
+        let mut decoded: State<TestImage> = decode(&bytes).unwrap();
+
+        assert_eq!(decoded.canvas().pixel(Point::ZERO), crate::color::BLACK);
+        decoded.execute(Event::Undo).unwrap();
+        assert_eq!(
+            decoded.canvas().pixel(Point::ZERO),
+            crate::color::TRANSPARENT
+        );
+    }
+
+    #[test]
+    fn missing_magic() {
+        let bytes = b"not a project".to_vec();
+
+        assert!(matches!(
+            decode::<TestImage>(&bytes),
+            Err(Error::CorruptProjectHeader)
+        ));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION_TAG);
+        bytes.extend_from_slice(&(PROJECT_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            decode::<TestImage>(&bytes),
+            Err(Error::UnsupportedProjectVersion(v)) if v == PROJECT_VERSION + 1
+        ));
+    }
+}
@@ -1,10 +1,19 @@
-use crate::color::{BLACK, TRANSPARENT};
+use crate::color::{BLACK, TRANSPARENT, WHITE};
+use crate::journal::Journal;
+use crate::project::{self, StateV1};
 use crate::util::{LoadProject, SaveProject};
 use crate::{
-    util, Action, AtomicAction, Bitmap, Canvas, CanvasEffect, Color, Error, Event, FreeImage,
-    Layers, Palette, Point, Position, Rect, Result, Size, Tool,
+    util, Action, AnimationExport, AnimationTag, AtomicAction, Bitmap, BlendMode, Canvas,
+    CanvasEffect, Color, Error, Event, FreeImage, Ghost, GroupId, Layer, Layers, OnionSkin,
+    Palette, Point, Position, Rect, Reference, Result, Size, Tool,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Default maximum number of actions kept in the undo history
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
 
 /// Represents a selection
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,6 +26,37 @@ pub enum Selection {
     FreeImage,
 }
 
+/// An action in the undo history, as returned by [`State::history`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// A human-readable description of the action, e.g. "Bucket fill"
+    pub label: String,
+    /// Whether the action is currently applied. If it isn't, it was undone and
+    /// can be redone
+    pub applied: bool,
+}
+
+/// Where the frames are in the image returned by [`State::spritesheet_img`],
+/// along with their timing and the animation tags, so that other programs can
+/// play the animations. See [`State::spritesheet_metadata`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpritesheetMetadata {
+    /// Size of the whole image
+    pub size: Size<i32>,
+    /// The frames, in order
+    pub frames: Vec<FrameMetadata>,
+    pub tags: Vec<AnimationTag>,
+}
+
+/// A frame of a [`SpritesheetMetadata`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameMetadata {
+    /// The area of the image where the frame is
+    pub rect: Rect<i32>,
+    /// For how long the frame is shown, in milliseconds
+    pub duration: u32,
+}
+
 /// The state of the image editor's core. Most importantly, this contains all
 /// the layers and images that are being drawn. This state can be modified
 /// externally mainly by sending [`Event`]s via the [`execute`] method.
@@ -34,14 +74,21 @@ pub struct State<IMG> {
     selection: Option<Selection>,
     free_image: Option<FreeImage<IMG>>,
     clipboard: Option<IMG>,
-    #[serde(skip, default = "Vec::new")]
     reversals: Vec<Action<IMG>>,
+    redos: Vec<Action<IMG>>,
+    history_limit: usize,
+    onion_skin: OnionSkin,
+    macros: Vec<Vec<Event>>,
+    #[serde(skip, default = "Option::default")]
+    recording_macro: Option<Vec<Event>>,
     #[serde(skip, default = "Option::default")]
     cur_reversal: Option<Action<IMG>>,
     #[serde(skip, default = "Option::default")]
     load_project_fn: Option<LoadProject>,
     #[serde(skip, default = "Option::default")]
     save_project_fn: Option<SaveProject>,
+    #[serde(skip, default = "Option::default")]
+    journal: Option<Journal>,
 }
 
 impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
@@ -62,19 +109,95 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             free_image: None,
             clipboard: None,
             reversals: Vec::new(),
+            redos: Vec::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            onion_skin: OnionSkin::default(),
+            macros: Vec::new(),
+            recording_macro: None,
             cur_reversal: None,
             load_project_fn,
             save_project_fn,
+            journal: None,
+        }
+    }
+
+    /// Rebuild a state from a journal recorded with
+    /// [`State::start_journal`], by loading the snapshot at its start and
+    /// executing all the events recorded after it. Events that write files
+    /// ([`Event::Save`], [`Event::SaveLayers`] and [`Event::SaveProject`]) are
+    /// not executed, and
+    /// events that read files need those files to be available. Errors
+    /// caused by the events themselves are ignored, since they also happened
+    /// (and were handled) in the recorded session.
+    pub fn replay<R: Read>(
+        mut reader: R,
+        load_project_fn: Option<LoadProject>,
+        save_project_fn: Option<SaveProject>,
+    ) -> Result<Self> {
+        let (mut state, last_event): (Self, Option<Event>) = Journal::read_snapshot(&mut reader)?;
+        state.events.extend(last_event);
+        state.load_project_fn = load_project_fn;
+        state.save_project_fn = save_project_fn;
+
+        while let Some(event) = Journal::read_event(&mut reader)? {
+            if !matches!(
+                event,
+                Event::Save(_) | Event::SaveLayers(_) | Event::SaveProject(_)
+            ) {
+                let _ = state.execute(event);
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Start recording a journal of every [`Event`] executed from now on into
+    /// the writer (see [`State::replay`]). If a journal was already being
+    /// recorded, it is stopped.
+    pub fn start_journal<W: Write + 'static>(&mut self, writer: W) -> Result<()> {
+        self.end_action();
+        self.journal = Some(Journal::start(Box::new(writer), &self, self.events.last())?);
+
+        Ok(())
+    }
+
+    /// Stop recording the journal
+    pub fn stop_journal(&mut self) {
+        if let Some(mut journal) = self.journal.take() {
+            if let Err(e) = journal.flush() {
+                eprintln!("Failed to write the journal: {e}");
+            }
         }
     }
 
-    fn start_action(&mut self) {
-        self.cur_reversal = Some(Action::default());
+    /// Whether a journal is being recorded
+    pub fn is_journaling(&self) -> bool {
+        self.journal.is_some()
     }
 
-    fn add_to_action(&mut self, actions: Vec<AtomicAction<IMG>>) -> Result<()> {
+    /// Save the project to a path using the [`SaveProject`] function, like
+    /// [`Event::SaveProject`] does, but without executing an event. This
+    /// doesn't interfere with actions in progress (e.g. a brush stroke), nor
+    /// with the journal, so it can be called at any moment (e.g. for
+    /// autosaving). An action in progress is saved as part of the canvas, but
+    /// not of the undo history.
+    pub fn save_snapshot(&self, path: PathBuf) -> Result<()> {
+        match &self.save_project_fn {
+            Some(f) => (f.0)(path, project::encode(self)?),
+            None => {
+                eprintln!("Bug: Missing save project function");
+                Ok(())
+            }
+        }
+    }
+
+    fn start_action(&mut self, label: &str) {
+        self.cur_reversal = Some(Action::new(label, Vec::new()));
+    }
+
+    fn add_to_action(&mut self, label: &str, actions: Vec<AtomicAction<IMG>>) -> Result<()> {
         if self.cur_reversal.is_none() {
-            self.start_action();
+            self.start_action(label);
         }
         self.cur_reversal
             .as_mut()
@@ -86,31 +209,156 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
 
     fn end_action(&mut self) {
         if let Some(action) = self.cur_reversal.take() {
-            self.reversals.push(action);
+            self.push_reversal(action);
         }
     }
 
     fn single_action(&mut self, action: Action<IMG>) {
         self.end_action();
+        self.push_reversal(action);
+    }
+
+    /// Register a new undoable action. Anything that was undone before cannot
+    /// be redone anymore after this.
+    fn push_reversal(&mut self, mut action: Action<IMG>) {
+        action.set_frame(self.layers.active_frame_id());
+        action.compact(&self.layers);
         self.reversals.push(action);
+        self.redos.clear();
+        self.trim_history();
     }
 
-    fn add_to_pixels_action(&mut self, actions: Vec<(Point<i32>, Color)>) -> Result<()> {
-        let actions = AtomicAction::set_pixel_vec(self.layers.active_index(), actions);
+    /// Drop the oldest actions of the undo history that go beyond the limit
+    fn trim_history(&mut self) {
+        if self.reversals.len() > self.history_limit {
+            let excess = self.reversals.len() - self.history_limit;
+            self.reversals.drain(..excess);
+        }
 
-        self.add_to_action(actions)
+        self.redos.truncate(self.history_limit);
     }
 
-    fn single_pixels_action(&mut self, actions: Vec<(Point<i32>, Color)>) {
-        let actions = AtomicAction::set_pixel_vec(self.layers.active_index(), actions);
-        self.single_action(actions.into());
+    fn add_to_pixels_action(
+        &mut self,
+        label: &str,
+        actions: Vec<(Point<i32>, Color)>,
+    ) -> Result<()> {
+        let actions = self.restore_alpha_locked_pixels(actions);
+        let actions = self.pixel_actions(actions);
+
+        self.add_to_action(label, actions)
+    }
+
+    fn single_pixels_action(&mut self, label: &str, actions: Vec<(Point<i32>, Color)>) {
+        let actions = self.restore_alpha_locked_pixels(actions);
+        let actions = self.pixel_actions(actions);
+        self.single_action(Action::new(label, actions));
+    }
+
+    /// Get the atomic actions that set pixels of the canvas being drawn on
+    /// (the active layer or its mask)
+    fn pixel_actions(&self, actions: Vec<(Point<i32>, Color)>) -> Vec<AtomicAction<IMG>> {
+        let id = self.layers.active_id();
+        match self.layers.editing_mask() {
+            true => AtomicAction::set_mask_pixel_vec(id, actions),
+            false => AtomicAction::set_pixel_vec(id, actions),
+        }
+    }
+
+    /// Get the atomic action that replaces an area of the canvas being drawn
+    /// on (the active layer or its mask)
+    fn area_action(&self, p: Point<i32>, img: IMG) -> AtomicAction<IMG> {
+        let id = self.layers.active_id();
+        match self.layers.editing_mask() {
+            true => AtomicAction::SetMaskArea(id, p, img),
+            false => AtomicAction::SetArea(id, p, img),
+        }
+    }
+
+    /// Whether the alpha lock of the active layer applies to what is being
+    /// drawn (it doesn't apply to masks)
+    fn alpha_locked(&self) -> bool {
+        self.layers.active().alpha_locked() && !self.layers.editing_mask()
+    }
+
+    /// If the alpha of the active layer is locked, restore the pixels changed
+    /// by an action that were transparent before it (given the pixels changed
+    /// and their previous colors), returning only the changes that were kept
+    fn restore_alpha_locked_pixels(
+        &mut self,
+        mut changes: Vec<(Point<i32>, Color)>,
+    ) -> Vec<(Point<i32>, Color)> {
+        if !self.alpha_locked() {
+            return changes;
+        }
+
+        // The first change to a pixel is the one holding its original color
+        let mut original = HashMap::new();
+        for (p, color) in changes.iter() {
+            original.entry(*p).or_insert(*color);
+        }
+        for (p, color) in original.iter() {
+            if color.a == 0 {
+                self.canvas_mut().set_pixel(*p, *color);
+            }
+        }
+        changes.retain(|(p, _)| original[p].a != 0);
+
+        changes
+    }
+
+    /// Register an action that changed an area of the active layer, given its
+    /// position and previous contents. Nothing is registered if there was no
+    /// change.
+    fn single_area_action(&mut self, label: &str, reversal: Option<(Point<i32>, IMG)>) {
+        if let Some((p, img)) = reversal {
+            if self.alpha_locked() && !self.restore_alpha_locked_area(p, &img) {
+                return;
+            }
+            let action = self.area_action(p, img);
+            self.single_action(Action::new(label, vec![action]));
+        }
+    }
+
+    /// Restore the pixels of an area of the active layer that were transparent
+    /// before it changed, given the previous contents of the area. Returns
+    /// whether any change to the area was kept
+    fn restore_alpha_locked_area(&mut self, pos: Point<i32>, previous: &IMG) -> bool {
+        let mut changed = false;
+
+        for i in 0..previous.width() {
+            for j in 0..previous.height() {
+                let p = Point::new(i, j);
+                let color = previous.pixel(p);
+                if color.a == 0 {
+                    self.canvas_mut().set_pixel(p + pos, color);
+                } else if self.canvas().pixel(p + pos) != color {
+                    changed = true;
+                }
+            }
+        }
+
+        changed
     }
 
     /// Execute an [`Event`]. This is the main way of changing the editor's
     /// state, and probably the most central method of this library. A
     /// [`CanvasEffect`] is returned to communicate to the caller what kind of
-    /// visual updates must be made.
+    /// visual updates must be made. If the event can't be recorded in the
+    /// journal, the journal is stopped (see [`State::is_journaling`]), but the
+    /// event is still executed.
     pub fn execute(&mut self, event: Event) -> Result<CanvasEffect> {
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.record(&event) {
+                eprintln!("Failed to write the journal, so it was stopped: {e}");
+                self.journal = None;
+            }
+        }
+
+        self.execute_event(event)
+    }
+
+    fn execute_event(&mut self, event: Event) -> Result<CanvasEffect> {
         if let Some(prev_event) = self.events.last() {
             if (prev_event == &event && !event.repeatable())
                 || (event.same_variant(prev_event) && !event.type_repeatable())
@@ -126,29 +374,33 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             self.anchor()?;
         }
 
+        if event.draws_on_canvas() && self.layers.active().locked() {
+            return Err(Error::LayerLocked(self.layers.active_id()));
+        }
+
         let mut skip_event = false;
+        let label = event.label();
 
         match event.clone() {
             Event::ClearCanvas => {
+                let id = self.layers.active_id();
                 let img = self.canvas_mut().clear();
-                let reversal = AtomicAction::SetLayerCanvas(self.layers.active_index(), img);
-                self.start_action();
-                self.add_to_action(vec![reversal])?;
+                let reversal = match self.layers.editing_mask() {
+                    true => AtomicAction::SetLayerMask(id, Some(img)),
+                    false => AtomicAction::SetLayerCanvas(id, img),
+                };
+                self.start_action(label);
+                self.add_to_action(label, vec![reversal])?;
                 self.end_action();
             }
             Event::ResizeCanvas(size) => {
-                self.start_action();
-                let imgs = self.resize_canvas(size);
-                self.add_to_action(
-                    imgs.into_iter()
-                        .enumerate()
-                        .map(|(i, img)| AtomicAction::SetLayerCanvas(i, img))
-                        .collect(),
-                )?;
+                self.start_action(label);
+                let reversals = self.resize_canvas(size);
+                self.add_to_action(label, reversals)?;
                 self.end_action();
             }
             Event::LineStart(_) | Event::RectStart(_) => (),
-            Event::BrushStart | Event::EraseStart => self.start_action(),
+            Event::BrushStart | Event::EraseStart => self.start_action(label),
             Event::BrushEnd | Event::EraseEnd => self.end_action(),
             Event::LineEnd(p) => {
                 let last_event = self.events.last();
@@ -158,7 +410,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
                 };
                 let color = self.main_color;
                 let reversals = self.canvas_mut().line(p0, p, color);
-                self.single_pixels_action(reversals);
+                self.single_pixels_action(label, reversals);
                 self.free_image = None;
             }
             Event::RectEnd(p) => {
@@ -169,7 +421,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
                 };
                 let color = self.main_color;
                 let reversals = self.canvas_mut().rectangle(p0, p, color);
-                self.single_pixels_action(reversals);
+                self.single_pixels_action(label, reversals);
                 self.free_image = None;
             }
             Event::BrushStroke(p) => {
@@ -187,7 +439,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
                     }
                     _ => Vec::new(),
                 };
-                self.add_to_pixels_action(reversals)?;
+                self.add_to_pixels_action(label, reversals)?;
             }
             Event::Erase(p) => {
                 let last_event = self.events.last();
@@ -204,28 +456,31 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
                         .collect(),
                     _ => Vec::new(),
                 };
-                self.add_to_pixels_action(reversals)?;
+                self.add_to_pixels_action(label, reversals)?;
             }
             Event::SetTool(tool) => self.tool = tool,
             Event::SetMainColor(color) => self.main_color = color,
             Event::Save(path) => self.save_image(path.to_string_lossy().as_ref())?,
+            Event::SaveLayers(dir) => self.save_layers(&dir)?,
+            Event::ExportAnimation(path, settings) => self.save_animation(&path, &settings)?,
             Event::OpenFile(path) => self.import_image(path.to_string_lossy().as_ref())?,
             Event::SaveProject(path) => {
+                self.end_action();
                 if let Some(f) = &self.save_project_fn {
-                    let bytes = bincode::serialize(&self)?;
-                    (f.0)(path, bytes);
+                    let bytes = project::encode(self)?;
+                    (f.0)(path, bytes)?;
                 } else {
                     eprintln!("Bug: Missing save project function");
                 }
             }
             Event::LoadProject(path) => {
                 if let Some(f) = &self.load_project_fn {
-                    let bytes = (f.0)(path);
-                    let (save_fn, load_fn) =
-                        (self.save_project_fn.take(), self.load_project_fn.take());
-                    *self = bincode::deserialize(&bytes)?;
-                    self.save_project_fn = save_fn;
-                    self.load_project_fn = load_fn;
+                    let bytes = (f.0)(path)?;
+                    let mut loaded = project::decode(&bytes)?;
+                    loaded.save_project_fn = self.save_project_fn.take();
+                    loaded.load_project_fn = self.load_project_fn.take();
+                    loaded.journal = self.journal.take();
+                    *self = loaded;
                 } else {
                     eprintln!("Bug: Missing load project function");
                 }
@@ -238,8 +493,8 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             Event::Bucket(p) => {
                 if self.canvas().is_in_bounds(p) {
                     let color = self.main_color;
-                    let reversals = self.canvas_mut().bucket(p, color);
-                    self.single_pixels_action(reversals);
+                    let reversal = self.canvas_mut().bucket(p, color);
+                    self.single_area_action(label, reversal);
                 }
             }
             Event::ClearSelection => (),
@@ -251,7 +506,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
                     let size = p.abs_diff(*p0);
                     let corner = p.rect_min_corner(*p0);
                     let rect = Rect::new(corner.x, corner.y, size.x + 1, size.y + 1);
-                    let r = rect.clip_to(self.canvas().rect());
+                    let r = rect.clip_to(self.selectable_rect());
                     self.set_selection(Some(Selection::Canvas(r)))?;
                 }
             }
@@ -272,8 +527,8 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             },
             Event::DeleteSelection => match self.selection {
                 Some(Selection::Canvas(rect)) => {
-                    let reversals = self.canvas_mut().set_area(rect, TRANSPARENT);
-                    self.single_pixels_action(reversals);
+                    let reversal = self.canvas_mut().set_area(rect, TRANSPARENT);
+                    self.single_area_action(label, reversal);
                 }
                 Some(Selection::FreeImage) => {
                     self.free_image = None;
@@ -283,7 +538,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             },
             Event::MoveStart(p) => match self.selection {
                 Some(Selection::Canvas(_)) => {
-                    self.free_image_from_selection(Some(p));
+                    self.free_image_from_selection(label, Some(p));
                 }
                 Some(Selection::FreeImage) => {
                     if let Some(free_image) = self.free_image.as_mut() {
@@ -310,7 +565,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             }
             Event::FlipHorizontal => {
                 if let Some(Selection::Canvas(_)) = self.selection {
-                    self.free_image_from_selection(None);
+                    self.free_image_from_selection(label, None);
                 }
                 if let Some(free_img) = self.free_image.as_mut() {
                     free_img.flip_horizontally();
@@ -318,7 +573,7 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             }
             Event::FlipVertical => {
                 if let Some(Selection::Canvas(_)) = self.selection {
-                    self.free_image_from_selection(None);
+                    self.free_image_from_selection(label, None);
                 }
                 if let Some(free_img) = self.free_image.as_mut() {
                     free_img.flip_vertically();
@@ -326,50 +581,246 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             }
             Event::ApplyTransform(t) => {
                 if let Some(Selection::Canvas(_)) = self.selection {
-                    self.free_image_from_selection(None);
+                    self.free_image_from_selection(label, None);
                 }
                 if let Some(free_img) = self.free_image.as_mut() {
                     t.apply(&mut free_img.texture);
                 }
             }
             Event::NewLayerAbove => {
-                self.layers.add_new_above();
-                self.end_action();
-                self.cur_reversal = Some(Action::default());
-                let i = self.layers.count() - 1;
-                self.cur_reversal
-                    .as_mut()
-                    .ok_or(Error::ReversalNotSet)?
-                    .push(AtomicAction::DestroyLayer(i));
-                self.end_action();
+                let id = self.layers.add_new_above();
+                self.single_action(Action::new(label, vec![AtomicAction::DestroyLayer(id)]));
             }
             Event::NewLayerBelow => todo!(),
-            Event::SwitchLayer(i) => self.layers.switch_to(i),
-            Event::ChangeLayerVisibility(i, visible) => self.layers.set_visibility(i, visible),
-            Event::ChangeLayerOpacity(i, alpha) => self.layers.set_opacity(i, alpha),
-            // TODO: this should not only remove it, as we need to be able to
-            // undo this
-            Event::DeleteLayer(i) => {
-                let img = self.layers.delete(i);
-                self.end_action();
-                self.cur_reversal = Some(Action::default());
-                self.cur_reversal
-                    .as_mut()
-                    .ok_or(Error::ReversalNotSet)?
-                    .push(AtomicAction::CreateLayer(i, img));
+            Event::SwitchLayer(id) => self.layers.switch_to(self.layers.index_of(id)?),
+            Event::ChangeLayerVisibility(id, visible) => self
+                .layers
+                .set_visibility(self.layers.index_of(id)?, visible),
+            Event::ChangeLayerOpacity(id, alpha) => {
+                self.layers.set_opacity(self.layers.index_of(id)?, alpha)
+            }
+            Event::SetLayerLock(id, locked) => {
+                self.layers.set_locked(self.layers.index_of(id)?, locked)
+            }
+            Event::SetLayerAlphaLock(id, locked) => self
+                .layers
+                .set_alpha_locked(self.layers.index_of(id)?, locked),
+            Event::RenameLayer(id, name) => {
+                let old = self.layers.rename(self.layers.index_of(id)?, name.clone());
+                if old != name {
+                    let reversal = AtomicAction::SetLayerName(id, old);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::SetLayerMetadata(id, key, value) => {
+                let i = self.layers.index_of(id)?;
+                let old = self.layers.set_metadata(i, key.clone(), value.clone())?;
+                if old != value {
+                    let reversal = AtomicAction::SetLayerMetadata(id, key, old);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::ChangeLayerBlendMode(id, mode) => {
+                let old = self.layers.set_blend_mode(self.layers.index_of(id)?, mode);
+                if old != mode {
+                    let reversal = AtomicAction::SetLayerBlendMode(id, old);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::DeleteLayer(id) => self.delete_layer(label, self.layers.index_of(id)?),
+            Event::DuplicateLayer(id) => {
+                let i = self.layers.index_of(id)?;
+                let layer = self.layers.get(i).duplicate();
+                let new_id = self.layers.add_at(i + 1, layer);
+                self.layers.switch_to(i + 1);
+                let reversal = AtomicAction::DestroyLayer(new_id);
+                self.single_action(Action::new(label, vec![reversal]));
+            }
+            Event::SelectionToNewLayer => self.selection_to_new_layer(label)?,
+            Event::AddLayerMask(id) => {
+                let i = self.layers.index_of(id)?;
+                if self.layers.get(i).mask().is_none() {
+                    let mask = IMG::new(self.layers.canvas_at(i).size(), WHITE);
+                    self.layers.set_mask(i, Some(mask));
+                    let reversal = AtomicAction::SetLayerMask(id, None);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::RemoveLayerMask(id) => {
+                let mask = self.layers.set_mask(self.layers.index_of(id)?, None);
+                let mask = mask.ok_or(Error::MissingLayerMask(id))?;
+                let reversal = AtomicAction::SetLayerMask(id, Some(mask));
+                self.single_action(Action::new(label, vec![reversal]));
+            }
+            Event::ApplyLayerMask(id) => {
+                let i = self.layers.index_of(id)?;
+                let img = self.layers.get(i).masked_img();
+                let mask = self.layers.set_mask(i, None);
+                let mask = mask.ok_or(Error::MissingLayerMask(id))?;
+                let old = self.layers.canvas_at_mut(i).take_inner();
+                self.layers.canvas_at_mut(i).set_img(img);
+                let reversals = vec![
+                    AtomicAction::SetLayerMask(id, Some(mask)),
+                    AtomicAction::SetLayerCanvas(id, old),
+                ];
+                self.single_action(Action::new(label, reversals));
+            }
+            Event::InvertLayerMask(id) => {
+                let i = self.layers.index_of(id)?;
+                let mask = self.layers.get(i).mask().map(|mask| mask.inner().clone());
+                self.layers.invert_mask(i)?;
+                let reversal = AtomicAction::SetLayerMask(id, mask);
+                self.single_action(Action::new(label, vec![reversal]));
+            }
+            Event::EditLayerMask(editing) => self.layers.set_editing_mask(editing),
+            Event::ImportReference(path) => {
+                let img = util::load_img_from_file(path.to_string_lossy().as_ref())?;
+                let mut layer = Layer::new_reference(util::img_from_raw(img));
+                if let Some(name) = path.file_stem() {
+                    layer.set_name(name.to_string_lossy());
+                }
+                let id = self.layers.add_at(self.layers.count(), layer);
+                self.single_action(Action::new(label, vec![AtomicAction::DestroyLayer(id)]));
+            }
+            Event::SetReferenceOffset(id, offset) => {
+                let i = self.layers.index_of(id)?;
+                let reference = self.layers.get(i).reference();
+                let scale = reference.ok_or(Error::NotAReferenceLayer(id))?.scale();
+                self.layers
+                    .set_reference(i, Reference::new(offset, scale))?;
+            }
+            Event::SetReferenceScale(id, scale) => {
+                if scale.is_nan() || scale <= 0. {
+                    return Err(Error::InvalidReferenceScale(scale));
+                }
+                let i = self.layers.index_of(id)?;
+                let reference = self.layers.get(i).reference();
+                let offset = reference.ok_or(Error::NotAReferenceLayer(id))?.offset();
+                self.layers
+                    .set_reference(i, Reference::new(offset, scale))?;
+            }
+            Event::ShiftLayer(id, delta) => {
+                self.shift_layer(label, self.layers.index_of(id)?, delta)?
+            }
+            Event::MergeLayerDown(id) => self.merge_layer_down(label, self.layers.index_of(id)?)?,
+            Event::FlattenImage => self.flatten_image(label),
+            Event::MoveLayerDown(id) => self.layers.move_layer_down(self.layers.index_of(id)?),
+            Event::MoveLayerUp(id) => self.layers.move_layer_up(self.layers.index_of(id)?),
+            Event::GroupLayers(first, last) => {
+                let range = self.layers.index_of(first)?..=self.layers.index_of(last)?;
+                self.change_groups(label, |layers| layers.group_layers(range).map(|_| ()))?
+            }
+            Event::Ungroup(id) => self.change_groups(label, |layers| layers.ungroup(id))?,
+            Event::ChangeGroupVisibility(id, visible) => {
+                self.layers.set_group_visibility(id, visible)?
+            }
+            Event::ChangeGroupOpacity(id, alpha) => self.layers.set_group_opacity(id, alpha)?,
+            Event::SetGroupCollapsed(id, collapsed) => {
+                self.layers.set_group_collapsed(id, collapsed)?
+            }
+            Event::DeleteGroup(id) => self.delete_group(label, id)?,
+            Event::MoveGroupDown(id) => self.layers.move_group_down(id)?,
+            Event::MoveGroupUp(id) => self.layers.move_group_up(id)?,
+            Event::AddFrame => self.new_frame(label, self.layers.frame_count())?,
+            Event::InsertFrame(index) => self.new_frame(label, index)?,
+            Event::DuplicateFrame(id) => {
+                let i = self.layers.frame_index_of(id)?;
+                let new_id = self.layers.duplicate_frame(i);
+                self.layers.switch_frame(i + 1);
+                let reversal = AtomicAction::DestroyFrame(new_id);
+                self.single_action(Action::new(label, vec![reversal]));
+            }
+            Event::DeleteFrame(id) => {
+                let i = self.layers.frame_index_of(id)?;
+                if self.layers.frame_count() > 1 {
+                    // Tags that only had this frame are deleted along with it
+                    let tags = self.layers.tags().to_vec();
+                    let (frame, imgs) = self.layers.delete_frame(i);
+                    let reversals = vec![
+                        AtomicAction::SetAnimationTags(tags),
+                        AtomicAction::CreateFrame(i, frame, imgs),
+                    ];
+                    self.single_action(Action::new(label, reversals));
+                }
+            }
+            Event::MoveFrame(id, index) => {
+                let from = self.layers.frame_index_of(id)?;
+                let count = self.layers.frame_count();
+                if index >= count {
+                    return Err(Error::InvalidFrameIndex(index, count));
+                }
+                if from != index {
+                    self.layers.move_frame(from, index);
+                    let reversal = AtomicAction::MoveFrame(id, from);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::SwitchFrame(id) => {
+                // A stroke in progress belongs to the frame it was drawn on
                 self.end_action();
+                self.layers.switch_frame(self.layers.frame_index_of(id)?);
+            }
+            Event::SetFrameDuration(id, duration) => {
+                if duration == 0 {
+                    return Err(Error::InvalidFrameDuration(duration));
+                }
+                let i = self.layers.frame_index_of(id)?;
+                let old = self.layers.set_frame_duration(i, duration);
+                if old != duration {
+                    let reversal = AtomicAction::SetFrameDuration(id, old);
+                    self.single_action(Action::new(label, vec![reversal]));
+                }
+            }
+            Event::AddAnimationTag(tag) => {
+                self.layers.check_tag(&tag)?;
+                let mut tags = self.layers.tags().to_vec();
+                tags.push(tag);
+                self.set_animation_tags(label, tags);
+            }
+            Event::SetAnimationTag(index, tag) => {
+                self.layers.check_tag(&tag)?;
+                let mut tags = self.layers.tags().to_vec();
+                *tags
+                    .get_mut(index)
+                    .ok_or(Error::MissingAnimationTag(index))? = tag;
+                self.set_animation_tags(label, tags);
+            }
+            Event::DeleteAnimationTag(index) => {
+                let mut tags = self.layers.tags().to_vec();
+                if index >= tags.len() {
+                    return Err(Error::MissingAnimationTag(index));
+                }
+                tags.remove(index);
+                self.set_animation_tags(label, tags);
+            }
+            Event::SetSpritesheet(size) => self.set_spritesheet(size)?,
+            Event::SetOnionSkin(settings) => {
+                settings.check()?;
+                self.onion_skin = settings;
+            }
+            Event::SetHistoryLimit(limit) => {
+                self.history_limit = limit;
+                self.trim_history();
             }
-            Event::MoveLayerDown(i) => self.layers.swap(i, i - 1),
-            Event::MoveLayerUp(i) => self.layers.swap(i, i + 1),
-            Event::SetSpritesheet(size) => self.set_spritesheet(size),
             Event::Undo => {
                 // TODO: we should add UNDO to the events list
                 #[allow(unused_must_use)]
                 {
                     dbg!(t0.elapsed());
                 }
-                return Ok(self.undo());
+                return self.undo();
             }
+            Event::Redo => return self.redo(),
+            Event::JumpToHistory(position) => return self.jump_to_history(position),
+            Event::StartMacro => self.recording_macro = Some(Vec::new()),
+            Event::StopMacro => {
+                if let Some(events) = self.recording_macro.take() {
+                    if !events.is_empty() {
+                        self.macros.push(events);
+                    }
+                }
+            }
+            Event::PlayMacro(i, offset) => self.play_macro(label, i, offset)?,
         }
 
         if event.clears_selection() {
@@ -386,14 +837,279 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
             Ok(CanvasEffect::None)
         } else {
             let effect = event.canvas_effect();
+            if let Some(events) = self.recording_macro.as_mut() {
+                if event.recordable() {
+                    events.push(event.clone());
+                }
+            }
             self.events.push(event);
 
             Ok(effect)
         }
     }
 
-    fn resize_canvas(&mut self, size: Size<i32>) -> Vec<IMG> {
-        self.layers.resize_all(size)
+    /// Resize the canvas, growing the layers that don't cover all of it
+    /// anymore. Returns the atomic actions that reverse it
+    fn resize_canvas(&mut self, size: Size<i32>) -> Vec<AtomicAction<IMG>> {
+        let old = self.layers.set_canvas_size(size);
+        let mut reversals = vec![AtomicAction::SetCanvasSize(old)];
+        for i in 0..self.layers.count() {
+            reversals.append(&mut self.cover_canvas(i));
+        }
+
+        reversals
+    }
+
+    /// Delete the layer at index, unless it's the last one that is not a
+    /// reference layer
+    fn delete_layer(&mut self, label: &str, index: usize) {
+        if !self.keeps_drawing_layer(index..=index) {
+            return;
+        }
+
+        let groups = self.layers.groups().to_vec();
+        let layer = self.layers.delete(index);
+        let mut reversals = vec![AtomicAction::CreateLayer(index, layer)];
+        if self.layers.prune_groups() {
+            reversals.push(AtomicAction::SetGroups(groups));
+        }
+        self.single_action(Action::new(label, reversals));
+    }
+
+    /// Move the layer at index by an offset, growing it to keep covering the
+    /// canvas. Reference layers are left alone
+    fn shift_layer(&mut self, label: &str, index: usize, delta: Point<i32>) -> Result<()> {
+        let layer = self.layers.get(index);
+        if layer.reference().is_some() {
+            return Ok(());
+        }
+        if layer.locked() {
+            return Err(Error::LayerLocked(layer.id()));
+        }
+
+        let (id, offset) = (layer.id(), layer.offset());
+        self.layers.set_offset(index, offset + delta);
+        let mut reversals = vec![AtomicAction::SetLayerOffset(id, offset)];
+        reversals.append(&mut self.cover_canvas(index));
+        self.single_action(Action::new(label, reversals));
+
+        Ok(())
+    }
+
+    /// Replace all animation tags, recording the change if there was one
+    fn set_animation_tags(&mut self, label: &str, tags: Vec<AnimationTag>) {
+        if tags.as_slice() != self.layers.tags() {
+            let old = self.layers.set_tags(tags);
+            let reversal = AtomicAction::SetAnimationTags(old);
+            self.single_action(Action::new(label, vec![reversal]));
+        }
+    }
+
+    /// Insert a new empty frame at an index, making it the active frame
+    fn new_frame(&mut self, label: &str, index: usize) -> Result<()> {
+        let count = self.layers.frame_count();
+        if index > count {
+            return Err(Error::InvalidFrameIndex(index, count));
+        }
+
+        let id = self.layers.new_frame_at(index);
+        self.layers.switch_frame(index);
+        self.single_action(Action::new(label, vec![AtomicAction::DestroyFrame(id)]));
+
+        Ok(())
+    }
+
+    /// Grow the layer at index so that it covers the whole canvas, if needed,
+    /// returning the atomic actions that reverse it
+    fn cover_canvas(&mut self, index: usize) -> Vec<AtomicAction<IMG>> {
+        let area = self.layers.canvas_rect();
+        let id = self.layers.get(index).id();
+        match self.layers.get_mut(index).cover(area) {
+            // When undoing, the mask is restored first, so that moving the
+            // layer back moves it too
+            Some((offset, imgs, mask)) => {
+                let mut reversals = vec![
+                    AtomicAction::SetLayerOffset(id, offset),
+                    AtomicAction::SetLayerCels(id, imgs),
+                ];
+                if mask.is_some() {
+                    reversals.push(AtomicAction::SetLayerMask(id, mask));
+                }
+                reversals
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Replace the layers in a range of indices by a single layer with all of
+    /// them blended together (in every frame), big enough to keep what is out
+    /// of the canvas. Reference layers are left out of it, and stay in place
+    fn merge_layers(&mut self, label: &str, range: std::ops::Range<usize>) {
+        let groups = self.layers.groups().to_vec();
+        let merged_layers: Vec<_> = range
+            .clone()
+            .filter(|i| self.layers.get(*i).reference().is_none())
+            .collect();
+        let bottom = match merged_layers.first() {
+            Some(i) => self.layers.get(*i),
+            None => return,
+        };
+        let bounds = self.layers.bounds(range.clone());
+        let imgs = (0..self.layers.frame_count())
+            .map(|frame| {
+                self.layers
+                    .blended_frame_range_area(frame, range.clone(), bounds)
+            })
+            .collect();
+        let mut merged = Layer::from_cels(imgs);
+        merged.set_offset(bounds.pos());
+        merged.set_group(self.layers.common_group(range.clone()));
+        merged.set_name(bottom.name());
+        for (key, value) in bottom.metadata().clone() {
+            merged
+                .set_metadata(key, Some(value))
+                .expect("metadata keys are validated before being stored");
+        }
+        let top = merged_layers[merged_layers.len() - 1];
+        let id = self.layers.add_at(top + 1, merged);
+
+        // The layers are recreated from the bottom up when undoing, and only
+        // then the merged layer (now on top of them) is destroyed
+        let mut reversals = vec![AtomicAction::DestroyLayer(id)];
+        for i in merged_layers.iter().rev() {
+            reversals.push(AtomicAction::CreateLayer(*i, self.layers.delete(*i)));
+        }
+        if self.layers.prune_groups() {
+            reversals.insert(0, AtomicAction::SetGroups(groups));
+        }
+
+        self.layers.switch_to(top + 1 - merged_layers.len());
+        self.single_action(Action::new(label, reversals));
+    }
+
+    /// Merge a layer into the one below it, unless any of them is a reference
+    /// layer. The merged layer is blended normally, so if any of them has
+    /// another blend mode, there can't be anything visible below them
+    fn merge_layer_down(&mut self, label: &str, index: usize) -> Result<()> {
+        if index == 0
+            || index >= self.layers.count()
+            || self.layers.get(index - 1).reference().is_some()
+            || self.layers.get(index).reference().is_some()
+        {
+            return Ok(());
+        }
+
+        let visible_below = (0..index - 1).any(|i| {
+            self.layers.get(i).reference().is_none() && self.layers.effective_visibility(i)
+        });
+        let blended = (index - 1..=index).find(|i| {
+            self.layers.get(*i).blend_mode() != BlendMode::Normal
+                && self.layers.effective_visibility(*i)
+        });
+        if let (true, Some(i)) = (visible_below, blended) {
+            return Err(Error::MergeBlendMode(self.layers.get(i).id()));
+        }
+        self.merge_layers(label, index - 1..index + 1);
+
+        Ok(())
+    }
+
+    /// Get the area that can be selected: the canvas and the active layer's
+    /// image, which can be partly outside of it
+    fn selectable_rect(&self) -> Rect<i32> {
+        self.layers.canvas_rect().union(self.canvas().rect())
+    }
+
+    /// Whether there would be any layer that is not a reference left after
+    /// deleting the layers in a range of indices
+    fn keeps_drawing_layer(&self, range: std::ops::RangeInclusive<usize>) -> bool {
+        (0..self.layers.count())
+            .any(|i| !range.contains(&i) && self.layers.get(i).reference().is_none())
+    }
+
+    fn flatten_image(&mut self, label: &str) {
+        let count = self.layers.count();
+        self.merge_layers(label, 0..count);
+    }
+
+    /// Move the selected area of the active layer (or the free image) into a
+    /// new layer right above it, which becomes the active layer
+    fn selection_to_new_layer(&mut self, label: &str) -> Result<()> {
+        let index = self.layers.active_index();
+        let mut reversals = Vec::new();
+
+        let free_image = match self.selection {
+            Some(Selection::Canvas(rect)) => {
+                let free_image = FreeImage::from_canvas_area(self.canvas(), rect, None);
+                // With the alpha locked the pixels are copied but left in place
+                if !self.alpha_locked() {
+                    if let Some((p, img)) = self.canvas_mut().set_area(rect, TRANSPARENT) {
+                        reversals.push(self.area_action(p, img));
+                    }
+                }
+                free_image
+            }
+            Some(Selection::FreeImage) => self.free_image.take().ok_or(Error::MissingFreeImage)?,
+            None => return Ok(()),
+        };
+
+        // The image only goes to the cel of the active frame
+        let mut layer = self.layers.new_layer();
+        layer.set_group(self.layers.active().group());
+        let id = self.layers.add_at(index + 1, layer);
+        self.layers.canvas_at_mut(index + 1).paste_obj(&free_image);
+        self.layers.switch_to(index + 1);
+
+        // The new layer is destroyed before the area is restored when undoing
+        reversals.push(AtomicAction::DestroyLayer(id));
+        self.single_action(Action::new(label, reversals));
+        self.set_selection(Some(Selection::Canvas(
+            free_image.rect.clip_to(self.layers.canvas_rect()),
+        )))
+    }
+
+    /// Delete a group along with all layers inside it, unless these are all
+    /// the layers there are
+    fn delete_group(&mut self, label: &str, id: GroupId) -> Result<()> {
+        self.layers.group(id)?;
+        let range = match self.layers.group_range(id) {
+            Some(range) if self.keeps_drawing_layer(range.clone()) => range,
+            _ => return Ok(()),
+        };
+
+        // The layers are recreated from the bottom up when undoing
+        let mut reversals = vec![AtomicAction::SetGroups(self.layers.groups().to_vec())];
+        for i in range.rev() {
+            reversals.push(AtomicAction::CreateLayer(i, self.layers.delete(i)));
+        }
+        self.layers.prune_groups();
+        self.single_action(Action::new(label, reversals));
+
+        Ok(())
+    }
+
+    /// Change the structure of the layer groups (without adding or removing
+    /// layers), registering the action that reverses it
+    fn change_groups<F>(&mut self, label: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Layers<IMG>) -> Result<()>,
+    {
+        let groups = self.layers.groups().to_vec();
+        let layer_groups: Vec<_> = (0..self.layers.count())
+            .map(|i| self.layers.get(i).group())
+            .collect();
+        f(&mut self.layers)?;
+
+        let mut reversals = vec![AtomicAction::SetGroups(groups)];
+        for (i, group) in layer_groups.into_iter().enumerate() {
+            let layer = self.layers.get(i);
+            if layer.group() != group {
+                reversals.push(AtomicAction::SetLayerGroup(layer.id(), group));
+            }
+        }
+        self.single_action(Action::new(label, reversals));
+
+        Ok(())
     }
 
     /// Get a mutable reference to the active [`Layer`]'s [`Canvas`]
@@ -426,23 +1142,124 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
         self.main_color
     }
 
-    /// Get the spritesheet dimensions (number of horizontal and vertical
-    /// frames). For a static image (not an animation) it will be `(1, 1)`.
+    /// Get the layout of exported images (number of columns and rows of
+    /// frames), which is `(1, 1)` by default. More rows are used if the frames
+    /// don't fit (see [`State::spritesheet_img`])
     pub fn spritesheet(&self) -> Size<u8> {
         self.spritesheet
     }
 
-    /// Set the spritesheet dimensions (number of horizontal and vertical
-    /// frames). For a static image (not an animation) it will be `(1, 1)`.
-    fn set_spritesheet(&mut self, size: Size<u8>) {
-        if self.canvas().width() % size.x as i32 != 0 || self.canvas().height() % size.y as i32 != 0
-        {
-            // TODO: relax this requirement
-            eprintln!("WARN: Canvas size should be a multiple of the spritesheet size");
-            return;
+    /// Set the layout of exported images (number of columns and rows of
+    /// frames). There must be at least one column and row
+    fn set_spritesheet(&mut self, size: Size<u8>) -> Result<()> {
+        if size.x == 0 || size.y == 0 {
+            return Err(Error::InvalidSpritesheet(size));
         }
-
         self.spritesheet = size;
+
+        Ok(())
+    }
+
+    /// Get an image with all the frames blended (see [`Layers::blended_frame`])
+    /// and placed in a grid, from left to right and top to bottom, with the
+    /// number of columns of [`State::spritesheet`]. It has as many rows as the
+    /// spritesheet, or more if the frames don't fit. This is the image that is
+    /// exported
+    pub fn spritesheet_img(&self) -> IMG {
+        let (size, rects) = self.spritesheet_layout();
+        let mut sheet = Canvas::new(size);
+
+        for (frame, rect) in rects.into_iter().enumerate() {
+            sheet.replace_area(rect.pos(), &self.layers.blended_frame(frame));
+        }
+
+        sheet.take_inner()
+    }
+
+    /// Get the layout of [`State::spritesheet_img`] along with the durations of
+    /// the frames and the animation tags
+    pub fn spritesheet_metadata(&self) -> SpritesheetMetadata {
+        let (size, rects) = self.spritesheet_layout();
+        let frames = rects
+            .into_iter()
+            .zip(self.layers.frames())
+            .map(|(rect, frame)| FrameMetadata {
+                rect,
+                duration: frame.duration(),
+            })
+            .collect();
+
+        SpritesheetMetadata {
+            size,
+            frames,
+            tags: self.layers.tags().to_vec(),
+        }
+    }
+
+    /// The size of the spritesheet image and the area of each frame in it
+    fn spritesheet_layout(&self) -> (Size<i32>, Vec<Rect<i32>>) {
+        let size = self.layers.canvas_size();
+        let frames = self.layers.frame_count();
+        let columns = self.spritesheet.x as usize;
+        let rows = frames.div_ceil(columns).max(self.spritesheet.y as usize);
+        let rects = (0..frames)
+            .map(|frame| {
+                let x = (frame % columns) as i32 * size.x;
+                let y = (frame / columns) as i32 * size.y;
+                Rect::new(x, y, size.x, size.y)
+            })
+            .collect();
+
+        (
+            Size::new(size.x * columns as i32, size.y * rows as i32),
+            rects,
+        )
+    }
+
+    /// Get the settings of onion skinning
+    pub fn onion_skin(&self) -> OnionSkin {
+        self.onion_skin
+    }
+
+    /// Get the frames to show as ghosts around the active frame, according to
+    /// the onion skin settings (see [`OnionSkin::ghosts`]). Their images can
+    /// be obtained with [`Layers::blended_frame`]
+    pub fn onion_skin_ghosts(&self) -> Vec<Ghost> {
+        self.onion_skin
+            .ghosts(self.layers.active_frame(), self.layers.frame_count())
+    }
+
+    /// Get the maximum number of actions kept in the undo history. This is
+    /// also how many steps back can be undone after loading a project.
+    pub fn history_limit(&self) -> usize {
+        self.history_limit
+    }
+
+    /// Get the undo history, from the oldest action to the newest one. The
+    /// actions that were undone (and can still be redone) come last, with
+    /// [`HistoryEntry::applied`] set to `false`.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        let applied = self.reversals.iter().map(|a| HistoryEntry {
+            label: a.label().to_owned(),
+            applied: true,
+        });
+        let undone = self.redos.iter().rev().map(|a| HistoryEntry {
+            label: a.label().to_owned(),
+            applied: false,
+        });
+
+        applied.chain(undone).collect()
+    }
+
+    /// Get the macros recorded with [`Event::StartMacro`] and
+    /// [`Event::StopMacro`]
+    pub fn macros(&self) -> &[Vec<Event>] {
+        &self.macros
+    }
+
+    /// Whether a macro is being recorded
+    pub fn is_recording_macro(&self) -> bool {
+        self.recording_macro.is_some()
     }
 
     /// Get the colors of the palette
@@ -484,11 +1301,17 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
     /// Anchor the [`FreeImage`] into the canvas.
     fn anchor(&mut self) -> Result<()> {
         if let Some(free_image) = self.free_image.take() {
+            // Events that create free images fail on locked layers, so this can
+            // only be a preview, which is discarded
+            if self.layers.active().locked() {
+                return Ok(());
+            }
+
             println!("Anchoring");
-            let reversals = self.canvas_mut().paste_obj(&free_image);
-            self.single_pixels_action(reversals);
+            let reversal = self.canvas_mut().paste_obj(&free_image);
+            self.single_area_action("Anchor selection", reversal);
             self.set_selection(Some(Selection::Canvas(
-                free_image.rect.clip_to(self.canvas().rect()),
+                free_image.rect.clip_to(self.selectable_rect()),
             )))?;
         }
 
@@ -497,12 +1320,93 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
 
     /// Undo the last undoable action. Returns the [`CanvasEffect`] to signal to
     /// the caller what needs to be updated visually
-    fn undo(&mut self) -> CanvasEffect {
+    fn undo(&mut self) -> Result<CanvasEffect> {
         if let Some(action) = self.reversals.pop() {
-            return action.apply(&mut self.layers);
+            return match action.apply(&mut self.layers) {
+                Ok((effect, redo)) => {
+                    self.redos.push(redo);
+                    Ok(effect)
+                }
+                Err((e, action)) => {
+                    self.reversals.push(*action);
+                    Err(e)
+                }
+            };
         }
 
-        CanvasEffect::None
+        Ok(CanvasEffect::None)
+    }
+
+    /// Redo the last undone action. Returns the [`CanvasEffect`] to signal to
+    /// the caller what needs to be updated visually
+    fn redo(&mut self) -> Result<CanvasEffect> {
+        if let Some(action) = self.redos.pop() {
+            return match action.apply(&mut self.layers) {
+                Ok((effect, reversal)) => {
+                    self.reversals.push(reversal);
+                    Ok(effect)
+                }
+                Err((e, action)) => {
+                    self.redos.push(*action);
+                    Err(e)
+                }
+            };
+        }
+
+        Ok(CanvasEffect::None)
+    }
+
+    /// Undo or redo as many actions as needed so that exactly `position`
+    /// actions of the history are applied (see [`State::history`]). Returns
+    /// the [`CanvasEffect`] to signal to the caller what needs to be updated
+    /// visually
+    fn jump_to_history(&mut self, position: usize) -> Result<CanvasEffect> {
+        let mut effect = CanvasEffect::None;
+
+        while self.reversals.len() > position {
+            effect = self.undo()?;
+        }
+        while self.reversals.len() < position && !self.redos.is_empty() {
+            effect = self.redo()?;
+        }
+
+        Ok(effect)
+    }
+
+    /// Play a recorded macro, merging all the actions it registers into one.
+    fn play_macro(&mut self, label: &str, index: usize, offset: Point<i32>) -> Result<()> {
+        let events = self
+            .macros
+            .get(index)
+            .ok_or(Error::MissingMacro(index))?
+            .clone();
+
+        self.end_action();
+        let start = self.reversals.len();
+        // The events of the macro are not recorded themselves (only the event
+        // that plays it), and none of its actions should be dropped from the
+        // history before being merged
+        let recording = self.recording_macro.take();
+        let history_limit = std::mem::replace(&mut self.history_limit, usize::MAX);
+
+        let mut result = Ok(());
+        for event in events {
+            if let Err(e) = self.execute_event(event.translated(offset)) {
+                result = Err(e);
+                break;
+            }
+        }
+
+        self.end_action();
+        self.recording_macro = recording;
+        self.history_limit = history_limit;
+
+        let actions = self.reversals.split_off(start);
+        if !actions.is_empty() {
+            self.push_reversal(Action::merge(label, actions));
+        }
+
+        result
     }
 
     /// When drawing lines, rectangles, etc. or moving things, there are visible
@@ -530,15 +1434,15 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
         Ok(())
     }
 
-    fn free_image_from_selection(&mut self, mouse_pos: Option<Point<i32>>) {
+    fn free_image_from_selection(&mut self, label: &str, mouse_pos: Option<Point<i32>>) {
         if let Some(Selection::Canvas(rect)) = self.selection {
             self.free_image = Some(FreeImage::from_canvas_area(
                 self.canvas(),
                 rect,
                 mouse_pos.map(|p| p - rect.pos()),
             ));
-            let reversals = self.canvas_mut().set_area(rect, TRANSPARENT);
-            self.single_pixels_action(reversals);
+            let reversal = self.canvas_mut().set_area(rect, TRANSPARENT);
+            self.single_area_action(label, reversal);
             self.selection = Some(Selection::FreeImage);
         }
     }
@@ -552,17 +1456,75 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
     }
 
     fn save_image(&self, path: &str) -> Result<()> {
-        let blended = self.layers.blended();
+        util::save_image(self.spritesheet_img(), path)
+    }
+
+    /// Export the frames played by an animation (see [`Layers::playback`]),
+    /// each blended and with its duration, as an animated image
+    fn save_animation(&self, path: &Path, settings: &AnimationExport) -> Result<()> {
+        settings.check()?;
+        let tag = match settings.tag {
+            Some(index) => Some(
+                self.layers
+                    .tags()
+                    .get(index)
+                    .ok_or(Error::MissingAnimationTag(index))?,
+            ),
+            None => None,
+        };
+        let frames = self
+            .layers
+            .playback(tag)
+            .into_iter()
+            .map(|i| {
+                let duration = self.layers.frames()[i].duration();
+                (self.layers.blended_frame(i), duration)
+            })
+            .collect();
+
+        util::save_animation(frames, path, settings)
+    }
+
+    /// Export each layer (except for reference layers) to a PNG file in a
+    /// directory, named after the layer's position and name, and keeping its
+    /// name and metadata. Only the cels of the active frame are exported
+    fn save_layers(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+
+        for i in 0..self.layers.count() {
+            let layer = self.layers.get(i);
+            if layer.reference().is_some() {
+                continue;
+            }
+            let name: String = layer
+                .name()
+                .chars()
+                .map(|c| match c {
+                    c if c.is_alphanumeric() || c == '-' || c == '_' => c,
+                    _ => '_',
+                })
+                .collect();
+            let file = match name.is_empty() {
+                true => format!("{:02}.png", i + 1),
+                false => format!("{:02}-{}.png", i + 1, name),
+            };
+
+            util::save_png_with_metadata(
+                &layer.masked_area(self.layers.canvas_rect()),
+                &dir.join(file),
+                layer.name(),
+                layer.metadata(),
+            )?;
+        }
 
-        util::save_image(blended, path)
+        Ok(())
     }
 
     fn import_image(&mut self, path: &str) -> Result<()> {
         let img = util::load_img_from_file(path)?;
 
-        if img.width() as i32 > self.canvas().width()
-            || img.height() as i32 > self.canvas().height()
-        {
+        let canvas_size = self.layers.canvas_size();
+        if img.width() as i32 > canvas_size.x || img.height() as i32 > canvas_size.y {
             self.resize_canvas((img.width() as i32, img.height() as i32).into());
         }
 
@@ -574,3 +1536,19 @@ impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> State<IMG> {
         Ok(())
     }
 }
+
+impl<IMG: Bitmap + Serialize + for<'de> Deserialize<'de>> From<StateV1<IMG>> for State<IMG> {
+    fn from(v1: StateV1<IMG>) -> Self {
+        Self {
+            layers: v1.layers.into_layers(v1.spritesheet),
+            tool: v1.tool,
+            main_color: v1.main_color,
+            spritesheet: v1.spritesheet,
+            palette: v1.palette,
+            selection: v1.selection,
+            free_image: v1.free_image,
+            clipboard: v1.clipboard,
+            ..Self::new(Size::ZERO, None, None)
+        }
+    }
+}
@@ -1,14 +1,19 @@
-use crate::{color, Bitmap, Color, Error, Result};
-use image::{codecs, ImageEncoder, ImageFormat, ImageOutputFormat};
+use crate::{color, AnimationExport, Bitmap, Color, Error, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{codecs, Delay, ImageEncoder, ImageFormat, ImageOutputFormat, RgbaImage};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 /// Holds a function that takes a path as input and outputs the bytes of the
-/// project file found at that path.
-pub struct LoadProject(pub fn(PathBuf) -> Vec<u8>);
+/// project file found at that path, or an error if it can't be read (usually
+/// [`Error::ProjectIo`]).
+pub struct LoadProject(pub fn(PathBuf) -> Result<Vec<u8>>);
 impl Debug for LoadProject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        f.write_str("LoadProject(fn(PathBuf) -> Vec<u8>>)")
+        f.write_str("LoadProject(fn(PathBuf) -> Result<Vec<u8>>)")
     }
 }
 impl PartialEq for LoadProject {
@@ -23,17 +28,18 @@ impl Clone for LoadProject {
     }
 }
 
-impl From<fn(PathBuf) -> Vec<u8>> for LoadProject {
-    fn from(val: fn(PathBuf) -> Vec<u8>) -> Self {
+impl From<fn(PathBuf) -> Result<Vec<u8>>> for LoadProject {
+    fn from(val: fn(PathBuf) -> Result<Vec<u8>>) -> Self {
         Self(val)
     }
 }
 /// Holds a function that takes a path and a set of bytes as input as saves
-/// those bytes as a project file at that path
-pub struct SaveProject(pub fn(PathBuf, Vec<u8>));
+/// those bytes as a project file at that path, or returns an error if it can't
+/// be written (usually [`Error::ProjectIo`]).
+pub struct SaveProject(pub fn(PathBuf, Vec<u8>) -> Result<()>);
 impl Debug for SaveProject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        f.write_str("SaveProject(fn(PathBuf, Vec<u8>))")
+        f.write_str("SaveProject(fn(PathBuf, Vec<u8>) -> Result<()>)")
     }
 }
 
@@ -95,3 +101,212 @@ pub fn save_image<IMG: Bitmap>(bitmap: IMG, path: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Speed of the color quantization of GIF frames with more than 256 colors,
+/// from 1 (best quality) to 30 (fastest)
+const GIF_QUANTIZATION_SPEED: i32 = 10;
+
+/// Save the frames of an animation, each with its duration in milliseconds,
+/// as an animated image to the specified file path. The format is chosen by
+/// the file extension: GIF (`.gif`), APNG (`.png` or `.apng`) or WebP
+/// (`.webp`)
+pub fn save_animation<IMG: Bitmap>(
+    frames: Vec<(IMG, u32)>,
+    path: &Path,
+    settings: &AnimationExport,
+) -> Result<()> {
+    let is_apng = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("apng"));
+    let format = match ImageFormat::from_path(path) {
+        _ if is_apng => ImageFormat::Png,
+        Ok(format @ (ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP)) => format,
+        _ => return Err(Error::UnsupportedImageFormat),
+    };
+
+    let scale = settings.scale as u32;
+    let frames = frames
+        .into_iter()
+        .map(|(bitmap, duration)| {
+            let (width, height) = (bitmap.width() as u32, bitmap.height() as u32);
+            let img = RgbaImage::from_raw(width, height, bitmap.bytes().to_vec())
+                .ok_or(Error::FailedImageFromRaw)?;
+            let img = match scale {
+                1 => img,
+                _ => imageops::resize(&img, width * scale, height * scale, FilterType::Nearest),
+            };
+            Ok((img, duration))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let file = std::fs::File::create(path)?;
+    let buffer = std::io::BufWriter::new(file);
+    match format {
+        ImageFormat::Gif => save_gif(frames, buffer, settings),
+        ImageFormat::Png => save_apng(frames, buffer, settings),
+        _ => save_webp(frames, buffer, settings),
+    }
+}
+
+/// Encode the frames of an animation as a GIF. GIF pixels are either fully
+/// opaque or fully transparent (see [`AnimationExport::alpha_threshold`]),
+/// and each frame can have at most 256 colors, so frames with more colors are
+/// quantized
+fn save_gif<W: Write>(
+    frames: Vec<(RgbaImage, u32)>,
+    writer: W,
+    settings: &AnimationExport,
+) -> Result<()> {
+    let mut encoder = GifEncoder::new_with_speed(writer, GIF_QUANTIZATION_SPEED);
+    encoder.set_repeat(match settings.repeat {
+        Some(times) => Repeat::Finite(times),
+        None => Repeat::Infinite,
+    })?;
+
+    for (mut img, duration) in frames {
+        for pixel in img.pixels_mut() {
+            if pixel[3] < settings.alpha_threshold {
+                pixel.0 = color::TRANSPARENT.into();
+            } else {
+                pixel[3] = 255;
+            }
+        }
+        let delay = Delay::from_numer_denom_ms(gif_delay(duration), 1);
+        encoder.encode_frame(image::Frame::from_parts(img, 0, 0, delay))?;
+    }
+
+    Ok(())
+}
+
+/// GIF delays are in hundredths of a second, and most viewers play frames
+/// shorter than 20 milliseconds slower, so durations (in milliseconds) are
+/// rounded to the nearest 10 milliseconds, and are at least 20 milliseconds
+fn gif_delay(duration: u32) -> u32 {
+    (duration.saturating_add(5) / 10 * 10).max(20)
+}
+
+/// Encode the frames of an animation as an APNG, which keeps their colors
+/// and transparency as they are
+fn save_apng<W: Write>(
+    frames: Vec<(RgbaImage, u32)>,
+    writer: W,
+    settings: &AnimationExport,
+) -> Result<()> {
+    let (width, height) = frames[0].0.dimensions();
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, play_count(settings))?;
+
+    let mut writer = encoder.write_header()?;
+    for (img, duration) in frames {
+        // Delays are fractions of a second with 16-bit terms, so longer
+        // durations lose precision
+        match u16::try_from(duration) {
+            Ok(ms) => writer.set_frame_delay(ms, 1000)?,
+            Err(_) => writer.set_frame_delay((duration / 100).min(u16::MAX as u32) as u16, 10)?,
+        }
+        writer.write_image_data(img.as_raw())?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Encode the frames of an animation as a lossless animated WebP, which keeps
+/// their colors and transparency as they are. Each frame is encoded as a still
+/// image, and its bitstream is placed in the animation container
+fn save_webp<W: Write>(
+    frames: Vec<(RgbaImage, u32)>,
+    mut writer: W,
+    settings: &AnimationExport,
+) -> Result<()> {
+    const ANIMATION_FLAG: u8 = 1 << 1;
+    const ALPHA_FLAG: u8 = 1 << 4;
+    const NO_BLENDING_FLAG: u8 = 1 << 1;
+    const RIFF_HEADER_LEN: usize = 12;
+
+    let (width, height) = frames[0].0.dimensions();
+    let mut chunks = Vec::new();
+
+    let mut vp8x = vec![ANIMATION_FLAG | ALPHA_FLAG, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    write_riff_chunk(&mut chunks, b"VP8X", &vp8x)?;
+
+    // Transparent background, and the number of times the animation is played
+    // (0 to loop forever)
+    let mut anim = vec![0; 4];
+    anim.extend_from_slice(&(play_count(settings).min(u16::MAX as u32) as u16).to_le_bytes());
+    write_riff_chunk(&mut chunks, b"ANIM", &anim)?;
+
+    for (img, duration) in frames {
+        let mut still = Vec::new();
+        image_webp::WebPEncoder::new(&mut still).encode(
+            img.as_raw(),
+            width,
+            height,
+            image_webp::ColorType::Rgba8,
+        )?;
+        // The still image is a RIFF header followed by a single VP8L chunk,
+        // which is also the data of a frame in the animation
+        let vp8l = &still[RIFF_HEADER_LEN..];
+
+        let mut anmf = vec![0; 6];
+        anmf.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&duration.min(0xFF_FFFF).to_le_bytes()[..3]);
+        anmf.push(NO_BLENDING_FLAG);
+        anmf.extend_from_slice(vp8l);
+        write_riff_chunk(&mut chunks, b"ANMF", &anmf)?;
+    }
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(chunks.len() as u32 + 4).to_le_bytes())?;
+    writer.write_all(b"WEBP")?;
+    writer.write_all(&chunks)?;
+
+    Ok(())
+}
+
+/// Write a chunk of a RIFF file (such as WebP), padded to an even size
+fn write_riff_chunk<W: Write>(writer: &mut W, name: &[u8; 4], data: &[u8]) -> Result<()> {
+    writer.write_all(name)?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    if data.len() % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+/// How many times an animation is played in total, as stored by APNG and
+/// WebP, where 0 means it loops forever
+fn play_count(settings: &AnimationExport) -> u32 {
+    settings.repeat.map_or(0, |times| times as u32 + 1)
+}
+
+/// Save an image as a PNG file with text chunks: the name of the image (with
+/// the `Title` keyword) followed by the metadata entries
+pub fn save_png_with_metadata<IMG: Bitmap>(
+    bitmap: &IMG,
+    path: &Path,
+    name: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    let file = std::fs::File::create(path)?;
+    let buffer = std::io::BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(buffer, bitmap.width() as u32, bitmap.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_itxt_chunk("Title".to_owned(), name.to_owned())?;
+    for (key, value) in metadata {
+        encoder.add_itxt_chunk(key.clone(), value.clone())?;
+    }
+
+    encoder.write_header()?.write_image_data(bitmap.bytes())?;
+
+    Ok(())
+}
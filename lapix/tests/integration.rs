@@ -1,8 +1,10 @@
 #[cfg(feature = "test-utils")]
 use lapix::TestImage;
+#[cfg(feature = "test-utils")]
+use lapix::{LoadProject, SaveProject};
 
-use lapix::color::{BLACK, TRANSPARENT};
-use lapix::{Color, Event, Point, Size, State};
+use lapix::color::{BLACK, TRANSPARENT, WHITE};
+use lapix::{Bitmap, BlendMode, Color, Event, Point, Size, State};
 
 #[cfg(feature = "test-utils")]
 #[test]
@@ -101,3 +103,1455 @@ fn bucket_then_erase() {
         }
     }
 }
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_then_redo() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::Undo).unwrap();
+
+    for i in 0..side {
+        for j in 0..side {
+            assert_eq!(state.canvas().pixel(Point::new(i, j)), TRANSPARENT);
+        }
+    }
+
+    state.execute(Event::Redo).unwrap();
+
+    for i in 0..side {
+        for j in 0..side {
+            assert_eq!(state.canvas().pixel(Point::new(i, j)), BLACK);
+        }
+    }
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn new_action_clears_redo() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::LineEnd(Point::new(side - 1, 0)))
+        .unwrap();
+    state.execute(Event::Redo).unwrap();
+
+    for i in 0..side {
+        let color = if i == 0 { BLACK } else { TRANSPARENT };
+        assert_eq!(state.canvas().pixel(Point::new(0, i)), color);
+    }
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn redo_deleted_layer() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::DeleteLayer(state.layers().get(1).id()))
+        .unwrap();
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 2);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+}
+
+#[cfg(feature = "test-utils")]
+fn save_project_to_file(path: std::path::PathBuf, bytes: Vec<u8>) -> lapix::Result<()> {
+    std::fs::write(&path, bytes).map_err(|e| lapix::Error::ProjectIo(path, e))
+}
+
+#[cfg(feature = "test-utils")]
+fn load_project_from_file(path: std::path::PathBuf) -> lapix::Result<Vec<u8>> {
+    std::fs::read(&path).map_err(|e| lapix::Error::ProjectIo(path, e))
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_after_loading_project() {
+    let side = 10;
+    let path = std::env::temp_dir().join("lapix_undo_after_loading_project.tarsila");
+    let mut state = State::<TestImage>::new(
+        Size::new(side, side),
+        Some(LoadProject(load_project_from_file)),
+        Some(SaveProject(save_project_to_file)),
+    );
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::SaveProject(path.clone())).unwrap();
+
+    let mut loaded = State::<TestImage>::new(
+        Size::new(side, side),
+        Some(LoadProject(load_project_from_file)),
+        Some(SaveProject(save_project_to_file)),
+    );
+    loaded.execute(Event::LoadProject(path.clone())).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.canvas().pixel(Point::new(0, 0)), BLACK);
+
+    loaded.execute(Event::Undo).unwrap();
+    assert_eq!(loaded.canvas().pixel(Point::new(0, 0)), TRANSPARENT);
+
+    loaded.execute(Event::Redo).unwrap();
+    assert_eq!(loaded.canvas().pixel(Point::new(0, 0)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn history_limit_drops_oldest_actions() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::SetHistoryLimit(1)).unwrap();
+    state
+        .execute(Event::SetMainColor(Color::new(255, 0, 0, 255)))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+
+    assert_eq!(state.canvas().pixel(Point::new(0, 0)), TRANSPARENT);
+    assert_eq!(state.main_color(), Color::new(255, 0, 0, 255));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn history_labels() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::BrushStart).unwrap();
+    state.execute(Event::BrushStroke(Point::new(1, 1))).unwrap();
+    state.execute(Event::BrushEnd).unwrap();
+    state.execute(Event::ResizeCanvas(Size::new(5, 5))).unwrap();
+    state.execute(Event::Undo).unwrap();
+
+    let history: Vec<_> = state
+        .history()
+        .into_iter()
+        .map(|entry| (entry.label, entry.applied))
+        .collect();
+    let expected = vec![
+        ("Bucket fill".to_owned(), true),
+        ("Brush stroke".to_owned(), true),
+        ("Resize canvas".to_owned(), false),
+    ];
+    assert_eq!(history, expected);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn jump_to_history() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    for i in 0..3 {
+        state.execute(Event::LineStart(Point::new(0, i))).unwrap();
+        state
+            .execute(Event::LineEnd(Point::new(side - 1, i)))
+            .unwrap();
+    }
+
+    state.execute(Event::JumpToHistory(0)).unwrap();
+    assert!(state.history().iter().all(|entry| !entry.applied));
+    for i in 0..3 {
+        assert_eq!(state.canvas().pixel(Point::new(0, i)), TRANSPARENT);
+    }
+
+    state.execute(Event::JumpToHistory(2)).unwrap();
+    let applied: Vec<_> = state.history().iter().map(|e| e.applied).collect();
+    assert_eq!(applied, vec![true, true, false]);
+    assert_eq!(state.canvas().pixel(Point::new(0, 1)), BLACK);
+    assert_eq!(state.canvas().pixel(Point::new(0, 2)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn replay_journal() {
+    let side = 10;
+    let path = std::env::temp_dir().join("lapix_replay_journal.journal");
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    let red = Color::new(255, 0, 0, 255);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::SetMainColor(red)).unwrap();
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+
+    state
+        .start_journal(std::fs::File::create(&path).unwrap())
+        .unwrap();
+    state
+        .execute(Event::LineEnd(Point::new(side - 1, 0)))
+        .unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.stop_journal();
+    state.execute(Event::ClearCanvas).unwrap();
+
+    let file = std::fs::File::open(&path).unwrap();
+    let mut replayed = State::<TestImage>::replay(file, None, None).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(replayed.layers().count(), 1);
+    assert_eq!(replayed.main_color(), red);
+    for i in 0..side {
+        assert_eq!(replayed.canvas().pixel(Point::new(i, 0)), red);
+        assert_eq!(replayed.canvas().pixel(Point::new(i, 1)), BLACK);
+    }
+
+    replayed.execute(Event::Undo).unwrap();
+    assert_eq!(replayed.canvas().pixel(Point::new(0, 0)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn failed_journal_write() {
+    struct FailingWriter(std::rc::Rc<std::cell::Cell<bool>>);
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match self.0.get() {
+                true => Err(std::io::Error::other("disk full")),
+                false => Ok(buf.len()),
+            }
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let fail = std::rc::Rc::new(std::cell::Cell::new(false));
+    let mut state = State::<TestImage>::new(Size::new(4, 4), None, None);
+    state.start_journal(FailingWriter(fail.clone())).unwrap();
+    fail.set(true);
+
+    // The journal is given up on, but editing goes on
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    assert!(!state.is_journaling());
+    assert_eq!(state.canvas().pixel(Point::new(2, 2)), BLACK);
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(2, 2)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_long_brush_stroke() {
+    let side = 20;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::SetMainColor(Color::new(255, 0, 0, 255)))
+        .unwrap();
+    state.execute(Event::BrushStart).unwrap();
+    for i in 0..side {
+        state.execute(Event::BrushStroke(Point::new(0, i))).unwrap();
+        state
+            .execute(Event::BrushStroke(Point::new(side - 1, i)))
+            .unwrap();
+    }
+    state.execute(Event::BrushEnd).unwrap();
+    state.execute(Event::Undo).unwrap();
+
+    for i in 0..side {
+        for j in 0..side {
+            assert_eq!(state.canvas().pixel(Point::new(i, j)), BLACK);
+        }
+    }
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(5, 5)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn play_macro_at_offset() {
+    let side = 10;
+    let mut state = State::<TestImage>::new(Size::new(side, side), None, None);
+    state.execute(Event::StartMacro).unwrap();
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::LineEnd(Point::new(2, 0))).unwrap();
+    state.execute(Event::RectStart(Point::new(0, 2))).unwrap();
+    state.execute(Event::RectEnd(Point::new(2, 4))).unwrap();
+    state.execute(Event::StopMacro).unwrap();
+    assert_eq!(state.macros().len(), 1);
+    assert_eq!(state.macros()[0].len(), 4);
+
+    state
+        .execute(Event::PlayMacro(0, Point::new(5, 5)))
+        .unwrap();
+    for i in 0..3 {
+        assert_eq!(state.canvas().pixel(Point::new(5 + i, 5)), BLACK);
+    }
+    assert_eq!(state.canvas().pixel(Point::new(6, 8)), TRANSPARENT);
+    assert_eq!(state.canvas().pixel(Point::new(7, 9)), BLACK);
+    assert_eq!(state.history().last().unwrap().label, "Play macro");
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(5, 5)), TRANSPARENT);
+    assert_eq!(state.canvas().pixel(Point::new(7, 9)), TRANSPARENT);
+    assert_eq!(state.canvas().pixel(Point::new(0, 0)), BLACK);
+
+    assert!(state.execute(Event::PlayMacro(1, Point::ZERO)).is_err());
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_macro_across_frames() {
+    let mut state = State::<TestImage>::new(Size::new(4, 4), None, None);
+    state.execute(Event::AddFrame).unwrap();
+    let first = state.layers().frames()[0].id();
+    let second = state.layers().frames()[1].id();
+    state.execute(Event::SwitchFrame(first)).unwrap();
+
+    state.execute(Event::StartMacro).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::SwitchFrame(second)).unwrap();
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::LineEnd(Point::new(3, 0))).unwrap();
+    state.execute(Event::StopMacro).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::SwitchFrame(first)).unwrap();
+
+    let cel_pixel = |state: &State<TestImage>, frame: usize, p: Point<i32>| {
+        state.layers().get(0).cel(frame).pixel(p)
+    };
+    assert_eq!(cel_pixel(&state, 0, Point::new(2, 2)), TRANSPARENT);
+    assert_eq!(cel_pixel(&state, 1, Point::new(0, 0)), TRANSPARENT);
+
+    state.execute(Event::PlayMacro(0, Point::ZERO)).unwrap();
+    assert_eq!(state.layers().active_frame(), 1);
+    assert_eq!(cel_pixel(&state, 0, Point::new(2, 2)), BLACK);
+    assert_eq!(cel_pixel(&state, 1, Point::new(0, 0)), BLACK);
+    assert_eq!(cel_pixel(&state, 1, Point::new(2, 2)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(cel_pixel(&state, 0, Point::new(2, 2)), TRANSPARENT);
+    assert_eq!(cel_pixel(&state, 1, Point::new(0, 0)), TRANSPARENT);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(cel_pixel(&state, 0, Point::new(2, 2)), BLACK);
+    assert_eq!(cel_pixel(&state, 1, Point::new(0, 0)), BLACK);
+    assert_eq!(cel_pixel(&state, 1, Point::new(2, 2)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn load_missing_project() {
+    let path = std::env::temp_dir().join("lapix_load_missing_project.tarsila");
+    let mut state = State::<TestImage>::new(
+        Size::new(10, 10),
+        Some(LoadProject(load_project_from_file)),
+        Some(SaveProject(save_project_to_file)),
+    );
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+
+    let result = state.execute(Event::LoadProject(path));
+    assert!(matches!(result, Err(lapix::Error::ProjectIo(_, _))));
+    assert_eq!(state.canvas().pixel(Point::new(0, 0)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn save_snapshot_during_stroke() {
+    let path = std::env::temp_dir().join("lapix_save_snapshot_during_stroke.tarsila");
+    let mut state = State::<TestImage>::new(
+        Size::new(10, 10),
+        Some(LoadProject(load_project_from_file)),
+        Some(SaveProject(save_project_to_file)),
+    );
+    state.execute(Event::BrushStart).unwrap();
+    state.execute(Event::BrushStroke(Point::new(0, 0))).unwrap();
+    state.save_snapshot(path.clone()).unwrap();
+    state.execute(Event::BrushStroke(Point::new(1, 0))).unwrap();
+    state.execute(Event::BrushEnd).unwrap();
+    assert_eq!(state.history().len(), 1);
+
+    let mut loaded = State::<TestImage>::new(
+        Size::new(10, 10),
+        Some(LoadProject(load_project_from_file)),
+        Some(SaveProject(save_project_to_file)),
+    );
+    loaded.execute(Event::LoadProject(path.clone())).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.canvas().pixel(Point::new(0, 0)), BLACK);
+    assert_eq!(loaded.canvas().pixel(Point::new(1, 0)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_blend_mode() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::ChangeLayerBlendMode(
+            state.layers().get(1).id(),
+            BlendMode::Multiply,
+        ))
+        .unwrap();
+    assert_eq!(state.layers().get(1).blend_mode(), BlendMode::Multiply);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().get(1).blend_mode(), BlendMode::Normal);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().get(1).blend_mode(), BlendMode::Multiply);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn merge_layer_down() {
+    let red = Color::new(255, 0, 0, 255);
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(1).id()))
+        .unwrap();
+    state.execute(Event::SetMainColor(red)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::ChangeLayerOpacity(state.layers().get(1).id(), 127))
+        .unwrap();
+    let expected = state.layers().visible_pixel(Point::new(3, 3));
+
+    state
+        .execute(Event::MergeLayerDown(state.layers().get(1).id()))
+        .unwrap();
+    assert_eq!(state.layers().count(), 1);
+    assert_eq!(state.layers().get(0).opacity(), 255);
+    assert_eq!(state.canvas().pixel(Point::new(3, 3)), expected);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.layers().canvas_at(0).pixel(Point::new(3, 3)), BLACK);
+    assert_eq!(state.layers().canvas_at(1).pixel(Point::new(3, 3)), red);
+    assert_eq!(state.layers().get(1).opacity(), 127);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+    assert_eq!(state.canvas().pixel(Point::new(3, 3)), expected);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn merge_layer_down_blend_mode() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state
+        .execute(Event::SetMainColor(Color::new(100, 100, 100, 255)))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(1).id()))
+        .unwrap();
+    state
+        .execute(Event::SetMainColor(Color::new(255, 0, 0, 255)))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    let multiply = state.layers().get(1).id();
+    state
+        .execute(Event::ChangeLayerBlendMode(multiply, BlendMode::Multiply))
+        .unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    let expected = state.layers().visible_pixel(Point::new(3, 3));
+    assert_eq!(expected, Color::new(100, 0, 0, 255));
+
+    // Merged into the layer above, the multiply layer would cover the bottom
+    // layer instead of darkening it
+    assert!(matches!(
+        state.execute(Event::MergeLayerDown(state.layers().get(2).id())),
+        Err(lapix::Error::MergeBlendMode(id)) if id == multiply
+    ));
+    assert_eq!(state.layers().count(), 3);
+
+    // There is nothing below the bottom layer, so the blend mode is baked in
+    state.execute(Event::MergeLayerDown(multiply)).unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.layers().get(0).blend_mode(), BlendMode::Normal);
+    assert_eq!(state.layers().visible_pixel(Point::new(3, 3)), expected);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn flatten_image() {
+    let path = std::env::temp_dir().join("lapix_flatten_image_reference.png");
+    let mut reference = State::<TestImage>::new(Size::new(2, 2), None, None);
+    reference.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    reference.execute(Event::Save(path.clone())).unwrap();
+
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(1).id()))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(2).id()))
+        .unwrap();
+    state
+        .execute(Event::SetMainColor(Color::new(0, 0, 255, 255)))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::ChangeLayerVisibility(
+            state.layers().get(2).id(),
+            false,
+        ))
+        .unwrap();
+    // The reference layer ends up between the hidden layer and the others
+    state.execute(Event::ImportReference(path.clone())).unwrap();
+    std::fs::remove_file(path).unwrap();
+    state
+        .execute(Event::MoveLayerDown(state.layers().get(3).id()))
+        .unwrap();
+    let reference_id = state.layers().get(2).id();
+
+    state.execute(Event::FlattenImage).unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.layers().active_index(), 1);
+    assert_eq!(state.canvas().pixel(Point::new(5, 5)), BLACK);
+    assert_eq!(state.layers().get(0).id(), reference_id);
+    assert!(state.layers().get(0).reference().is_some());
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 4);
+    assert_eq!(
+        state.layers().canvas_at(0).pixel(Point::new(5, 5)),
+        TRANSPARENT
+    );
+    assert_eq!(state.layers().get(2).id(), reference_id);
+    assert!(!state.layers().get(3).visible());
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.canvas().pixel(Point::new(5, 5)), BLACK);
+    assert_eq!(state.layers().get(0).id(), reference_id);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn undo_group_layers() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::GroupLayers(
+            state.layers().get(1).id(),
+            state.layers().get(2).id(),
+        ))
+        .unwrap();
+    let id = state.layers().get(1).group().unwrap();
+    state
+        .execute(Event::GroupLayers(
+            state.layers().get(2).id(),
+            state.layers().get(2).id(),
+        ))
+        .unwrap();
+    assert_eq!(state.layers().ancestors(2).len(), 2);
+
+    state.execute(Event::Ungroup(id)).unwrap();
+    assert_eq!(state.layers().get(1).group(), None);
+    assert_eq!(state.layers().ancestors(2).len(), 1);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().get(1).group(), Some(id));
+    assert_eq!(state.layers().ancestors(2).len(), 2);
+
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    assert!(state.layers().groups().is_empty());
+    assert_eq!(state.layers().get(2).group(), None);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().group_range(id), Some(1..=2));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn delete_group() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(2).id()))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::GroupLayers(
+            state.layers().get(1).id(),
+            state.layers().get(2).id(),
+        ))
+        .unwrap();
+    let id = state.layers().get(1).group().unwrap();
+
+    state.execute(Event::DeleteGroup(id)).unwrap();
+    assert_eq!(state.layers().count(), 1);
+    assert!(state.layers().groups().is_empty());
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 3);
+    assert_eq!(state.layers().group_range(id), Some(1..=2));
+    assert_eq!(state.layers().canvas_at(2).pixel(Point::new(5, 5)), BLACK);
+
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+    assert!(state.execute(Event::DeleteGroup(id)).is_err());
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn merge_layer_down_in_group() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::SwitchLayer(state.layers().get(2).id()))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::GroupLayers(
+            state.layers().get(1).id(),
+            state.layers().get(2).id(),
+        ))
+        .unwrap();
+    let id = state.layers().get(1).group().unwrap();
+    state.execute(Event::ChangeGroupOpacity(id, 127)).unwrap();
+    let expected = state.layers().visible_pixel(Point::new(3, 3));
+
+    state
+        .execute(Event::MergeLayerDown(state.layers().get(2).id()))
+        .unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.layers().get(1).group(), Some(id));
+    assert_eq!(state.layers().visible_pixel(Point::new(3, 3)), expected);
+
+    state
+        .execute(Event::MergeLayerDown(state.layers().get(1).id()))
+        .unwrap();
+    assert!(state.layers().groups().is_empty());
+    assert_eq!(state.layers().visible_pixel(Point::new(3, 3)), expected);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().group_range(id), Some(1..=1));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn rename_layer_and_set_metadata() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    assert_eq!(state.layers().get(1).name(), "Layer 2");
+
+    state
+        .execute(Event::RenameLayer(
+            state.layers().get(1).id(),
+            "outline".to_owned(),
+        ))
+        .unwrap();
+    state
+        .execute(Event::SetLayerMetadata(
+            state.layers().get(1).id(),
+            "author".to_owned(),
+            Some("me".to_owned()),
+        ))
+        .unwrap();
+    assert!(state
+        .execute(Event::SetLayerMetadata(
+            state.layers().get(1).id(),
+            String::new(),
+            None
+        ))
+        .is_err());
+    assert_eq!(state.layers().get(1).name(), "outline");
+    assert_eq!(state.layers().get(1).metadata()["author"], "me");
+
+    state.execute(Event::Undo).unwrap();
+    assert!(state.layers().get(1).metadata().is_empty());
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().get(1).name(), "Layer 2");
+    state.execute(Event::Redo).unwrap();
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().get(1).name(), "outline");
+
+    state
+        .execute(Event::MergeLayerDown(state.layers().get(1).id()))
+        .unwrap();
+    assert_eq!(state.layers().get(0).name(), "Layer 1");
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn save_layers() {
+    let dir = std::env::temp_dir().join("lapix_save_layers");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut state = State::<TestImage>::new(Size::new(3, 2), None, None);
+    state.execute(Event::NewLayerAbove).unwrap();
+    state
+        .execute(Event::RenameLayer(
+            state.layers().get(1).id(),
+            "line art".to_owned(),
+        ))
+        .unwrap();
+    state
+        .execute(Event::SetLayerMetadata(
+            state.layers().get(1).id(),
+            "author".to_owned(),
+            Some("me".to_owned()),
+        ))
+        .unwrap();
+    state.execute(Event::SaveLayers(dir.clone())).unwrap();
+
+    assert!(dir.join("01-Layer_1.png").exists());
+    let file = std::fs::File::open(dir.join("02-line_art.png")).unwrap();
+    let reader = png::Decoder::new(file).read_info().unwrap();
+    let info = reader.info();
+    let text: Vec<_> = info
+        .utf8_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), chunk.get_text().unwrap()))
+        .collect();
+
+    assert_eq!((info.width, info.height), (3, 2));
+    assert_eq!(
+        text,
+        vec![
+            ("Title", "line art".to_owned()),
+            ("author", "me".to_owned())
+        ]
+    );
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn locked_layer() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    let id = state.layers().get(0).id();
+    state.execute(Event::SetLayerLock(id, true)).unwrap();
+
+    assert!(matches!(
+        state.execute(Event::Bucket(Point::new(0, 0))),
+        Err(lapix::Error::LayerLocked(locked)) if locked == id
+    ));
+    assert!(state.execute(Event::BrushStart).is_err());
+    assert_eq!(state.canvas().pixel(Point::new(0, 0)), TRANSPARENT);
+
+    state.execute(Event::SetLayerLock(id, false)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(0, 0)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn alpha_locked_layer() {
+    let red = Color::new(255, 0, 0, 255);
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::LineEnd(Point::new(3, 0))).unwrap();
+    state
+        .execute(Event::SetLayerAlphaLock(state.layers().get(0).id(), true))
+        .unwrap();
+    state.execute(Event::SetMainColor(red)).unwrap();
+
+    state.execute(Event::BrushStart).unwrap();
+    state.execute(Event::BrushStroke(Point::new(2, 0))).unwrap();
+    state.execute(Event::BrushStroke(Point::new(5, 0))).unwrap();
+    state.execute(Event::BrushStroke(Point::new(5, 1))).unwrap();
+    state.execute(Event::BrushEnd).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(1, 0)), BLACK);
+    assert_eq!(state.canvas().pixel(Point::new(2, 0)), red);
+    assert_eq!(state.canvas().pixel(Point::new(3, 0)), red);
+    assert_eq!(state.canvas().pixel(Point::new(4, 0)), TRANSPARENT);
+    assert_eq!(state.canvas().pixel(Point::new(5, 1)), TRANSPARENT);
+
+    state.execute(Event::Bucket(Point::new(9, 9))).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(9, 9)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.canvas().pixel(Point::new(2, 0)), BLACK);
+    assert_eq!(state.canvas().pixel(Point::new(4, 0)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn duplicate_layer() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::ChangeLayerOpacity(state.layers().get(0).id(), 100))
+        .unwrap();
+    state
+        .execute(Event::DuplicateLayer(state.layers().get(0).id()))
+        .unwrap();
+
+    let copy = state.layers().get(1);
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(state.layers().active_index(), 1);
+    assert_eq!(copy.canvas().pixel(Point::new(5, 5)), BLACK);
+    assert_eq!(copy.opacity(), 100);
+    assert_eq!(copy.name(), "Layer 1 copy");
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().count(), 2);
+    assert_eq!(
+        state.layers().get(1).canvas().pixel(Point::new(5, 5)),
+        BLACK
+    );
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn selection_to_new_layer() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::StartSelection(Point::new(0, 0)))
+        .unwrap();
+    state
+        .execute(Event::EndSelection(Point::new(1, 1)))
+        .unwrap();
+    state.execute(Event::SelectionToNewLayer).unwrap();
+
+    let (bottom, top) = (state.layers().get(0), state.layers().get(1));
+    assert_eq!(state.layers().active_index(), 1);
+    assert_eq!(bottom.canvas().pixel(Point::new(1, 1)), TRANSPARENT);
+    assert_eq!(bottom.canvas().pixel(Point::new(2, 2)), BLACK);
+    assert_eq!(top.canvas().pixel(Point::new(1, 1)), BLACK);
+    assert_eq!(top.canvas().pixel(Point::new(2, 2)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+    assert_eq!(state.canvas().pixel(Point::new(1, 1)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn free_image_to_new_layer() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::StartSelection(Point::new(0, 0)))
+        .unwrap();
+    state
+        .execute(Event::EndSelection(Point::new(1, 1)))
+        .unwrap();
+    state.execute(Event::MoveStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::MoveEnd(Point::new(5, 5))).unwrap();
+    state.execute(Event::SelectionToNewLayer).unwrap();
+
+    let (bottom, top) = (state.layers().get(0), state.layers().get(1));
+    assert!(state.free_image().is_none());
+    assert_eq!(bottom.canvas().pixel(Point::new(0, 0)), TRANSPARENT);
+    assert_eq!(top.canvas().pixel(Point::new(6, 6)), BLACK);
+    assert_eq!(top.canvas().pixel(Point::new(0, 0)), TRANSPARENT);
+    assert_eq!(
+        state.selection(),
+        Some(lapix::Selection::Canvas(lapix::Rect::new(5, 5, 2, 2)))
+    );
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn layer_mask() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::AddLayerMask(state.layers().get(0).id()))
+        .unwrap();
+    state.execute(Event::EditLayerMask(true)).unwrap();
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::LineEnd(Point::new(9, 0))).unwrap();
+
+    let layers = state.layers();
+    assert_eq!(layers.visible_pixel(Point::new(5, 0)), TRANSPARENT);
+    assert_eq!(layers.visible_pixel(Point::new(5, 5)), BLACK);
+    assert_eq!(layers.canvas_at(0).pixel(Point::new(5, 0)), BLACK);
+
+    state
+        .execute(Event::InvertLayerMask(state.layers().get(0).id()))
+        .unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 0)), BLACK);
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 5)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 0)), BLACK);
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 0)), TRANSPARENT);
+
+    state
+        .execute(Event::RemoveLayerMask(state.layers().get(0).id()))
+        .unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 0)), BLACK);
+    let id = state.layers().get(0).id();
+    assert!(matches!(
+        state.execute(Event::ApplyLayerMask(id)),
+        Err(lapix::Error::MissingLayerMask(missing)) if missing == id
+    ));
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(5, 0)), TRANSPARENT);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn apply_layer_mask() {
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::AddLayerMask(state.layers().get(0).id()))
+        .unwrap();
+    state.execute(Event::EditLayerMask(true)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::ApplyLayerMask(state.layers().get(0).id()))
+        .unwrap();
+
+    assert!(state.layers().get(0).mask().is_none());
+    assert_eq!(state.canvas().pixel(Point::new(5, 5)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert!(state.layers().get(0).mask().is_some());
+    assert_eq!(state.layers().canvas_at(0).pixel(Point::new(5, 5)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn reference_layer() {
+    let path = std::env::temp_dir().join("lapix_reference_layer.png");
+    let mut reference = State::<TestImage>::new(Size::new(4, 2), None, None);
+    reference.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    reference.execute(Event::Save(path.clone())).unwrap();
+
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::ImportReference(path)).unwrap();
+    state
+        .execute(Event::SetReferenceOffset(
+            state.layers().get(1).id(),
+            Point::new(3, 3),
+        ))
+        .unwrap();
+    state
+        .execute(Event::SetReferenceScale(state.layers().get(1).id(), 2.))
+        .unwrap();
+
+    let layer = state.layers().get(1);
+    let placement = layer.reference().unwrap();
+    assert_eq!(layer.name(), "lapix_reference_layer");
+    assert_eq!(layer.canvas().size(), Size::new(4, 2));
+    assert_eq!(
+        (placement.offset(), placement.scale()),
+        (Point::new(3, 3), 2.)
+    );
+    assert_eq!(
+        state.layers().blended().pixel(Point::new(3, 3)),
+        TRANSPARENT
+    );
+
+    // Reference layers can't be drawn on
+    state
+        .execute(Event::SwitchLayer(state.layers().get(1).id()))
+        .unwrap();
+    assert_eq!(state.layers().active_index(), 0);
+    state.execute(Event::ResizeCanvas(Size::new(5, 5))).unwrap();
+    assert_eq!(state.layers().canvas_size(), Size::new(5, 5));
+    assert_eq!(state.layers().get(1).canvas().size(), Size::new(4, 2));
+    let id = state.layers().get(0).id();
+    assert!(matches!(
+        state.execute(Event::SetReferenceScale(id, 2.)),
+        Err(lapix::Error::NotAReferenceLayer(layer)) if layer == id
+    ));
+
+    state
+        .execute(Event::DeleteLayer(state.layers().get(0).id()))
+        .unwrap();
+    assert_eq!(state.layers().count(), 2);
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().count(), 1);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn reference_layer_in_new_frame() {
+    let path = std::env::temp_dir().join("lapix_reference_layer_in_new_frame.png");
+    let mut reference = State::<TestImage>::new(Size::new(4, 2), None, None);
+    reference.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    reference.execute(Event::Save(path.clone())).unwrap();
+
+    let mut state = State::<TestImage>::new(Size::new(10, 10), None, None);
+    state.execute(Event::ImportReference(path.clone())).unwrap();
+    std::fs::remove_file(path).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+
+    assert_eq!(state.layers().active_frame(), 1);
+    let cel = state.layers().get(1).cel(1);
+    assert_eq!(cel.size(), Size::new(4, 2));
+    assert_eq!(cel.pixel(Point::new(3, 1)), BLACK);
+    assert_eq!(
+        state.layers().get(0).cel(1).pixel(Point::new(0, 0)),
+        TRANSPARENT
+    );
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn shift_layer() {
+    let mut state = State::<TestImage>::new(Size::new(4, 4), None, None);
+    state.execute(Event::LineStart(Point::new(0, 0))).unwrap();
+    state.execute(Event::LineEnd(Point::new(3, 0))).unwrap();
+    state
+        .execute(Event::ShiftLayer(
+            state.layers().get(0).id(),
+            Point::new(-2, 1),
+        ))
+        .unwrap();
+
+    let layer = state.layers().get(0);
+    assert_eq!(layer.offset(), Point::new(-2, 0));
+    assert_eq!(layer.canvas().size(), Size::new(6, 5));
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 1)), BLACK);
+    assert_eq!(state.layers().visible_pixel(Point::new(2, 1)), TRANSPARENT);
+    assert_eq!(state.layers().blended().pixel(Point::new(1, 1)), BLACK);
+
+    // Drawing on the area the layer grew to cover
+    state.execute(Event::Bucket(Point::new(3, 3))).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), BLACK);
+
+    state
+        .execute(Event::ShiftLayer(
+            state.layers().get(0).id(),
+            Point::new(2, -1),
+        ))
+        .unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), BLACK);
+    assert_eq!(state.layers().visible_pixel(Point::new(3, 0)), BLACK);
+
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().get(0).offset(), Point::ZERO);
+    assert_eq!(state.layers().get(0).canvas().size(), Size::new(4, 4));
+    assert_eq!(state.layers().visible_pixel(Point::new(3, 0)), BLACK);
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 1)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn resize_keeps_content() {
+    let mut state = State::<TestImage>::new(Size::new(4, 4), None, None);
+    state
+        .execute(Event::AddLayerMask(state.layers().get(0).id()))
+        .unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::ResizeCanvas(Size::new(2, 2))).unwrap();
+    assert_eq!(state.layers().canvas_size(), Size::new(2, 2));
+    assert_eq!(state.layers().blended().size(), Size::new(2, 2));
+    assert_eq!(state.canvas().size(), Size::new(4, 4));
+
+    state.execute(Event::ResizeCanvas(Size::new(5, 3))).unwrap();
+    let layers = state.layers();
+    assert_eq!(layers.get(0).canvas().size(), Size::new(5, 4));
+    assert_eq!(layers.get(0).mask().unwrap().size(), Size::new(5, 4));
+    assert_eq!(layers.visible_pixel(Point::new(3, 2)), BLACK);
+    assert_eq!(layers.visible_pixel(Point::new(4, 2)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().canvas_size(), Size::new(2, 2));
+    assert_eq!(state.canvas().size(), Size::new(4, 4));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn layer_ids() {
+    let mut state = State::<TestImage>::new(Size::new(4, 4), None, None);
+    let bottom = state.layers().get(0).id();
+    state.execute(Event::NewLayerAbove).unwrap();
+    let top = state.layers().get(1).id();
+    assert_ne!(bottom, top);
+
+    state.execute(Event::MoveLayerDown(top)).unwrap();
+    assert_eq!(state.layers().index_of(top).unwrap(), 0);
+    assert_eq!(state.layers().index_of(bottom).unwrap(), 1);
+
+    // Undoing a deletion brings the layer back with the same id
+    state.execute(Event::DeleteLayer(top)).unwrap();
+    assert!(matches!(
+        state.execute(Event::SwitchLayer(top)),
+        Err(lapix::Error::MissingLayer(missing)) if missing == top
+    ));
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().get(0).id(), top);
+    state.execute(Event::SwitchLayer(top)).unwrap();
+    assert_eq!(state.layers().active_id(), top);
+
+    // Ids are not reused after a layer is deleted
+    state.execute(Event::DeleteLayer(top)).unwrap();
+    state.execute(Event::NewLayerAbove).unwrap();
+    assert_ne!(state.layers().get(1).id(), top);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn frames() {
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    let first = state.layers().active_frame_id();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    let second = state.layers().active_frame_id();
+    assert_eq!(state.layers().frame_count(), 2);
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), TRANSPARENT);
+
+    // Each frame has its own cel, and undoing switches back to its frame
+    state.execute(Event::SetMainColor(WHITE)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::SwitchFrame(first)).unwrap();
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), BLACK);
+    assert!(matches!(
+        state.execute(Event::Undo).unwrap(),
+        lapix::CanvasEffect::Layer
+    ));
+    assert_eq!(state.layers().active_frame_id(), second);
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), TRANSPARENT);
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().frame_count(), 1);
+    assert_eq!(state.layers().active_frame_id(), first);
+    state.execute(Event::Redo).unwrap();
+    assert_eq!(state.layers().active_frame_id(), second);
+
+    state.execute(Event::DuplicateFrame(first)).unwrap();
+    assert_eq!(state.layers().frame_count(), 3);
+    assert_eq!(state.layers().active_frame(), 1);
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), BLACK);
+
+    state.execute(Event::MoveFrame(first, 2)).unwrap();
+    assert_eq!(state.layers().frame_index_of(first).unwrap(), 2);
+    assert!(matches!(
+        state.execute(Event::MoveFrame(first, 3)),
+        Err(lapix::Error::InvalidFrameIndex(3, 3))
+    ));
+    assert!(matches!(
+        state.execute(Event::InsertFrame(4)),
+        Err(lapix::Error::InvalidFrameIndex(4, 3))
+    ));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn delete_frame() {
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    let first = state.layers().active_frame_id();
+    state.execute(Event::DeleteFrame(first)).unwrap();
+    assert_eq!(state.layers().frame_count(), 1);
+
+    state.execute(Event::AddFrame).unwrap();
+    let second = state.layers().active_frame_id();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::DeleteFrame(second)).unwrap();
+    assert_eq!(state.layers().frame_count(), 1);
+    assert_eq!(state.layers().active_frame_id(), first);
+    assert!(matches!(
+        state.execute(Event::SwitchFrame(second)),
+        Err(lapix::Error::MissingFrame(missing)) if missing == second
+    ));
+
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().frame_index_of(second).unwrap(), 1);
+    assert_eq!(state.layers().active_frame_id(), second);
+    assert_eq!(state.layers().visible_pixel(Point::new(0, 0)), BLACK);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn spritesheet_img() {
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state
+        .execute(Event::DuplicateFrame(state.layers().active_frame_id()))
+        .unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    // With the default layout (a single column), frames are stacked vertically
+    assert_eq!(state.spritesheet_img().size(), Size::new(2, 6));
+
+    // Frames that don't fit the layout go in extra rows
+    state
+        .execute(Event::SetSpritesheet(Size::new(2, 1)))
+        .unwrap();
+    let img = state.spritesheet_img();
+    assert_eq!(img.size(), Size::new(4, 4));
+    assert_eq!(img.pixel(Point::new(3, 1)), BLACK);
+    assert_eq!(img.pixel(Point::new(1, 3)), TRANSPARENT);
+
+    assert!(matches!(
+        state.execute(Event::SetSpritesheet(Size::new(0, 1))),
+        Err(lapix::Error::InvalidSpritesheet(_))
+    ));
+    assert_eq!(state.spritesheet(), Size::new(2, 1));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn frame_durations() {
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    let first = state.layers().active_frame_id();
+    state.execute(Event::SetFrameDuration(first, 50)).unwrap();
+    state.execute(Event::DuplicateFrame(first)).unwrap();
+    let second = state.layers().active_frame_id();
+    assert_eq!(state.layers().frames()[1].duration(), 50);
+    state.execute(Event::SetFrameDuration(second, 200)).unwrap();
+
+    assert_eq!(state.layers().frame_at_time(None, 49), 0);
+    assert_eq!(state.layers().frame_at_time(None, 50), 1);
+    assert_eq!(state.layers().frame_at_time(None, 249), 1);
+    assert_eq!(state.layers().frame_at_time(None, 250), 0);
+
+    assert!(matches!(
+        state.execute(Event::SetFrameDuration(second, 0)),
+        Err(lapix::Error::InvalidFrameDuration(0))
+    ));
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().frames()[1].duration(), 50);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn animation_tags() {
+    use lapix::{AnimationDirection, AnimationTag};
+
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    for _ in 0..3 {
+        state.execute(Event::AddFrame).unwrap();
+    }
+    let run = AnimationTag::new("run", 1, 3, AnimationDirection::PingPong);
+    state.execute(Event::AddAnimationTag(run.clone())).unwrap();
+    assert_eq!(state.layers().playback(Some(&run)), vec![1, 2, 3, 2]);
+    assert_eq!(state.layers().playback(None), vec![0, 1, 2, 3]);
+    assert_eq!(state.layers().frame_at_time(Some(&run), 350), 2);
+
+    assert!(matches!(
+        state.execute(Event::AddAnimationTag(AnimationTag::new(
+            "idle",
+            2,
+            4,
+            AnimationDirection::Forward
+        ))),
+        Err(lapix::Error::InvalidAnimationTagRange(2, 4, 4))
+    ));
+    assert!(matches!(
+        state.execute(Event::DeleteAnimationTag(1)),
+        Err(lapix::Error::MissingAnimationTag(1))
+    ));
+
+    let idle = AnimationTag::new("idle", 0, 0, AnimationDirection::Forward);
+    state.execute(Event::AddAnimationTag(idle)).unwrap();
+    let id = state.layers().frames()[0].id();
+    state.execute(Event::DeleteFrame(id)).unwrap();
+    assert_eq!(state.layers().tags().len(), 1);
+    assert_eq!(state.layers().tags()[0].range(), 0..=2);
+
+    // Undoing brings back the tags that lost their only frame
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().tags()[0], run);
+    assert_eq!(state.layers().tags()[1].name(), "idle");
+
+    let reverse = AnimationTag::new("back", 0, 3, AnimationDirection::Reverse);
+    state.execute(Event::SetAnimationTag(0, reverse)).unwrap();
+    assert_eq!(state.layers().tags()[0].name(), "back");
+    state.execute(Event::Undo).unwrap();
+    assert_eq!(state.layers().tags()[0], run);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn spritesheet_metadata() {
+    let mut state = State::<TestImage>::new(Size::new(2, 3), None, None);
+    state.execute(Event::AddFrame).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    state
+        .execute(Event::SetFrameDuration(
+            state.layers().active_frame_id(),
+            30,
+        ))
+        .unwrap();
+    state
+        .execute(Event::SetSpritesheet(Size::new(2, 1)))
+        .unwrap();
+
+    let metadata = state.spritesheet_metadata();
+    assert_eq!(metadata.size, Size::new(4, 6));
+    assert_eq!(metadata.frames.len(), 3);
+    assert_eq!(metadata.frames[1].rect, lapix::Rect::new(2, 0, 2, 3));
+    assert_eq!(metadata.frames[2].rect, lapix::Rect::new(0, 3, 2, 3));
+    assert_eq!(metadata.frames[2].duration, 30);
+    assert_eq!(metadata.frames[0].duration, lapix::DEFAULT_FRAME_DURATION);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn onion_skin() {
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    state.execute(Event::AddFrame).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    let middle = state.layers().frames()[1].id();
+    state.execute(Event::SwitchFrame(middle)).unwrap();
+    assert!(state.onion_skin_ghosts().is_empty());
+
+    let settings = lapix::OnionSkin {
+        enabled: true,
+        ..Default::default()
+    };
+    state.execute(Event::SetOnionSkin(settings)).unwrap();
+    let ghosts = state.onion_skin_ghosts();
+    assert_eq!(ghosts.len(), 2);
+    assert_eq!(ghosts[0].frame, 0);
+    assert_eq!(ghosts[0].tint, settings.before_tint);
+    assert_eq!(ghosts[1].frame, 2);
+    assert_eq!(ghosts[1].opacity, settings.opacity);
+
+    let invalid = lapix::OnionSkin {
+        falloff: 1.5,
+        ..settings
+    };
+    assert!(matches!(
+        state.execute(Event::SetOnionSkin(invalid)),
+        Err(lapix::Error::InvalidOnionSkinFalloff(_))
+    ));
+    assert_eq!(state.onion_skin(), settings);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn export_gif() {
+    use image::AnimationDecoder;
+    use lapix::{AnimationDirection, AnimationExport, AnimationTag};
+
+    let path = std::env::temp_dir().join("lapix_export_gif.gif");
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    let first = state.layers().active_frame_id();
+    state.execute(Event::SetFrameDuration(first, 123)).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    let translucent = Color::new(255, 0, 0, 100);
+    state.execute(Event::SetMainColor(translucent)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    let tag = AnimationTag::new("all", 0, 2, AnimationDirection::PingPong);
+    state.execute(Event::AddAnimationTag(tag)).unwrap();
+
+    let settings = AnimationExport {
+        tag: Some(0),
+        repeat: Some(2),
+        ..Default::default()
+    };
+    state
+        .execute(Event::ExportAnimation(path.clone(), settings))
+        .unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let frames = image::codecs::gif::GifDecoder::new(file)
+        .unwrap()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0].delay().numer_denom_ms(), (120, 1));
+    assert_eq!(frames[1].delay().numer_denom_ms(), (100, 1));
+    assert_eq!(frames[0].buffer().get_pixel(1, 1).0, [0, 0, 0, 255]);
+    // GIF has no partial transparency, so translucent pixels are dropped
+    assert_eq!(frames[2].buffer().get_pixel(1, 1).0[3], 0);
+
+    let unsupported = std::env::temp_dir().join("lapix_export_gif.bmp");
+    assert!(matches!(
+        state.execute(Event::ExportAnimation(unsupported, settings)),
+        Err(lapix::Error::UnsupportedImageFormat)
+    ));
+    let missing_tag = AnimationExport {
+        tag: Some(1),
+        ..settings
+    };
+    assert!(matches!(
+        state.execute(Event::ExportAnimation("a.gif".into(), missing_tag)),
+        Err(lapix::Error::MissingAnimationTag(1))
+    ));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn export_apng_and_webp() {
+    use image::AnimationDecoder;
+    use lapix::AnimationExport;
+
+    let mut state = State::<TestImage>::new(Size::new(2, 2), None, None);
+    let translucent = Color::new(255, 0, 0, 100);
+    state.execute(Event::SetMainColor(translucent)).unwrap();
+    state.execute(Event::Bucket(Point::new(0, 0))).unwrap();
+    state.execute(Event::AddFrame).unwrap();
+    let second = state.layers().active_frame_id();
+    state.execute(Event::SetFrameDuration(second, 250)).unwrap();
+    let settings = AnimationExport {
+        repeat: Some(1),
+        scale: 3,
+        ..Default::default()
+    };
+
+    let path = std::env::temp_dir().join("lapix_export_apng.apng");
+    state
+        .execute(Event::ExportAnimation(path.clone(), settings))
+        .unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let frames = image::codecs::png::PngDecoder::new(file)
+        .unwrap()
+        .apng()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].buffer().dimensions(), (6, 6));
+    // Translucent pixels are kept as they are
+    assert_eq!(frames[0].buffer().get_pixel(5, 5).0, [255, 0, 0, 100]);
+    assert_eq!(frames[1].buffer().get_pixel(5, 5).0[3], 0);
+    assert_eq!(
+        std::time::Duration::from(frames[1].delay()),
+        std::time::Duration::from_millis(250)
+    );
+
+    let path = std::env::temp_dir().join("lapix_export_webp.webp");
+    state
+        .execute(Event::ExportAnimation(path.clone(), settings))
+        .unwrap();
+    let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+    let mut decoder = image_webp::WebPDecoder::new(file).unwrap();
+    assert!(decoder.is_animated());
+    assert_eq!(decoder.num_frames(), 2);
+    assert_eq!(decoder.dimensions(), (6, 6));
+    assert_eq!(
+        decoder.loop_count(),
+        image_webp::LoopCount::Times(2.try_into().unwrap())
+    );
+    let mut buf = vec![0; decoder.output_buffer_size().unwrap()];
+    assert_eq!(decoder.read_frame(&mut buf).unwrap(), 100);
+    assert_eq!(buf[buf.len() - 4..], [255, 0, 0, 100]);
+    assert_eq!(decoder.read_frame(&mut buf).unwrap(), 250);
+    assert_eq!(buf[buf.len() - 1], 0);
+    std::fs::remove_file(path).unwrap();
+
+    let invalid = AnimationExport {
+        scale: 0,
+        ..settings
+    };
+    assert!(matches!(
+        state.execute(Event::ExportAnimation("a.webp".into(), invalid)),
+        Err(lapix::Error::InvalidExportScale(0))
+    ));
+}
@@ -0,0 +1,219 @@
+use crate::{Result, Timer};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_INTERVAL_S: u64 = 60;
+const DEFAULT_KEEP: usize = 5;
+const INTERVAL_VAR: &str = "TARSILA_AUTOSAVE_INTERVAL";
+const KEEP_VAR: &str = "TARSILA_AUTOSAVE_KEEP";
+const DIR_VAR: &str = "TARSILA_RECOVERY_DIR";
+const EXTENSION: &str = "tarsila";
+const LOCK_EXTENSION: &str = "lock";
+
+/// Periodically saves the project into a recovery directory (when it changed),
+/// so that the session can be restored if tarsila crashes. Autosaves are
+/// removed when tarsila exits normally, and each session holds a lock on a
+/// file while it runs, so any autosave of an unlocked session comes from a
+/// session that crashed.
+///
+/// Configured via environment variables:
+/// * `TARSILA_AUTOSAVE_INTERVAL`: seconds between autosaves (0 disables
+///   autosaving);
+/// * `TARSILA_AUTOSAVE_KEEP`: how many autosaves of the session to keep;
+/// * `TARSILA_RECOVERY_DIR`: where to store the autosaves.
+pub struct Autosave {
+    dir: PathBuf,
+    session: String,
+    interval_ms: u64,
+    keep: usize,
+    count: usize,
+    timer: Timer,
+    changed: bool,
+    /// Held while the session runs, from its first autosave on
+    lock: Option<File>,
+}
+
+impl Autosave {
+    pub fn from_env() -> Self {
+        let interval_s = env_or(INTERVAL_VAR, DEFAULT_INTERVAL_S);
+        let keep = env_or(KEEP_VAR, DEFAULT_KEEP).max(1);
+        let dir = std::env::var_os(DIR_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(default_dir);
+
+        Self::new(dir, interval_s * 1000, keep)
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(default_dir(), 0, DEFAULT_KEEP)
+    }
+
+    fn new(dir: PathBuf, interval_ms: u64, keep: usize) -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let mut timer = Timer::new();
+        timer.start(interval_ms);
+
+        Self {
+            dir,
+            // Zero-padded so that sessions are sorted chronologically by name
+            session: format!("{millis:020}-{}", std::process::id()),
+            interval_ms,
+            keep,
+            count: 0,
+            timer,
+            changed: false,
+            lock: None,
+        }
+    }
+
+    /// Register that the project changed since the last autosave
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.interval_ms > 0 && self.changed && self.timer.expired()
+    }
+
+    /// Path for the next autosave of this session. Restarts the timer.
+    pub fn next_path(&mut self) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir).map_err(lapix::Error::from)?;
+        if self.lock.is_none() {
+            let file = File::create(self.lock_path(&self.session)).map_err(lapix::Error::from)?;
+            file.lock().map_err(lapix::Error::from)?;
+            self.lock = Some(file);
+        }
+        self.timer.start(self.interval_ms);
+        self.changed = false;
+        self.count += 1;
+
+        Ok(self
+            .dir
+            .join(format!("{}-{:06}.{EXTENSION}", self.session, self.count)))
+    }
+
+    /// Remove the oldest autosaves of this session beyond the retention limit
+    pub fn prune(&self) -> Result<()> {
+        let files = self.session_files(|session| session == self.session)?;
+        let excess = files.len().saturating_sub(self.keep);
+
+        remove_files(&files[..excess])
+    }
+
+    /// Most recent autosave left by a session that crashed, if any
+    pub fn recovered(&self) -> Option<PathBuf> {
+        let files =
+            self.session_files(|session| session != self.session && !self.is_running(session));
+
+        files.ok()?.pop()
+    }
+
+    /// Remove the autosaves of the session that left a recovered autosave
+    /// (see [`Autosave::recovered`])
+    pub fn discard_recovered(&self, recovered: &Path) -> Result<()> {
+        let session = match session_of(recovered) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        remove_files(&self.session_files(|s| s == session)?)?;
+        remove_lock_file(&self.lock_path(session))
+    }
+
+    /// Remove the autosaves of this session, to be called on a normal exit
+    pub fn clear(&mut self) -> Result<()> {
+        remove_files(&self.session_files(|session| session == self.session)?)?;
+        if self.lock.take().is_some() {
+            remove_lock_file(&self.lock_path(&self.session))?;
+        }
+
+        Ok(())
+    }
+
+    fn lock_path(&self, session: &str) -> PathBuf {
+        self.dir.join(format!("{session}.{LOCK_EXTENSION}"))
+    }
+
+    /// Whether another session is still running, i.e. still holds the lock on
+    /// its lock file (which the OS releases if the session crashes)
+    fn is_running(&self, session: &str) -> bool {
+        match File::open(self.lock_path(session)) {
+            Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+            Err(_) => false,
+        }
+    }
+
+    /// Autosaves in the recovery directory, sorted from oldest to newest, of
+    /// the sessions accepted by a filter
+    fn session_files(&self, filter: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(lapix::Error::from(e).into()),
+        };
+
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .filter(|path| session_of(path).is_some_and(&filter))
+            .collect();
+        files.sort();
+
+        Ok(files)
+    }
+}
+
+fn env_or<T: std::str::FromStr>(var: &str, default: T) -> T {
+    match std::env::var(var) {
+        Ok(val) => val.parse().unwrap_or_else(|_| {
+            eprintln!("WARNING: invalid value `{val}` for {var}, using the default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// A `tarsila/recovery` directory inside the user's data directory, or inside
+/// the temporary directory if that can't be found
+fn default_dir() -> PathBuf {
+    let data_dir = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+
+    data_dir
+        .unwrap_or_else(std::env::temp_dir)
+        .join("tarsila")
+        .join("recovery")
+}
+
+/// The session that made an autosave, from its file name
+fn session_of(path: &Path) -> Option<&str> {
+    let (session, _count) = path.file_stem()?.to_str()?.rsplit_once('-')?;
+
+    Some(session)
+}
+
+fn remove_lock_file(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(lapix::Error::from(e).into()),
+        _ => Ok(()),
+    }
+}
+
+fn remove_files(files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        std::fs::remove_file(file).map_err(lapix::Error::from)?;
+    }
+
+    Ok(())
+}
@@ -5,6 +5,6 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Image error: {0}")]
+    #[error("{0}")]
     LapixError(#[from] LapixError),
 }
@@ -1,19 +1,15 @@
 use crate::wrapped_image::WrappedImage;
 use crate::UiState;
 use lapix::graphics;
-use lapix::{Bitmap, FreeImage, Point, Position, Rect, Selection, Size};
-use macroquad::prelude::Color as MqColor;
+use lapix::{Bitmap, FreeImage, Point, Position, Rect, Reference, Selection, Size};
 use macroquad::prelude::*;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const DASHED_LINE_SEGMENT: f32 = 5.;
 const DASHED_LINE_ANIMATION_MS: u128 = 250;
-const SPRSHEET_LINE_THICKNESS: f32 = 1.;
-const SPRSHEET_LINE_COLOR: MqColor = BLACK;
 
 #[derive(Debug, Copy, Clone)]
 pub struct DrawContext {
-    pub spritesheet: Size<u8>,
     pub scale: f32,
     pub canvas_pos: Position<f32>,
     pub camera: Position<f32>,
@@ -110,47 +106,97 @@ pub fn draw_selection(ctx: DrawContext, free_image: Option<&FreeImage<WrappedIma
     draw_animated_dashed_rect(r);
 }
 
-pub fn draw_spritesheet_boundaries(ctx: DrawContext) {
-    for i in 0..ctx.spritesheet.x {
-        for j in 0..ctx.spritesheet.y {
-            let p0 = ctx.canvas_pos - ctx.camera;
-            let w = ctx.canvas_size.x / ctx.spritesheet.x as f32 * ctx.scale;
-            let h = ctx.canvas_size.y / ctx.spritesheet.y as f32 * ctx.scale;
-            let x = p0.x + i as f32 * w;
-            let y = p0.y + j as f32 * h;
-
-            macroquad::prelude::draw_rectangle_lines(
-                x,
-                y,
-                w,
-                h,
-                SPRSHEET_LINE_THICKNESS,
-                SPRSHEET_LINE_COLOR,
-            );
+pub fn draw_canvas(state: &UiState) {
+    // Onion skin ghosts of the frames around the active one go under it
+    for ghost in state.onion_skin_ghosts() {
+        let tint = ghost.tint;
+        let color = [tint.r, tint.g, tint.b, ghost.opacity];
+        if let Some(texture) = state.frame_tex(ghost.frame) {
+            draw_canvas_texture(state, texture, color, Reference::default());
         }
     }
-}
 
-pub fn draw_canvas(state: &UiState) {
+    let blended = state.blended_tex();
+    if let Some(texture) = blended {
+        draw_canvas_texture(state, texture, [255; 4], Reference::default());
+    }
+
     for i in 0..state.num_layers() {
-        if !state.layer(i).visible() {
+        if !state.layer_visible(i) {
             continue;
         }
 
-        let texture = state.layer_tex(i);
-        let size = Size::new(texture.width(), texture.height());
-        let p = state.canvas_pos() - state.camera();
-        let scale = state.zoom();
-
-        let params = DrawTextureParams {
-            dest_size: Some(Vec2 {
-                x: size.x * scale,
-                y: size.y * scale,
-            }),
-            ..Default::default()
-        };
-
-        let color = [255, 255, 255, state.layer(i).opacity()];
-        macroquad::prelude::draw_texture_ex(texture, p.x, p.y, color.into(), params);
+        // Reference layers are not part of the blended texture, so they are
+        // drawn over it
+        match state.layer_reference(i) {
+            Some(reference) => {
+                let color = [255, 255, 255, state.layer_opacity(i)];
+                draw_canvas_texture(state, state.layer_tex(i), color, reference)
+            }
+            None if blended.is_none() => draw_layer_texture(
+                state,
+                state.layer_tex(i),
+                state.layer_opacity(i),
+                state.layer_offset(i),
+            ),
+            None => (),
+        }
+    }
+}
+
+/// Draw the texture of a layer that is not a reference at its offset, leaving
+/// out the parts that are out of the canvas
+fn draw_layer_texture(state: &UiState, texture: Texture2D, opacity: u8, offset: Point<i32>) {
+    let size = state.canvas_size();
+    let covered = Rect::new(
+        offset.x,
+        offset.y,
+        texture.width() as i32,
+        texture.height() as i32,
+    );
+    let visible = covered.clip_to(Rect::new(0, 0, size.x, size.y));
+    if visible.w <= 0 || visible.h <= 0 {
+        return;
     }
+
+    let scale = state.zoom();
+    let p = state.canvas_pos() - state.camera()
+        + Position::new(visible.x as f32 * scale, visible.y as f32 * scale);
+
+    let params = DrawTextureParams {
+        source: Some(macroquad::prelude::Rect {
+            x: (visible.x - offset.x) as f32,
+            y: (visible.y - offset.y) as f32,
+            w: visible.w as f32,
+            h: visible.h as f32,
+        }),
+        dest_size: Some(Vec2 {
+            x: visible.w as f32 * scale,
+            y: visible.h as f32 * scale,
+        }),
+        ..Default::default()
+    };
+
+    let color = [255, 255, 255, opacity];
+    macroquad::prelude::draw_texture_ex(texture, p.x, p.y, color.into(), params);
+}
+
+/// Draw a texture over the canvas, placed as a reference layer would be (the
+/// default placement covers the canvas exactly), multiplied by a color
+fn draw_canvas_texture(state: &UiState, texture: Texture2D, color: [u8; 4], placement: Reference) {
+    let size = Size::new(texture.width(), texture.height());
+    let scale = state.zoom();
+    let offset: Position<f32> = placement.offset().into();
+    let p = state.canvas_pos() - state.camera() + Position::new(offset.x * scale, offset.y * scale);
+    let scale = scale * placement.scale();
+
+    let params = DrawTextureParams {
+        dest_size: Some(Vec2 {
+            x: size.x * scale,
+            y: size.y * scale,
+        }),
+        ..Default::default()
+    };
+
+    macroquad::prelude::draw_texture_ex(texture, p.x, p.y, color.into(), params);
 }
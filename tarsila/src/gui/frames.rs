@@ -0,0 +1,253 @@
+use crate::Effect;
+use lapix::{AnimationDirection, AnimationTag, Event, FrameId, OnionSkin};
+
+const DIRECTIONS: [(AnimationDirection, &str); 3] = [
+    (AnimationDirection::Forward, "forward"),
+    (AnimationDirection::Reverse, "reverse"),
+    (AnimationDirection::PingPong, "ping-pong"),
+];
+
+pub struct FramesPanel {
+    frames: Vec<FrameId>,
+    active_frame: usize,
+    /// Duration of the active frame, as it's being edited
+    duration: String,
+    /// Tags, along with their names as they're being edited
+    tags: Vec<(AnimationTag, String)>,
+    onion_skin: OnionSkin,
+}
+
+impl FramesPanel {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            active_frame: 0,
+            duration: String::new(),
+            tags: Vec::new(),
+            onion_skin: OnionSkin::default(),
+        }
+    }
+
+    pub fn sync(
+        &mut self,
+        frames: Vec<FrameId>,
+        active_frame: usize,
+        durations: Vec<u32>,
+        tags: Vec<AnimationTag>,
+        onion_skin: OnionSkin,
+    ) {
+        self.frames = frames;
+        self.active_frame = active_frame;
+        self.duration = durations
+            .get(active_frame)
+            .map_or_else(String::new, |d| d.to_string());
+        self.tags = tags
+            .into_iter()
+            .map(|tag| {
+                let name = tag.name().to_owned();
+                (tag, name)
+            })
+            .collect();
+        self.onion_skin = onion_skin;
+    }
+
+    pub fn update(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
+        let mut events = Vec::new();
+        let count = self.frames.len();
+        let active = self.active_frame;
+
+        // Not synced yet
+        let id = match self.frames.get(active) {
+            Some(id) => *id,
+            None => return events,
+        };
+
+        egui::Window::new("Frames")
+            .default_open(false)
+            .show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("+")
+                        .on_hover_text("add frame at the end")
+                        .clicked()
+                    {
+                        events.push(Event::AddFrame.into());
+                    }
+                    if ui
+                        .button("insert")
+                        .on_hover_text("insert frame before the active one")
+                        .clicked()
+                    {
+                        events.push(Event::InsertFrame(active).into());
+                    }
+                    if ui
+                        .button("duplicate")
+                        .on_hover_text("duplicate the active frame")
+                        .clicked()
+                    {
+                        events.push(Event::DuplicateFrame(id).into());
+                    }
+                    ui.add_enabled_ui(active > 0, |ui| {
+                        if ui.button("<").on_hover_text("move frame left").clicked() {
+                            events.push(Event::MoveFrame(id, active - 1).into());
+                        }
+                    });
+                    ui.add_enabled_ui(active + 1 < count, |ui| {
+                        if ui.button(">").on_hover_text("move frame right").clicked() {
+                            events.push(Event::MoveFrame(id, active + 1).into());
+                        }
+                    });
+                    ui.add_enabled_ui(count > 1, |ui| {
+                        if ui.button("x").on_hover_text("delete frame").clicked() {
+                            events.push(Event::DeleteFrame(id).into());
+                        }
+                    });
+                });
+
+                ui.separator();
+                egui::ScrollArea::horizontal().show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        for (i, frame) in self.frames.iter().enumerate() {
+                            let label = (i + 1).to_string();
+                            if ui.selectable_label(i == active, label).clicked() {
+                                events.push(Event::SwitchFrame(*frame).into());
+                            }
+                        }
+                    });
+                });
+
+                ui.horizontal(|ui| {
+                    let label = ui.label("duration (ms):");
+                    let text_edit = ui
+                        .add(
+                            egui::widgets::TextEdit::singleline(&mut self.duration)
+                                .desired_width(40.0),
+                        )
+                        .labelled_by(label.id);
+                    if text_edit.changed() {
+                        if let Ok(duration @ 1..) = self.duration.parse() {
+                            events.push(Event::SetFrameDuration(id, duration).into());
+                        }
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("tags");
+                    if ui
+                        .button("+")
+                        .on_hover_text("add a tag with the active frame")
+                        .clicked()
+                    {
+                        let name = format!("Tag {}", self.tags.len() + 1);
+                        let tag = AnimationTag::new(name, active, active, Default::default());
+                        events.push(Event::AddAnimationTag(tag).into());
+                    }
+                });
+                for i in 0..self.tags.len() {
+                    events.append(&mut self.tag_row(ui, i));
+                }
+
+                ui.separator();
+                events.append(&mut self.onion_skin_settings(ui));
+            });
+
+        events
+    }
+
+    fn tag_row(&mut self, ui: &mut egui::Ui, index: usize) -> Vec<Effect> {
+        let mut events = Vec::new();
+        let count = self.frames.len();
+        let (tag, name) = &mut self.tags[index];
+
+        ui.horizontal(|ui| {
+            let text_edit = ui.add(egui::widgets::TextEdit::singleline(name).desired_width(60.0));
+            if text_edit.changed() {
+                let edited = AnimationTag::new(
+                    name.clone(),
+                    *tag.range().start(),
+                    *tag.range().end(),
+                    tag.direction(),
+                );
+                events.push(Event::SetAnimationTag(index, edited).into());
+            }
+
+            // Frames are numbered from 1 in the panel
+            let (mut from, mut to) = (tag.range().start() + 1, tag.range().end() + 1);
+            let from_changed = ui
+                .add(egui::DragValue::new(&mut from).clamp_range(1..=to))
+                .on_hover_text("first frame")
+                .changed();
+            ui.label("to");
+            let to_changed = ui
+                .add(egui::DragValue::new(&mut to).clamp_range(from..=count))
+                .on_hover_text("last frame")
+                .changed();
+            let mut direction = tag.direction();
+            egui::ComboBox::from_id_source(("tag_direction", index))
+                .width(70.)
+                .selected_text(
+                    DIRECTIONS
+                        .iter()
+                        .find(|d| d.0 == direction)
+                        .map_or("", |d| d.1),
+                )
+                .show_ui(ui, |ui| {
+                    for (option, text) in DIRECTIONS {
+                        ui.selectable_value(&mut direction, option, text);
+                    }
+                });
+            if from_changed || to_changed || direction != tag.direction() {
+                let edited = AnimationTag::new(tag.name(), from - 1, to - 1, direction);
+                events.push(Event::SetAnimationTag(index, edited).into());
+            }
+
+            if ui.button("x").on_hover_text("delete tag").clicked() {
+                events.push(Event::DeleteAnimationTag(index).into());
+            }
+        });
+
+        events
+    }
+
+    fn onion_skin_settings(&mut self, ui: &mut egui::Ui) -> Vec<Effect> {
+        let mut events = Vec::new();
+        let mut settings = self.onion_skin;
+
+        ui.checkbox(&mut settings.enabled, "onion skin")
+            .on_hover_text("show the frames around the active one as ghosts");
+        if settings.enabled {
+            ui.horizontal(|ui| {
+                ui.label("before:");
+                ui.add(egui::DragValue::new(&mut settings.before).clamp_range(0..=10));
+                let tint = settings.before_tint;
+                let mut rgb = [tint.r, tint.g, tint.b];
+                ui.color_edit_button_srgb(&mut rgb);
+                settings.before_tint = [rgb[0], rgb[1], rgb[2], 255].into();
+                ui.label("after:");
+                ui.add(egui::DragValue::new(&mut settings.after).clamp_range(0..=10));
+                let tint = settings.after_tint;
+                let mut rgb = [tint.r, tint.g, tint.b];
+                ui.color_edit_button_srgb(&mut rgb);
+                settings.after_tint = [rgb[0], rgb[1], rgb[2], 255].into();
+            });
+            ui.horizontal(|ui| {
+                ui.label("opacity:");
+                ui.add(egui::DragValue::new(&mut settings.opacity));
+                ui.label("falloff:")
+                    .on_hover_text("how much opacity is kept for each frame further away");
+                ui.add(
+                    egui::DragValue::new(&mut settings.falloff)
+                        .speed(0.05)
+                        .clamp_range(0.0..=1.0),
+                );
+            });
+        }
+
+        if settings != self.onion_skin {
+            events.push(Event::SetOnionSkin(settings).into());
+        }
+
+        events
+    }
+}
@@ -0,0 +1,51 @@
+use crate::Effect;
+use lapix::{Event, HistoryEntry};
+
+pub struct HistoryPanel {
+    history: Vec<HistoryEntry>,
+}
+
+impl HistoryPanel {
+    pub fn new() -> Self {
+        Self {
+            history: Vec::new(),
+        }
+    }
+
+    pub fn sync(&mut self, history: Vec<HistoryEntry>) {
+        self.history = history;
+    }
+
+    pub fn update(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
+        let mut events = Vec::new();
+        let position = self.history.iter().filter(|e| e.applied).count();
+
+        egui::Window::new("History")
+            .default_open(false)
+            .show(egui_ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(200.)
+                    .show(ui, |ui| {
+                        if ui
+                            .selectable_label(position == 0, "Initial state")
+                            .clicked()
+                        {
+                            events.push(Event::JumpToHistory(0).into());
+                        }
+
+                        for (i, entry) in self.history.iter().enumerate() {
+                            let mut text = egui::RichText::new(&entry.label);
+                            if !entry.applied {
+                                text = text.italics();
+                            }
+
+                            if ui.selectable_label(position == i + 1, text).clicked() {
+                                events.push(Event::JumpToHistory(i + 1).into());
+                            }
+                        }
+                    });
+            });
+
+        events
+    }
+}
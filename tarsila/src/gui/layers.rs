@@ -1,11 +1,27 @@
-use crate::Effect;
-use lapix::Event;
+use crate::{Effect, UiEvent};
+use lapix::{BlendMode, Event, GroupId, LayerGroup, LayerId, Point, Reference};
+
+const INDENT: f32 = 12.;
+const NAME_WIDTH: f32 = 80.;
 
 pub struct LayersPanel {
     num_layers: usize,
     active_layer: usize,
+    layers_id: Vec<LayerId>,
     layers_vis: Vec<bool>,
     layers_alpha: Vec<String>,
+    layers_blend: Vec<BlendMode>,
+    layers_locked: Vec<bool>,
+    layers_alpha_locked: Vec<bool>,
+    layers_name: Vec<String>,
+    layers_group: Vec<Option<GroupId>>,
+    groups: Vec<(LayerGroup, String)>,
+    layers_reference: Vec<Option<Reference>>,
+    layers_mask: Vec<bool>,
+    editing_mask: bool,
+    /// Layer being renamed, and its new name so far
+    renaming: Option<(LayerId, String)>,
+    rename_started: bool,
 }
 
 impl LayersPanel {
@@ -13,22 +29,86 @@ impl LayersPanel {
         Self {
             num_layers: 1,
             active_layer: 0,
+            layers_id: Vec::new(),
             layers_vis: vec![true],
             layers_alpha: vec!["255".to_owned()],
+            layers_blend: vec![BlendMode::Normal],
+            layers_locked: vec![false],
+            layers_alpha_locked: vec![false],
+            layers_name: vec![String::new()],
+            layers_group: vec![None],
+            groups: Vec::new(),
+            layers_reference: vec![None],
+            layers_mask: vec![false],
+            editing_mask: false,
+            renaming: None,
+            rename_started: false,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn sync(
         &mut self,
         num_layers: usize,
         active_layer: usize,
+        layers_id: Vec<LayerId>,
         layers_vis: Vec<bool>,
         layers_alpha: Vec<u8>,
+        layers_blend: Vec<BlendMode>,
+        layers_locked: Vec<bool>,
+        layers_alpha_locked: Vec<bool>,
+        layers_name: Vec<String>,
+        layers_group: Vec<Option<GroupId>>,
+        groups: Vec<LayerGroup>,
+        layers_reference: Vec<Option<Reference>>,
+        layers_mask: Vec<bool>,
+        editing_mask: bool,
     ) {
         self.active_layer = active_layer;
         self.num_layers = num_layers;
+        self.layers_id = layers_id;
         self.layers_vis = layers_vis;
         self.layers_alpha = layers_alpha.into_iter().map(|x| x.to_string()).collect();
+        self.layers_blend = layers_blend;
+        self.layers_locked = layers_locked;
+        self.layers_alpha_locked = layers_alpha_locked;
+        self.layers_name = layers_name;
+        self.layers_group = layers_group;
+        self.groups = groups
+            .into_iter()
+            .map(|group| {
+                let alpha = group.opacity().to_string();
+                (group, alpha)
+            })
+            .collect();
+        self.layers_reference = layers_reference;
+        self.layers_mask = layers_mask;
+        self.editing_mask = editing_mask;
+    }
+
+    /// The groups containing a layer, from the outermost to the innermost
+    fn group_chain(&self, layer: usize) -> Vec<GroupId> {
+        let mut chain = Vec::new();
+        let mut id = self.layers_group[layer];
+
+        while let Some(group) = id.and_then(|id| self.groups.iter().find(|g| g.0.id() == id)) {
+            chain.insert(0, group.0.id());
+            id = group.0.parent();
+        }
+
+        chain
+    }
+
+    fn any_collapsed(&self, ids: &[GroupId]) -> bool {
+        ids.iter()
+            .any(|id| self.groups[self.group_index(*id)].0.collapsed())
+    }
+
+    fn group_index(&self, id: GroupId) -> usize {
+        self.groups
+            .iter()
+            .position(|group| group.0.id() == id)
+            .expect("layer groups are synced")
     }
 
     pub fn update(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
@@ -37,34 +117,108 @@ impl LayersPanel {
         egui::Window::new("Layers")
             //    .default_pos((15., 410.))
             .show(egui_ctx, |ui| {
-                let btn = ui.button("+");
-                if btn.clicked() {
-                    events.push(Event::NewLayerAbove.into());
-                    events.push(Event::SwitchLayer(self.num_layers).into());
-                }
+                ui.horizontal(|ui| {
+                    let btn = ui.button("+");
+                    if btn.clicked() {
+                        events.push(UiEvent::NewLayer.into());
+                    }
+                    let btn = ui.button("Flatten").on_hover_text("merge all layers");
+                    if btn.clicked() {
+                        events.push(Event::FlattenImage.into());
+                    }
+                    let mut editing_mask = self.editing_mask;
+                    let checkbox = ui
+                        .checkbox(&mut editing_mask, "Edit mask")
+                        .on_hover_text("draw on the mask of the active layer, if it has one");
+                    if checkbox.changed() {
+                        events.push(Event::EditLayerMask(editing_mask).into());
+                    }
+                });
 
                 ui.horizontal(|ui| {
                     ui.label("#");
                     ui.separator();
+                    ui.add_sized((NAME_WIDTH, 0.), egui::Label::new("name"));
+                    ui.separator();
                     ui.label("act.");
                     ui.separator();
                     ui.label("vis.");
                     ui.separator();
                     ui.label("alpha");
+                    ui.separator();
+                    ui.label("blend");
+                    ui.separator();
+                    ui.label("lock");
+                    ui.separator();
+                    ui.label("mask");
                 });
 
+                // Groups whose header was already shown, from the outermost
+                let mut open_groups: Vec<GroupId> = Vec::new();
                 for i in 0..self.num_layers {
                     let i = self.num_layers - i - 1;
+                    let chain = self.group_chain(i);
+                    while !chain.starts_with(&open_groups) {
+                        open_groups.pop();
+                    }
+                    for depth in open_groups.len()..chain.len() {
+                        if !self.any_collapsed(&chain[..depth]) {
+                            events.append(&mut self.group_row(ui, chain[depth], depth));
+                        }
+                        open_groups.push(chain[depth]);
+                    }
+                    if self.any_collapsed(&chain) {
+                        continue;
+                    }
+                    let id = self.layers_id[i];
+
                     ui.horizontal(|ui| {
+                        ui.add_space(chain.len() as f32 * INDENT);
                         ui.label((i + 1).to_string());
                         ui.separator();
-                        let tooltip = format!("select layer {}", i + 1);
-                        if ui
-                            .radio(i == self.active_layer, "")
-                            .on_hover_text(tooltip)
-                            .clicked()
-                        {
-                            events.push(Event::SwitchLayer(i).into());
+                        match self.renaming.as_mut() {
+                            Some((renaming, name)) if *renaming == id => {
+                                let edit = ui.add(
+                                    egui::widgets::TextEdit::singleline(name)
+                                        .desired_width(NAME_WIDTH),
+                                );
+                                if self.rename_started {
+                                    edit.request_focus();
+                                    self.rename_started = false;
+                                }
+                                if edit.lost_focus() {
+                                    events.push(Event::RenameLayer(id, name.clone()).into());
+                                    self.renaming = None;
+                                }
+                            }
+                            _ => {
+                                let label = egui::Label::new(&self.layers_name[i])
+                                    .wrap(false)
+                                    .sense(egui::Sense::click());
+                                if ui
+                                    .add_sized((NAME_WIDTH, 0.), label)
+                                    .on_hover_text("double-click to rename")
+                                    .double_clicked()
+                                {
+                                    self.renaming = Some((id, self.layers_name[i].clone()));
+                                    self.rename_started = true;
+                                }
+                            }
+                        }
+                        ui.separator();
+                        let reference = self.layers_reference[i];
+                        if reference.is_some() {
+                            ui.add_enabled(false, egui::RadioButton::new(false, ""))
+                                .on_disabled_hover_text("reference layers can't be drawn on");
+                        } else {
+                            let tooltip = format!("select layer {}", i + 1);
+                            if ui
+                                .radio(i == self.active_layer, "")
+                                .on_hover_text(tooltip)
+                                .clicked()
+                            {
+                                events.push(Event::SwitchLayer(id).into());
+                            }
                         }
                         ui.separator();
                         let tooltip = format!("toggle visibility of layer {}", i + 1);
@@ -74,7 +228,7 @@ impl LayersPanel {
                             .clicked()
                         {
                             events
-                                .push(Event::ChangeLayerVisibility(i, !self.layers_vis[i]).into());
+                                .push(Event::ChangeLayerVisibility(id, !self.layers_vis[i]).into());
                         }
                         ui.separator();
                         let text_edit = ui.add(
@@ -84,37 +238,148 @@ impl LayersPanel {
 
                         if text_edit.changed() {
                             if let Ok(opacity) = self.layers_alpha[i].parse() {
-                                events.push(Event::ChangeLayerOpacity(i, opacity).into());
+                                events.push(Event::ChangeLayerOpacity(id, opacity).into());
+                            }
+                        }
+                        ui.separator();
+                        let mut mode = self.layers_blend[i];
+                        egui::ComboBox::from_id_source(("blend_mode", i))
+                            .width(70.)
+                            .selected_text(mode.name())
+                            .show_ui(ui, |ui| {
+                                for option in BlendMode::ALL {
+                                    ui.selectable_value(&mut mode, option, option.name());
+                                }
+                            });
+                        if mode != self.layers_blend[i] {
+                            events.push(Event::ChangeLayerBlendMode(id, mode).into());
+                        }
+                        ui.separator();
+                        let locked = self.layers_locked[i];
+                        if ui
+                            .selectable_label(locked, "🔒")
+                            .on_hover_text(format!("lock layer {} (prevent drawing)", i + 1))
+                            .clicked()
+                        {
+                            events.push(Event::SetLayerLock(id, !locked).into());
+                        }
+                        let alpha_locked = self.layers_alpha_locked[i];
+                        if ui
+                            .selectable_label(alpha_locked, "α")
+                            .on_hover_text(format!(
+                                "lock alpha of layer {} (only draw on non-transparent pixels)",
+                                i + 1
+                            ))
+                            .clicked()
+                        {
+                            events.push(Event::SetLayerAlphaLock(id, !alpha_locked).into());
+                        }
+                        ui.separator();
+                        if let Some(reference) = reference {
+                            ui.menu_button("ref", |ui| {
+                                let mut offset = reference.offset();
+                                let mut scale = reference.scale();
+                                ui.horizontal(|ui| {
+                                    ui.label("offset");
+                                    let x = ui.add(egui::DragValue::new(&mut offset.x));
+                                    let y = ui.add(egui::DragValue::new(&mut offset.y));
+                                    if x.changed() || y.changed() {
+                                        let offset = Point::new(offset.x, offset.y);
+                                        events.push(Event::SetReferenceOffset(id, offset).into());
+                                    }
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("scale");
+                                    let drag = egui::DragValue::new(&mut scale)
+                                        .speed(0.01)
+                                        .clamp_range(0.01..=100.);
+                                    if ui.add(drag).changed() {
+                                        events.push(Event::SetReferenceScale(id, scale).into());
+                                    }
+                                });
+                            })
+                            .response
+                            .on_hover_text(format!("placement of reference layer {}", i + 1));
+                        } else if self.layers_mask[i] {
+                            ui.menu_button("M", |ui| {
+                                if ui.button("Invert").clicked() {
+                                    events.push(Event::InvertLayerMask(id).into());
+                                    ui.close_menu();
+                                }
+                                if ui.button("Apply").clicked() {
+                                    events.push(Event::ApplyLayerMask(id).into());
+                                    ui.close_menu();
+                                }
+                                if ui.button("Remove").clicked() {
+                                    events.push(Event::RemoveLayerMask(id).into());
+                                    ui.close_menu();
+                                }
+                            })
+                            .response
+                            .on_hover_text(format!("mask of layer {}", i + 1));
+                        } else {
+                            let btn = ui
+                                .button("+")
+                                .on_hover_text(format!("add a mask to layer {}", i + 1));
+                            if btn.clicked() {
+                                events.push(Event::AddLayerMask(id).into());
                             }
                         }
                         // Move layer below button
-                        ui.add_enabled_ui(i > 0, |ui| {
+                        ui.add_enabled_ui(i > 0 || !chain.is_empty(), |ui| {
                             let btn = ui.button("v");
                             if btn.clicked() {
-                                events.push(Event::MoveLayerDown(i).into());
-                                events.push(Event::SwitchLayer(i - 1).into());
+                                // The active layer follows the moved layer
+                                events.push(Event::SwitchLayer(id).into());
+                                events.push(Event::MoveLayerDown(id).into());
                             }
                         });
                         // Move layer above button
-                        ui.add_enabled_ui(i < self.num_layers - 1, |ui| {
+                        ui.add_enabled_ui(i < self.num_layers - 1 || !chain.is_empty(), |ui| {
                             let btn = ui.button("^");
                             if btn.clicked() {
-                                events.push(Event::MoveLayerUp(i).into());
-                                events.push(Event::SwitchLayer(i + 1).into());
+                                events.push(Event::SwitchLayer(id).into());
+                                events.push(Event::MoveLayerUp(id).into());
+                            }
+                        });
+                        // Group layer button
+                        let btn = ui
+                            .button("g")
+                            .on_hover_text(format!("put layer {} in a new group", i + 1));
+                        if btn.clicked() {
+                            events.push(Event::GroupLayers(id, id).into());
+                        }
+                        // Duplicate layer button
+                        let btn = ui
+                            .button("d")
+                            .on_hover_text(format!("duplicate layer {}", i + 1));
+                        if btn.clicked() {
+                            events.push(Event::DuplicateLayer(id).into());
+                        }
+                        // Merge layer down button
+                        let mergeable =
+                            i > 0 && reference.is_none() && self.layers_reference[i - 1].is_none();
+                        ui.add_enabled_ui(mergeable, |ui| {
+                            let btn = ui
+                                .button("m")
+                                .on_hover_text(format!("merge layer {} down", i + 1));
+                            if btn.clicked() {
+                                events.push(Event::MergeLayerDown(id).into());
                             }
                         });
                         // Delete layer button
                         ui.add_enabled_ui(self.num_layers > 1, |ui| {
                             let btn = ui.button("x");
                             if btn.clicked() {
-                                events.push(Event::DeleteLayer(i).into());
+                                events.push(Event::DeleteLayer(id).into());
 
                                 let select_layer = match self.active_layer {
-                                    x if i > x => self.active_layer,
-                                    x if i == x && i == 0 => 0,
-                                    _ => self.active_layer - 1,
+                                    x if i != x => x,
+                                    0 => 1,
+                                    x => x - 1,
                                 };
-                                events.push(Event::SwitchLayer(select_layer).into());
+                                let select_id = self.layers_id[select_layer];
+                                events.push(Event::SwitchLayer(select_id).into());
                             }
                         });
                     });
@@ -123,4 +388,60 @@ impl LayersPanel {
 
         events
     }
+
+    fn group_row(&mut self, ui: &mut egui::Ui, id: GroupId, depth: usize) -> Vec<Effect> {
+        let mut events = Vec::new();
+        let index = self.group_index(id);
+
+        ui.horizontal(|ui| {
+            ui.add_space(depth as f32 * INDENT);
+            let (group, alpha) = &mut self.groups[index];
+            let collapsed = group.collapsed();
+            let btn = ui
+                .button(if collapsed { ">" } else { "v" })
+                .on_hover_text(if collapsed {
+                    "expand group"
+                } else {
+                    "collapse group"
+                });
+            if btn.clicked() {
+                events.push(Event::SetGroupCollapsed(id, !collapsed).into());
+            }
+            ui.label("group");
+            ui.separator();
+            if ui
+                .radio(group.visible(), "")
+                .on_hover_text("toggle visibility of the group")
+                .clicked()
+            {
+                events.push(Event::ChangeGroupVisibility(id, !group.visible()).into());
+            }
+            ui.separator();
+            let text_edit = ui.add(egui::widgets::TextEdit::singleline(alpha).desired_width(30.0));
+            if text_edit.changed() {
+                if let Ok(opacity) = alpha.parse() {
+                    events.push(Event::ChangeGroupOpacity(id, opacity).into());
+                }
+            }
+            ui.separator();
+            if ui.button("v").on_hover_text("move group down").clicked() {
+                events.push(Event::MoveGroupDown(id).into());
+            }
+            if ui.button("^").on_hover_text("move group up").clicked() {
+                events.push(Event::MoveGroupUp(id).into());
+            }
+            if ui.button("u").on_hover_text("ungroup").clicked() {
+                events.push(Event::Ungroup(id).into());
+            }
+            if ui
+                .button("x")
+                .on_hover_text("delete the group and its layers")
+                .clicked()
+            {
+                events.push(Event::DeleteGroup(id).into());
+            }
+        });
+
+        events
+    }
 }
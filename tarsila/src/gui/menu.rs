@@ -1,17 +1,25 @@
 use crate::{Effect, UiEvent};
-use lapix::{Event, Size, Tool, Transform};
+use lapix::{AnimationExport, Event, Size, Tool, Transform};
 use std::path::PathBuf;
 
 pub struct MenuBar {
     last_file: Option<PathBuf>,
     show_resize_window: bool,
     show_spritesheet_window: bool,
+    show_history_limit_window: bool,
+    show_animation_window: bool,
     show_confirm_exit_window: bool,
     show_confirm_new_window: bool,
     canvas_size: Size<i32>,
     spritesheet: Size<u8>,
+    history_limit: usize,
+    journaling: bool,
+    /// Names of the animation tags
+    tags: Vec<String>,
+    animation_export: AnimationExport,
     canvas_size_str: Option<(String, String)>,
     spritesheet_str: Option<(String, String)>,
+    history_limit_str: Option<String>,
 }
 
 impl MenuBar {
@@ -20,24 +28,46 @@ impl MenuBar {
             last_file: None,
             show_resize_window: false,
             show_spritesheet_window: false,
+            show_history_limit_window: false,
+            show_animation_window: false,
             show_confirm_exit_window: false,
             show_confirm_new_window: false,
             canvas_size: Size::ZERO,
             spritesheet: (1, 1).into(),
+            history_limit: lapix::DEFAULT_HISTORY_LIMIT,
+            journaling: false,
+            tags: Vec::new(),
+            animation_export: AnimationExport::default(),
             canvas_size_str: None,
             spritesheet_str: None,
+            history_limit_str: None,
         }
     }
 
-    pub fn sync(&mut self, canvas_size: Size<i32>, spritesheet: Size<u8>) {
+    pub fn sync(
+        &mut self,
+        canvas_size: Size<i32>,
+        spritesheet: Size<u8>,
+        history_limit: usize,
+        journaling: bool,
+        tags: Vec<String>,
+    ) {
         self.canvas_size = canvas_size;
         self.spritesheet = spritesheet;
+        self.history_limit = history_limit;
+        self.journaling = journaling;
+        if self.animation_export.tag.is_some_and(|i| i >= tags.len()) {
+            self.animation_export.tag = None;
+        }
+        self.tags = tags;
     }
 
     pub fn update(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
         let mut events = self.update_menu(egui_ctx);
         events.append(&mut self.update_resize_window(egui_ctx));
         events.append(&mut self.update_spritesheet_window(egui_ctx));
+        events.append(&mut self.update_history_limit_window(egui_ctx));
+        events.append(&mut self.update_animation_window(egui_ctx));
         events.append(&mut self.update_confirm_exit_window(egui_ctx));
         events.append(&mut self.update_confirm_new_window(egui_ctx));
         events
@@ -99,6 +129,22 @@ impl MenuBar {
                             events.push(Event::Save(path).into());
                         }
                     }
+                    if ui.button("Export Animation").clicked() {
+                        ui.close_menu();
+                        self.show_animation_window = true;
+                    }
+                    if ui.button("Export Layers").clicked() {
+                        ui.close_menu();
+                        let mut dialog = rfd::FileDialog::new();
+
+                        if let Some(dir) = self.last_file.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir);
+                        }
+
+                        if let Some(dir) = dialog.pick_folder() {
+                            events.push(Event::SaveLayers(dir).into());
+                        }
+                    }
                     if ui.button("Import Image").clicked() {
                         ui.close_menu();
                         let mut dialog = rfd::FileDialog::new()
@@ -116,11 +162,78 @@ impl MenuBar {
                             events.push(Event::SetTool(Tool::Move).into());
                         }
                     }
+                    if ui.button("Import Reference").clicked() {
+                        ui.close_menu();
+                        let mut dialog = rfd::FileDialog::new()
+                            .add_filter("All files", &["*"])
+                            .add_filter("PNG files", &["png"])
+                            .add_filter("JPEG files", &["jpg", "jpeg"]);
+
+                        if let Some(dir) = self.last_file.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir);
+                        }
+
+                        if let Some(path) = dialog.pick_file() {
+                            self.last_file = Some(path.clone());
+                            events.push(Event::ImportReference(path).into());
+                        }
+                    }
+                    if self.journaling {
+                        if ui.button("Stop Recording Journal").clicked() {
+                            ui.close_menu();
+                            events.push(UiEvent::StopJournal.into());
+                        }
+                    } else if ui.button("Record Journal").clicked() {
+                        ui.close_menu();
+                        let mut dialog = rfd::FileDialog::new()
+                            .add_filter("Journal files", &["journal"])
+                            .add_filter("All files", &["*"]);
+
+                        if let Some(dir) = self.last_file.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir).set_file_name("session.journal");
+                        }
+
+                        if let Some(path) = dialog.save_file() {
+                            events.push(UiEvent::StartJournal(path).into());
+                        }
+                    }
+                    if ui.button("Replay Journal").clicked() {
+                        ui.close_menu();
+                        let mut dialog = rfd::FileDialog::new()
+                            .add_filter("Journal files", &["journal"])
+                            .add_filter("All files", &["*"]);
+
+                        if let Some(dir) = self.last_file.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir);
+                        }
+
+                        if let Some(path) = dialog.pick_file() {
+                            events.push(UiEvent::ReplayJournal(path).into());
+                        }
+                    }
                     if ui.button("Exit").clicked() {
                         self.show_confirm_exit_window = true;
                         ui.close_menu();
                     }
                 });
+                ui.menu_button("Edit", |ui| {
+                    if ui.button("Undo").clicked() {
+                        events.push(Event::Undo.into());
+                        ui.close_menu();
+                    }
+                    if ui.button("Redo").clicked() {
+                        events.push(Event::Redo.into());
+                        ui.close_menu();
+                    }
+                    if ui.button("Selection to New Layer").clicked() {
+                        events.push(Event::SelectionToNewLayer.into());
+                        ui.close_menu();
+                    }
+                    if ui.button("History Limit").clicked() {
+                        ui.close_menu();
+                        self.show_history_limit_window = true;
+                    }
+                });
                 ui.menu_button("View", |ui| {
                     if ui.button("Zoom in").clicked() {
                         events.push(Effect::UiEvent(UiEvent::ZoomIn));
@@ -151,7 +264,7 @@ impl MenuBar {
                         ui.close_menu();
                         self.show_resize_window = true;
                     }
-                    if ui.button("Change Spritesheet").clicked() {
+                    if ui.button("Export Layout").clicked() {
                         ui.close_menu();
                         self.show_spritesheet_window = true;
                     }
@@ -238,7 +351,7 @@ impl MenuBar {
                 ));
             }
 
-            egui::Window::new("Spritesheet")
+            egui::Window::new("Export Layout")
                 .default_pos((200., 30.))
                 .show(egui_ctx, |ui| {
                     ui.horizontal(|ui| {
@@ -259,9 +372,9 @@ impl MenuBar {
                         )
                         .labelled_by(label.id);
                         if ui.button("Ok").clicked() {
-                            if let (Ok(w), Ok(h)) = (
-                                self.spritesheet_str.as_ref().unwrap().0.parse(),
-                                self.spritesheet_str.as_ref().unwrap().1.parse(),
+                            if let (Ok(w @ 1..), Ok(h @ 1..)) = (
+                                self.spritesheet_str.as_ref().unwrap().0.parse::<u8>(),
+                                self.spritesheet_str.as_ref().unwrap().1.parse::<u8>(),
                             ) {
                                 events.push(Event::SetSpritesheet((w, h).into()).into());
                             }
@@ -279,6 +392,120 @@ impl MenuBar {
         events
     }
 
+    fn update_history_limit_window(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
+        let mut events = Vec::new();
+
+        if self.show_history_limit_window {
+            if self.history_limit_str.is_none() {
+                self.history_limit_str = Some(self.history_limit.to_string());
+            }
+
+            egui::Window::new("History Limit")
+                .default_pos((200., 30.))
+                .show(egui_ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        let label = ui.label("actions:");
+                        ui.add(
+                            egui::widgets::TextEdit::singleline(
+                                self.history_limit_str.as_mut().unwrap(),
+                            )
+                            .desired_width(40.0),
+                        )
+                        .labelled_by(label.id);
+                        if ui.button("Ok").clicked() {
+                            if let Ok(limit) = self.history_limit_str.as_ref().unwrap().parse() {
+                                events.push(Event::SetHistoryLimit(limit).into());
+                            }
+                            self.history_limit_str = None;
+                            self.show_history_limit_window = false;
+                        }
+                        if ui.button("cancel").clicked() {
+                            self.history_limit_str = None;
+                            self.show_history_limit_window = false;
+                        }
+                    });
+                });
+        }
+
+        events
+    }
+
+    fn update_animation_window(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
+        let mut events = Vec::new();
+
+        if !self.show_animation_window {
+            return events;
+        }
+
+        let settings = &mut self.animation_export;
+        egui::Window::new("Export Animation")
+            .default_pos((200., 30.))
+            .show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("frames:");
+                    let selected = settings.tag.map_or("all frames", |i| self.tags[i].as_str());
+                    egui::ComboBox::from_id_source("export_tag")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut settings.tag, None, "all frames");
+                            for (i, name) in self.tags.iter().enumerate() {
+                                ui.selectable_value(&mut settings.tag, Some(i), name);
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    let mut forever = settings.repeat.is_none();
+                    ui.checkbox(&mut forever, "loop forever");
+                    if !forever {
+                        let repeat = settings.repeat.get_or_insert(0);
+                        ui.label("repeat:");
+                        ui.add(egui::DragValue::new(repeat))
+                            .on_hover_text("times played after the first one");
+                    } else {
+                        settings.repeat = None;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("scale:");
+                    ui.add(egui::DragValue::new(&mut settings.scale).clamp_range(1..=16))
+                        .on_hover_text("size of each pixel in the exported image");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("alpha threshold:");
+                    ui.add(egui::DragValue::new(&mut settings.alpha_threshold))
+                        .on_hover_text(
+                            "GIF pixels are either opaque or transparent: pixels with a lower \
+                            alpha become transparent",
+                        );
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("export").clicked() {
+                        self.show_animation_window = false;
+                        let mut dialog = rfd::FileDialog::new()
+                            .add_filter("GIF files", &["gif"])
+                            .add_filter("APNG files", &["png", "apng"])
+                            .add_filter("WebP files", &["webp"])
+                            .add_filter("All files", &["*"]);
+
+                        if let Some(dir) = self.last_file.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir);
+                        }
+
+                        if let Some(path) = dialog.save_file() {
+                            self.last_file = Some(path.clone());
+                            events.push(Event::ExportAnimation(path, *settings).into());
+                        }
+                    }
+                    if ui.button("cancel").clicked() {
+                        self.show_animation_window = false;
+                    }
+                });
+            });
+
+        events
+    }
+
     fn update_confirm_exit_window(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
         let mut events = Vec::new();
 
@@ -1,18 +1,28 @@
 use crate::{Effect, UiEvent, UiState};
-use lapix::{Position, Size, Tool};
+use lapix::{
+    AnimationTag, BlendMode, FrameId, GroupId, HistoryEntry, LayerGroup, LayerId, OnionSkin,
+    Position, Reference, Size, Tool,
+};
 use macroquad::prelude::*;
+use std::path::PathBuf;
 
+mod frames;
+mod history;
 mod layers;
 mod menu;
 mod palette;
 mod preview;
+mod recovery;
 mod status;
 mod toolbar;
 
+use frames::FramesPanel;
+use history::HistoryPanel;
 use layers::LayersPanel;
 use menu::MenuBar;
 use palette::Palette;
 use preview::Preview;
+use recovery::RecoveryWindow;
 use status::StatusBar;
 use toolbar::Toolbar;
 
@@ -21,8 +31,23 @@ pub struct GuiSyncParams {
     pub main_color: [u8; 4],
     pub num_layers: usize,
     pub active_layer: usize,
+    pub layers_id: Vec<LayerId>,
     pub layers_vis: Vec<bool>,
     pub layers_alpha: Vec<u8>,
+    pub layers_blend: Vec<BlendMode>,
+    pub layers_locked: Vec<bool>,
+    pub layers_alpha_locked: Vec<bool>,
+    pub layers_name: Vec<String>,
+    pub layers_group: Vec<Option<GroupId>>,
+    pub groups: Vec<LayerGroup>,
+    pub layers_reference: Vec<Option<Reference>>,
+    pub layers_mask: Vec<bool>,
+    pub editing_mask: bool,
+    pub frames: Vec<FrameId>,
+    pub active_frame: usize,
+    pub frames_duration: Vec<u32>,
+    pub tags: Vec<AnimationTag>,
+    pub onion_skin: OnionSkin,
     pub palette: Vec<[u8; 4]>,
     pub mouse_canvas: Position<i32>,
     pub is_on_canvas: bool,
@@ -30,17 +55,27 @@ pub struct GuiSyncParams {
     pub visible_pixel_on_mouse: Option<[u8; 4]>,
     pub canvas_size: Size<i32>,
     pub spritesheet: Size<u8>,
+    pub history_limit: usize,
+    /// The undo history, only when it changed since the last sync
+    pub history: Option<Vec<HistoryEntry>>,
+    pub journaling: bool,
+    pub recovered_session: Option<PathBuf>,
     pub zoom: f32,
     pub fps: f32,
+    /// Message to show in the status bar
+    pub notice: Option<String>,
 }
 
 pub struct Gui {
     toolbar: Toolbar,
     layers_panel: LayersPanel,
+    frames_panel: FramesPanel,
+    history_panel: HistoryPanel,
     preview: Preview,
     palette: Palette,
     status_bar: StatusBar,
     menu: MenuBar,
+    recovery_window: RecoveryWindow,
     mouse_on_canvas: bool,
     selected_tool: Tool,
 }
@@ -50,10 +85,13 @@ impl Gui {
         Self {
             toolbar: Toolbar::new(),
             layers_panel: LayersPanel::new(),
+            frames_panel: FramesPanel::new(),
+            history_panel: HistoryPanel::new(),
             preview: Preview::new(),
             palette: Palette::new(),
             status_bar: StatusBar::new(),
             menu: MenuBar::new(),
+            recovery_window: RecoveryWindow::new(),
             mouse_on_canvas: false,
             selected_tool: Tool::Brush,
         }
@@ -67,17 +105,52 @@ impl Gui {
         self.layers_panel.sync(
             params.num_layers,
             params.active_layer,
+            params.layers_id.clone(),
             params.layers_vis.clone(),
             params.layers_alpha.clone(),
+            params.layers_blend.clone(),
+            params.layers_locked.clone(),
+            params.layers_alpha_locked.clone(),
+            params.layers_name.clone(),
+            params.layers_group.clone(),
+            params.groups.clone(),
+            params.layers_reference.clone(),
+            params.layers_mask.clone(),
+            params.editing_mask,
+        );
+        self.frames_panel.sync(
+            params.frames.clone(),
+            params.active_frame,
+            params.frames_duration.clone(),
+            params.tags.clone(),
+            params.onion_skin,
         );
         self.preview.sync(
-            params.spritesheet,
+            params
+                .tags
+                .iter()
+                .map(|tag| tag.name().to_owned())
+                .collect(),
             params.canvas_size,
             params.layers_vis.clone(),
             params.layers_alpha.clone(),
         );
+        if let Some(history) = params.history.clone() {
+            self.history_panel.sync(history);
+        }
         self.palette.sync(params.palette.clone());
-        self.menu.sync(params.canvas_size, params.spritesheet);
+        self.recovery_window.sync(params.recovered_session.clone());
+        self.menu.sync(
+            params.canvas_size,
+            params.spritesheet,
+            params.history_limit,
+            params.journaling,
+            params
+                .tags
+                .iter()
+                .map(|tag| tag.name().to_owned())
+                .collect(),
+        );
         self.status_bar.sync(params);
     }
 
@@ -120,9 +193,18 @@ impl Gui {
             let mut layers_events = self.layers_panel.update(egui_ctx);
             events.append(&mut layers_events);
 
+            let mut frames_events = self.frames_panel.update(egui_ctx);
+            events.append(&mut frames_events);
+
+            let mut history_events = self.history_panel.update(egui_ctx);
+            events.append(&mut history_events);
+
             let mut menu_events = self.menu.update(egui_ctx);
             events.append(&mut menu_events);
 
+            let mut recovery_events = self.recovery_window.update(egui_ctx);
+            events.append(&mut recovery_events);
+
             self.preview.update(egui_ctx);
             self.status_bar.update(egui_ctx);
 
@@ -1,11 +1,12 @@
 use crate::UiState;
-use lapix::{Position, Rect, Size};
+use lapix::{Position, Rect, Reference, Size};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const MS_PER_FRAME: usize = 100;
-
 pub struct Preview {
-    spritesheet: Size<u8>,
+    /// Names of the animation tags, and the index of the one being played (or
+    /// `None` to play all frames)
+    tags: Vec<String>,
+    tag: Option<usize>,
     canvas_size: Size<i32>,
     image: egui::ColorImage,
     texture: Option<egui::TextureHandle>,
@@ -19,7 +20,8 @@ impl Preview {
     pub fn new() -> Self {
         let bytes = [0, 0, 0, 0];
         Self {
-            spritesheet: (1, 1).into(),
+            tags: Vec::new(),
+            tag: None,
             canvas_size: (0, 0).into(),
             image: egui::ColorImage::from_rgba_unmultiplied([1, 1], &bytes),
             texture: None,
@@ -32,12 +34,15 @@ impl Preview {
 
     pub fn sync(
         &mut self,
-        spritesheet: Size<u8>,
+        tags: Vec<String>,
         canvas_size: Size<i32>,
         layers_vis: Vec<bool>,
         layers_alpha: Vec<u8>,
     ) {
-        self.spritesheet = spritesheet;
+        if self.tag.is_some_and(|tag| tag >= tags.len()) {
+            self.tag = None;
+        }
+        self.tags = tags;
         self.layers_vis = layers_vis;
         self.layers_alpha = layers_alpha;
         self.canvas_size = canvas_size;
@@ -53,17 +58,26 @@ impl Preview {
                         egui::widgets::TextEdit::singleline(&mut self.scale).desired_width(30.0),
                     )
                     .labelled_by(label.id);
+
+                    let selected = self.tag.map_or("all frames", |i| self.tags[i].as_str());
+                    egui::ComboBox::from_id_source("preview_tag")
+                        .width(80.)
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.tag, None, "all frames");
+                            for (i, name) in self.tags.iter().enumerate() {
+                                ui.selectable_value(&mut self.tag, Some(i), name);
+                            }
+                        });
                 });
                 let scroll_area = egui::ScrollArea::new([true, true]);
                 scroll_area.show_viewport(ui, |ui, viewport| {
-                    let frame_ratios = self.frame_ratios();
-
                     let tex: &egui::TextureHandle = self.texture.get_or_insert_with(|| {
                         ui.ctx()
                             .load_texture("", self.image.clone(), egui::TextureOptions::NEAREST)
                     });
-                    let frame_size = frame_ratios
-                        * egui::vec2(self.canvas_size.x as f32, self.canvas_size.y as f32);
+                    let frame_size =
+                        egui::vec2(self.canvas_size.x as f32, self.canvas_size.y as f32);
                     let scale = self.scale.parse().unwrap_or(1.);
 
                     let image = egui::Image::new(tex, frame_size * scale)
@@ -93,56 +107,82 @@ impl Preview {
         use macroquad::prelude::*;
 
         if let Some((offset, rect)) = self.config {
-            let frames = self.spritesheet.x as usize * self.spritesheet.y as usize;
             let t = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_millis();
-            let frame = (t as usize / MS_PER_FRAME) % frames;
-            let frame_size: Size<f32> = (
-                (self.canvas_size.x as usize / self.spritesheet.x as usize) as f32,
-                (self.canvas_size.y as usize / self.spritesheet.y as usize) as f32,
-            )
-                .into();
-            let frame = Rect {
-                x: ((frame % (self.spritesheet.x as usize)) as f32) * frame_size.x,
-                y: ((frame / (self.spritesheet.x as usize)) as f32) * frame_size.y,
-                w: frame_size.x,
-                h: frame_size.y,
-            };
+            let frame = state.frame_at_time(self.tag, t as u64);
             let preview_scale = self.scale.parse().unwrap_or(1.);
             let scrollarea_frame = Rect {
-                x: frame.x + (offset.x / preview_scale),
-                y: frame.y + (offset.y / preview_scale),
+                x: offset.x / preview_scale,
+                y: offset.y / preview_scale,
                 w: rect.w / preview_scale,
                 h: rect.h / preview_scale,
             };
 
-            for i in 0..state.num_layers() {
-                if !state.layer(i).visible() {
-                    continue;
-                }
+            // The active frame is drawn from the layer textures, which are
+            // always up to date, and the other ones from their composited
+            // textures. Reference layers are not part of the blended texture,
+            // so they are drawn over it
+            let blended = if frame == state.active_frame() {
+                state.blended_tex()
+            } else {
+                state.frame_tex(frame)
+            };
+            let textures: Vec<_> = blended
+                .map(|texture| (texture, 255, Reference::default()))
+                .into_iter()
+                .chain(
+                    (0..state.num_layers())
+                        .filter(|i| state.layer_visible(*i))
+                        .filter_map(|i| match state.layer_reference(i) {
+                            Some(reference) => Some((reference, i)),
+                            None if blended.is_none() => {
+                                Some((Reference::new(state.layer_offset(i), 1.), i))
+                            }
+                            None => None,
+                        })
+                        .map(|(reference, i)| {
+                            (state.layer_tex(i), state.layer_opacity(i), reference)
+                        }),
+                )
+                .collect();
+
+            for (texture, opacity, placement) in textures {
+                // The part of the frame covered by the texture, in canvas
+                // pixels
+                let offset = placement.offset();
+                let scale = placement.scale();
+                let covered = Rect {
+                    x: offset.x as f32,
+                    y: offset.y as f32,
+                    w: texture.width() * scale,
+                    h: texture.height() * scale,
+                };
+                let visible = match covered.intersect(scrollarea_frame) {
+                    Some(visible) => visible,
+                    None => continue,
+                };
 
-                let texture = state.layer_tex(i);
                 let params = DrawTextureParams {
-                    source: Some(scrollarea_frame),
+                    source: Some(Rect {
+                        x: (visible.x - covered.x) / scale,
+                        y: (visible.y - covered.y) / scale,
+                        w: visible.w / scale,
+                        h: visible.h / scale,
+                    }),
                     dest_size: Some(Vec2 {
-                        x: rect.w,
-                        y: rect.h,
+                        x: visible.w * preview_scale,
+                        y: visible.h * preview_scale,
                     }),
                     ..Default::default()
                 };
 
-                let color = [255, 255, 255, state.layer(i).opacity()];
-                draw_texture_ex(texture, rect.x, rect.y, color.into(), params);
+                let x = rect.x + (visible.x - scrollarea_frame.x) * preview_scale;
+                let y = rect.y + (visible.y - scrollarea_frame.y) * preview_scale;
+                let color = [255, 255, 255, opacity];
+                draw_texture_ex(texture, x, y, color.into(), params);
             }
         }
     }
-
-    fn frame_ratios(&self) -> egui::Vec2 {
-        let nx = self.spritesheet.x;
-        let ny = self.spritesheet.y;
-
-        egui::Vec2::new(1. / nx as f32, 1. / ny as f32)
-    }
 }
@@ -0,0 +1,43 @@
+use crate::{Effect, UiEvent};
+use std::path::PathBuf;
+
+pub struct RecoveryWindow {
+    recovered: Option<PathBuf>,
+}
+
+impl RecoveryWindow {
+    pub fn new() -> Self {
+        Self { recovered: None }
+    }
+
+    pub fn sync(&mut self, recovered: Option<PathBuf>) {
+        self.recovered = recovered;
+    }
+
+    pub fn update(&mut self, egui_ctx: &egui::Context) -> Vec<Effect> {
+        let mut events = Vec::new();
+
+        let path = match &self.recovered {
+            Some(path) => path,
+            None => return events,
+        };
+
+        egui::Window::new("Recover Session")
+            .default_pos((200., 30.))
+            .collapsible(false)
+            .show(egui_ctx, |ui| {
+                ui.label("Tarsila did not exit properly last time. Restore the last autosave?");
+                ui.label(path.display().to_string());
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        events.push(UiEvent::RestoreSession.into());
+                    }
+                    if ui.button("Discard").clicked() {
+                        events.push(UiEvent::DiscardRecoveredSession.into());
+                    }
+                });
+            });
+
+        events
+    }
+}
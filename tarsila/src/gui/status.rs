@@ -9,6 +9,7 @@ pub struct StatusBar {
     canvas_size: Size<i32>,
     zoom: f32,
     fps: f32,
+    notice: Option<String>,
 }
 
 impl StatusBar {
@@ -21,6 +22,7 @@ impl StatusBar {
             canvas_size: Size::ZERO,
             zoom: 1.,
             fps: 60.,
+            notice: None,
         }
     }
 
@@ -32,6 +34,7 @@ impl StatusBar {
         self.canvas_size = params.canvas_size;
         self.zoom = params.zoom;
         self.fps = params.fps;
+        self.notice = params.notice;
     }
 
     pub fn update(&mut self, egui_ctx: &egui::Context) {
@@ -68,6 +71,11 @@ impl StatusBar {
                             .on_hover_text("color under cursor");
                     }
                 }
+
+                if let Some(notice) = &self.notice {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::from_rgb(160, 0, 0), notice);
+                }
             });
         });
     }
@@ -257,6 +257,37 @@ impl KeyBindings {
                 .into(),
                 Event::Undo.into(),
             ),
+            // REDO
+            (
+                vec![
+                    InputEvent::KeyModifier(KeyboardModifier::Control),
+                    InputEvent::KeyPress(mq::KeyCode::Y.into()),
+                ]
+                .into(),
+                Event::Redo.into(),
+            ),
+            (
+                vec![
+                    InputEvent::KeyModifier(KeyboardModifier::Control),
+                    InputEvent::KeyModifier(KeyboardModifier::Shift),
+                    InputEvent::KeyPress(mq::KeyCode::Z.into()),
+                ]
+                .into(),
+                Event::Redo.into(),
+            ),
+            // MACROS
+            (
+                InputEvent::KeyPress(mq::KeyCode::F5.into()).into(),
+                Event::StartMacro.into(),
+            ),
+            (
+                InputEvent::KeyPress(mq::KeyCode::F6.into()).into(),
+                Event::StopMacro.into(),
+            ),
+            (
+                InputEvent::KeyPress(mq::KeyCode::F7.into()).into(),
+                UiEvent::PlayMacroNextFrame.into(),
+            ),
             // COPY + PASTE
             (
                 vec![
@@ -1,5 +1,6 @@
 use macroquad::prelude::*;
 
+mod autosave;
 mod bg;
 mod error;
 mod graphics;
@@ -31,10 +32,19 @@ fn window_conf() -> Conf {
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut state = UiState::default();
+    let mut state = UiState::new();
     let mut frame = 0;
 
+    // Closing the window must go through `UiEvent::Exit`, which cleans up
+    prevent_quit();
+
     loop {
+        if is_quit_requested() {
+            if let Err(e) = state.process_event(UiEvent::Exit) {
+                eprintln!("ERROR: {e}");
+            }
+        }
+
         if let Err(e) = state.update(frame) {
             eprintln!("ERROR: {e}");
         }
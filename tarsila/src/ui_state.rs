@@ -1,3 +1,4 @@
+use crate::autosave::Autosave;
 use crate::bg::Background;
 use crate::graphics::DrawContext;
 use crate::gui::{Gui, GuiSyncParams};
@@ -8,10 +9,15 @@ use crate::project;
 use crate::wrapped_image::WrappedImage;
 use crate::{graphics, Result, Timer};
 use lapix::primitives::*;
-use lapix::{Canvas, CanvasEffect, Event, Layer, LoadProject, SaveProject, Selection, State, Tool};
+use lapix::{
+    BlendMode, Canvas, CanvasEffect, Event, FrameId, Ghost, HistoryEntry, LoadProject, Reference,
+    SaveProject, Selection, State, Tool,
+};
 use macroquad::prelude::Color as MqColor;
 use macroquad::prelude::{FilterMode, Texture2D};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
+use std::path::PathBuf;
 use std::time::SystemTime;
 
 pub const WINDOW_W: i32 = 1000;
@@ -23,6 +29,7 @@ const LEFT_TOOLBAR_W: u16 = 300;
 const CAMERA_SPEED: f32 = 12.;
 const BG_COLOR: MqColor = MqColor::new(0.5, 0.5, 0.5, 1.);
 const GUI_REST_MS: u64 = 100;
+const NOTICE_MS: u64 = 3000;
 const FPS_INTERVAL: usize = 15;
 const DEFAULT_ZOOM_LEVEL: f32 = 8.;
 pub const MIN_ZOOM: f32 = 0.125;
@@ -67,6 +74,12 @@ pub enum UiEvent {
     Paste,
     Exit,
     NewProject,
+    StartJournal(PathBuf),
+    StopJournal,
+    ReplayJournal(PathBuf),
+    PlayMacroNextFrame,
+    RestoreSession,
+    DiscardRecoveredSession,
     GuiInteraction,
     SetZoom100,
     SetCursor(CursorType),
@@ -75,6 +88,7 @@ pub enum UiEvent {
     ToolStart,
     ToolStroke,
     ToolEnd,
+    NewLayer,
     BlockCanvas,
     UnblockCanvas,
 }
@@ -91,7 +105,7 @@ impl<'a> From<&'a UiState> for GuiSyncParams {
         let (x, y) = macroquad::prelude::mouse_position();
         let (x, y) = state.screen_to_canvas(x, y);
         let p = (x, y).into();
-        let in_canvas = state.canvas().is_in_bounds(p);
+        let in_canvas = state.is_on_canvas(p);
         let visible_pixel = if in_canvas {
             Some(state.visible_pixel(p))
         } else {
@@ -102,21 +116,72 @@ impl<'a> From<&'a UiState> for GuiSyncParams {
             main_color: state.inner.main_color().into(),
             num_layers: n_layers,
             active_layer: state.inner.layers().active_index(),
+            layers_id: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).id())
+                .collect(),
             layers_vis: (0..n_layers)
                 .map(|i| state.inner.layers().get(i).visible())
                 .collect(),
             layers_alpha: (0..n_layers)
                 .map(|i| state.inner.layers().get(i).opacity())
                 .collect(),
+            layers_blend: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).blend_mode())
+                .collect(),
+            layers_locked: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).locked())
+                .collect(),
+            layers_alpha_locked: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).alpha_locked())
+                .collect(),
+            layers_name: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).name().to_owned())
+                .collect(),
+            layers_group: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).group())
+                .collect(),
+            groups: state.inner.layers().groups().to_vec(),
+            layers_reference: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).reference())
+                .collect(),
+            layers_mask: (0..n_layers)
+                .map(|i| state.inner.layers().get(i).mask().is_some())
+                .collect(),
+            editing_mask: state.inner.layers().editing_mask(),
+            frames: state
+                .inner
+                .layers()
+                .frames()
+                .iter()
+                .map(|frame| frame.id())
+                .collect(),
+            active_frame: state.inner.layers().active_frame(),
+            frames_duration: state
+                .inner
+                .layers()
+                .frames()
+                .iter()
+                .map(|frame| frame.duration())
+                .collect(),
+            tags: state.inner.layers().tags().to_vec(),
+            onion_skin: state.inner.onion_skin(),
             palette: state.inner.palette().iter().map(|c| (*c).into()).collect(),
             mouse_canvas: (x, y).into(),
             is_on_canvas: in_canvas,
             selected_tool: state.selected_tool(),
             visible_pixel_on_mouse: visible_pixel,
-            canvas_size: state.canvas().size(),
+            canvas_size: state.canvas_size(),
             spritesheet: state.inner.spritesheet(),
+            history_limit: state.inner.history_limit(),
+            history: state.history.clone(),
+            journaling: state.inner.is_journaling(),
+            recovered_session: state.recovered_session.clone(),
             zoom: state.zoom,
             fps: state.fps,
+            notice: state
+                .notice
+                .clone()
+                .filter(|_| !state.notice_timer.expired()),
         }
     }
 }
@@ -128,18 +193,40 @@ pub struct UiState {
     canvas_pos: Position<f32>,
     zoom: f32,
     layer_textures: Vec<Texture2D>,
+    blended_tex: Option<Texture2D>,
+    /// Whether the layers changed since `blended_tex` was composited. It's
+    /// composited again once per frame at most, as it takes all layers
+    blended_dirty: bool,
+    /// Texture of each frame other than the active one with all layers
+    /// composited, used by the preview and onion skinning
+    frame_textures: HashMap<FrameId, Texture2D>,
+    /// Frames whose texture is out of date. They're composited again once per
+    /// frame at most, and only when they're not the active one
+    dirty_frames: HashSet<FrameId>,
+    /// Undo history taken after an event that may have changed it, until it's
+    /// passed on to the history panel. It isn't taken every frame, as that
+    /// clones the label of every action
+    history: Option<Vec<HistoryEntry>>,
     input: InputManager,
     mouse: MouseManager,
     mouse_over_gui: bool,
     key_bindings: KeyBindings,
     gui_interaction_rest: Timer,
+    /// Message shown in the status bar until its timer expires
+    notice: Option<String>,
+    notice_timer: Timer,
     manual_canvas_block: bool,
     free_image_tex: Option<Texture2D>,
     must_exit: bool,
+    autosave: Autosave,
+    recovered_session: Option<PathBuf>,
     t0: SystemTime,
     fps: f32,
     bg: Background,
     prev_cursor: CursorType,
+    /// Where the move tool started dragging the active layer (when there was
+    /// no selection to move)
+    shift_start: Option<Point<i32>>,
 }
 
 impl Default for UiState {
@@ -164,23 +251,45 @@ impl Default for UiState {
             canvas_pos: (CANVAS_X, CANVAS_Y).into(),
             zoom: DEFAULT_ZOOM_LEVEL,
             layer_textures: vec![drawing],
+            blended_tex: None,
+            blended_dirty: false,
+            frame_textures: HashMap::new(),
+            dirty_frames: HashSet::new(),
+            history: Some(Vec::new()),
             input,
             mouse: MouseManager::new(),
             mouse_over_gui: false,
             key_bindings,
             gui_interaction_rest: Timer::new(),
+            notice: None,
+            notice_timer: Timer::new(),
             free_image_tex: None,
             must_exit: false,
+            autosave: Autosave::disabled(),
+            recovered_session: None,
             t0: SystemTime::now(),
             fps: 60.,
             bg: Background::new(),
             prev_cursor: CursorType::Tool(Tool::Brush),
             manual_canvas_block: false,
+            shift_start: None,
         }
     }
 }
 
 impl UiState {
+    /// Create the state for a new session, with autosave enabled
+    pub fn new() -> Self {
+        let autosave = Autosave::from_env();
+        let recovered_session = autosave.recovered();
+
+        Self {
+            autosave,
+            recovered_session,
+            ..Default::default()
+        }
+    }
+
     pub fn must_exit(&self) -> bool {
         self.must_exit
     }
@@ -203,6 +312,7 @@ impl UiState {
         self.mouse_over_gui = false;
 
         self.gui.sync((&*self).into());
+        self.history = None;
         let fx = self.gui.update();
         self.process_fx(fx)?;
 
@@ -216,9 +326,31 @@ impl UiState {
 
         self.sync_mouse();
 
+        if self.autosave.is_due() {
+            self.autosave()?;
+        }
+
+        if self.blended_dirty {
+            self.sync_blended_texture();
+        }
+        self.sync_frame_textures();
+
         Ok(())
     }
 
+    fn autosave(&mut self) -> Result<()> {
+        let path = self.autosave.next_path()?;
+        self.inner.save_snapshot(path)?;
+        self.autosave.prune()
+    }
+
+    /// Start over with a default state, keeping the same autosave session
+    fn reset(&mut self) {
+        let autosave = std::mem::replace(&mut self.autosave, Autosave::disabled());
+        *self = UiState::default();
+        self.autosave = autosave;
+    }
+
     fn process_fx(&mut self, fx: Vec<Effect>) -> Result<()> {
         for effect in fx {
             match effect {
@@ -238,11 +370,10 @@ impl UiState {
 
     fn draw_ctx(&self) -> DrawContext {
         DrawContext {
-            spritesheet: self.inner.spritesheet(),
             scale: self.zoom(),
             canvas_pos: self.canvas_pos(),
             camera: self.camera(),
-            canvas_size: (self.canvas().width() as f32, self.canvas().height() as f32).into(),
+            canvas_size: self.canvas_size().into(),
             selection: self.inner.selection(),
         }
     }
@@ -254,7 +385,6 @@ impl UiState {
 
         self.bg.draw(ctx);
         graphics::draw_canvas(&*self);
-        graphics::draw_spritesheet_boundaries(ctx);
 
         let (x, y) = macroquad::prelude::mouse_position();
         let mouse_canvas = self.screen_to_canvas(x, y).into();
@@ -298,19 +428,63 @@ impl UiState {
         let (x, y) = macroquad::prelude::mouse_position();
         let (x, y) = self.screen_to_canvas(x, y);
         let p = (x, y).into();
-        let in_canvas = self.canvas().is_in_bounds(p);
+        let in_canvas = self.is_on_canvas(p);
 
         self.mouse.sync(in_canvas, self.selected_tool());
     }
 
     pub fn execute(&mut self, event: Event) -> Result<()> {
-        let effect = self.inner.execute(event)?;
+        let changes_layer_settings = matches!(
+            event,
+            Event::ChangeLayerVisibility(_, _)
+                | Event::ChangeLayerOpacity(_, _)
+                | Event::ChangeGroupVisibility(_, _)
+                | Event::ChangeGroupOpacity(_, _)
+        );
+        // These only change which frame is active or where it is, so no other
+        // frame has to be composited again
+        let changes_frames = matches!(
+            event,
+            Event::AddFrame
+                | Event::InsertFrame(_)
+                | Event::DuplicateFrame(_)
+                | Event::DeleteFrame(_)
+                | Event::MoveFrame(_, _)
+                | Event::SwitchFrame(_)
+                | Event::SetFrameDuration(_, _)
+        );
+        // Strokes are added to the history when they end, and other events
+        // that can't be undone only add to it by anchoring a free image or by
+        // ending a stroke in progress
+        let changes_history = match event {
+            Event::BrushStroke(_) | Event::Erase(_) => false,
+            Event::SwitchFrame(_) => true,
+            _ => event.undoable() || !event.recordable() || self.inner.free_image().is_some(),
+        };
+        let previous_frame = self.inner.layers().active_frame_id();
+        let effect = match self.inner.execute(event) {
+            // Editing a locked layer fails on every mouse movement while
+            // drawing, so it's shown in the status bar instead of as an error
+            Err(lapix::Error::LayerLocked(id)) => {
+                let name = self.inner.layers().by_id(id).map_or("", |l| l.name());
+                self.notice = Some(format!("Layer \"{name}\" is locked"));
+                self.notice_timer.start(NOTICE_MS);
+                return Ok(());
+            }
+            result => result?,
+        };
+        self.autosave.mark_changed();
+        if changes_history {
+            self.history = Some(self.inner.history());
+        }
 
         match effect {
             // TODO: Texture2D is copy, so we don't need `drawing_mut` here, but
             // it would be better.
             CanvasEffect::Update => {
-                self.drawing().update(&self.canvas().inner().0);
+                // The active canvas may be the mask of the layer
+                let layer = self.inner.layers().active();
+                self.drawing().update(&layer.canvas().inner().0);
             }
             CanvasEffect::New | CanvasEffect::Layer => {
                 self.sync_layer_textures();
@@ -318,6 +492,19 @@ impl UiState {
             CanvasEffect::None => (),
         };
 
+        if !matches!(effect, CanvasEffect::None) || changes_layer_settings {
+            self.blended_dirty = true;
+            // Drawing only changes the active frame (an undo may switch to
+            // another one first), while other events may change all of them
+            self.dirty_frames.insert(previous_frame);
+            self.dirty_frames
+                .insert(self.inner.layers().active_frame_id());
+        }
+        let changes_all_frames = matches!(effect, CanvasEffect::New | CanvasEffect::Layer);
+        if (changes_all_frames && !changes_frames) || changes_layer_settings {
+            self.mark_all_frames_dirty();
+        }
+
         Ok(())
     }
 
@@ -325,6 +512,91 @@ impl UiState {
         for layer in 0..self.inner.layers().count() {
             self.sync_layer_texture(layer);
         }
+        self.blended_dirty = true;
+    }
+
+    fn mark_all_frames_dirty(&mut self) {
+        let frames = self.inner.layers().frames().iter().map(|f| f.id());
+        self.dirty_frames.extend(frames);
+    }
+
+    /// Composite again the frames that changed (or are new) since their
+    /// textures were made, except for the active one, which is drawn from the
+    /// layer textures, and delete the textures of frames that are gone
+    fn sync_frame_textures(&mut self) {
+        let layers = self.inner.layers();
+        let active = layers.active_frame_id();
+        let single_frame = layers.frame_count() == 1;
+        self.frame_textures.retain(|id, tex| {
+            let keep = !single_frame && layers.frame_index_of(*id).is_ok();
+            if !keep {
+                tex.delete();
+            }
+            keep
+        });
+        self.dirty_frames
+            .retain(|id| layers.frame_index_of(*id).is_ok());
+        if single_frame {
+            return;
+        }
+
+        for (frame, id) in layers.frames().iter().map(|f| f.id()).enumerate() {
+            if id == active
+                || (self.frame_textures.contains_key(&id) && !self.dirty_frames.contains(&id))
+            {
+                continue;
+            }
+            self.dirty_frames.remove(&id);
+            let texture = Texture2D::from_image(&layers.blended_frame(frame).0);
+            texture.set_filter(FilterMode::Nearest);
+            if let Some(old) = self.frame_textures.insert(id, texture) {
+                old.delete();
+            }
+        }
+    }
+
+    /// Layer textures are drawn on top of each other with alpha blending only,
+    /// so if any layer uses another [`BlendMode`] or has a mask, or a group is
+    /// translucent (its opacity applies to its layers composited together),
+    /// the canvas is composited by lapix instead (just like when exporting)
+    /// and drawn as a single texture
+    fn sync_blended_texture(&mut self) {
+        self.blended_dirty = false;
+        let layers = self.inner.layers();
+        let needs_blending = (0..layers.count()).any(|i| {
+            let layer = layers.get(i);
+            layers.effective_visibility(i)
+                && layer.reference().is_none()
+                && ((i > 0 && layer.blend_mode() != BlendMode::Normal) || layer.mask().is_some())
+        });
+        let translucent_group = layers
+            .groups()
+            .iter()
+            .any(|group| group.visible() && group.opacity() < 255);
+
+        if !needs_blending && !translucent_group {
+            if let Some(tex) = self.blended_tex.take() {
+                tex.delete();
+            }
+            return;
+        }
+
+        let img = layers.blended();
+        match self.blended_tex {
+            Some(tex)
+                if tex.width() as u16 == img.0.width && tex.height() as u16 == img.0.height =>
+            {
+                tex.update(&img.0);
+            }
+            _ => {
+                if let Some(tex) = self.blended_tex.take() {
+                    tex.delete();
+                }
+                let tex = Texture2D::from_image(&img.0);
+                tex.set_filter(FilterMode::Nearest);
+                self.blended_tex = Some(tex);
+            }
+        }
     }
 
     pub fn sync_layer_texture(&mut self, index: usize) {
@@ -362,8 +634,49 @@ impl UiState {
             UiEvent::Paste => {
                 self.execute(Event::Paste(p))?;
             }
-            UiEvent::Exit => self.must_exit = true,
-            UiEvent::NewProject => *self = UiState::default(),
+            UiEvent::Exit => {
+                self.must_exit = true;
+                self.autosave.clear()?;
+            }
+            UiEvent::NewProject => self.reset(),
+            UiEvent::NewLayer => {
+                // The new layer goes on top of all others
+                self.execute(Event::NewLayerAbove)?;
+                let layers = self.inner.layers();
+                let id = layers.get(layers.count() - 1).id();
+                self.execute(Event::SwitchLayer(id))?;
+            }
+            UiEvent::StartJournal(path) => {
+                let file = std::fs::File::create(path).map_err(lapix::Error::from)?;
+                self.inner.start_journal(file)?;
+                // A stroke in progress is ended before the journal starts
+                self.history = Some(self.inner.history());
+            }
+            UiEvent::StopJournal => self.inner.stop_journal(),
+            UiEvent::PlayMacroNextFrame => self.play_macro_next_frame()?,
+            UiEvent::RestoreSession => {
+                if let Some(path) = self.recovered_session.take() {
+                    self.execute(Event::LoadProject(path.clone()))?;
+                    self.autosave.discard_recovered(&path)?;
+                }
+            }
+            UiEvent::DiscardRecoveredSession => {
+                if let Some(path) = self.recovered_session.take() {
+                    self.autosave.discard_recovered(&path)?;
+                }
+            }
+            UiEvent::ReplayJournal(path) => {
+                let file = std::fs::File::open(path).map_err(lapix::Error::from)?;
+                let inner = State::replay(
+                    file,
+                    Some(LoadProject(project::load)),
+                    Some(SaveProject(project::save)),
+                )?;
+                self.reset();
+                self.inner = inner;
+                self.history = Some(self.inner.history());
+                self.sync_layer_textures();
+            }
             UiEvent::SetPreviousCursor => self.mouse.set_cursor(self.prev_cursor),
             UiEvent::SetCursor(c) => {
                 self.prev_cursor = self.mouse.cursor();
@@ -387,9 +700,15 @@ impl UiState {
                 (Tool::Rectangle, false) => self.execute(Event::RectStart(p))?,
                 (Tool::Bucket, false) => self.execute(Event::Bucket(p))?,
                 (Tool::Selection, false) => self.execute(Event::StartSelection(p))?,
-                (Tool::Move, false) => self.execute(Event::MoveStart(p))?,
+                (Tool::Move, false) => {
+                    // Without a selection, the whole active layer is moved
+                    if self.inner.selection().is_none() {
+                        self.shift_start = Some(p);
+                    }
+                    self.execute(Event::MoveStart(p))?
+                }
                 (Tool::Eyedropper, false) => {
-                    if self.canvas().is_in_bounds(p) {
+                    if self.is_on_canvas(p) {
                         let color = self.visible_pixel(p);
                         self.execute(Event::SetMainColor(color.into()))?;
                         self.execute(Event::SetTool(Tool::Brush))?;
@@ -412,7 +731,12 @@ impl UiState {
                     self.execute(Event::SetTool(Tool::Move))?;
                 }
                 (Tool::Move, false) => {
-                    if self.is_mouse_on_selection() {
+                    if let Some(p0) = self.shift_start.take() {
+                        if p != p0 {
+                            let id = self.inner.layers().active_id();
+                            self.execute(Event::ShiftLayer(id, p - p0))?;
+                        }
+                    } else if self.is_mouse_on_selection() {
                         self.execute(Event::MoveEnd(p))?;
                     } else {
                         self.execute(Event::ClearSelection)?;
@@ -441,12 +765,36 @@ impl UiState {
         self.canvas_pos
     }
 
+    /// Size of the canvas in pixels (the layers may be bigger or smaller)
+    pub fn canvas_size(&self) -> Size<i32> {
+        self.inner.layers().canvas_size()
+    }
+
+    /// Whether a point (in canvas pixels) is inside the canvas
+    pub fn is_on_canvas(&self, p: Point<i32>) -> bool {
+        let size = self.canvas_size();
+        p.x >= 0 && p.y >= 0 && p.x < size.x && p.y < size.y
+    }
+
     pub fn canvas_actual_size(&self) -> Size<f32> {
-        (
-            self.inner.canvas().width() as f32 * self.zoom,
-            self.inner.canvas().height() as f32 * self.zoom,
-        )
-            .into()
+        let size = self.canvas_size();
+        (size.x as f32 * self.zoom, size.y as f32 * self.zoom).into()
+    }
+
+    /// Play the last recorded macro on the frame after the active one (or the
+    /// first frame, after the last one), which becomes the active frame
+    fn play_macro_next_frame(&mut self) -> Result<()> {
+        let index = match self.inner.macros().len().checked_sub(1) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let layers = self.inner.layers();
+        let next = (layers.active_frame() + 1) % layers.frame_count();
+        let id = layers.frames()[next].id();
+        self.execute(Event::SwitchFrame(id))?;
+
+        self.execute(Event::PlayMacro(index, Point::ZERO))
     }
 
     pub fn selected_tool(&self) -> Tool {
@@ -457,8 +805,24 @@ impl UiState {
         self.zoom
     }
 
-    pub fn layer(&self, index: usize) -> &Layer<WrappedImage> {
-        self.inner.layers().get(index)
+    /// Whether a layer is visible, considering the groups that contain it
+    pub fn layer_visible(&self, index: usize) -> bool {
+        self.inner.layers().effective_visibility(index)
+    }
+
+    /// Opacity of a layer, including the opacity of the groups that contain it
+    pub fn layer_opacity(&self, index: usize) -> u8 {
+        self.inner.layers().effective_opacity(index)
+    }
+
+    /// Placement of a layer's image over the canvas, if it is a reference layer
+    pub fn layer_reference(&self, index: usize) -> Option<Reference> {
+        self.inner.layers().get(index).reference()
+    }
+
+    /// Position of the top left corner of a layer's image in the canvas
+    pub fn layer_offset(&self, index: usize) -> Point<i32> {
+        self.inner.layers().get(index).offset()
     }
 
     pub fn num_layers(&self) -> usize {
@@ -469,6 +833,38 @@ impl UiState {
         self.layer_textures[index]
     }
 
+    pub fn active_frame(&self) -> usize {
+        self.inner.layers().active_frame()
+    }
+
+    /// Frames shown as ghosts around the active one by onion skinning, which
+    /// are drawn with [`UiState::frame_tex`]
+    pub fn onion_skin_ghosts(&self) -> Vec<Ghost> {
+        self.inner.onion_skin_ghosts()
+    }
+
+    /// Index of the frame shown at some time (in milliseconds) of the
+    /// animation of a tag (or of all frames), given by its index
+    pub fn frame_at_time(&self, tag: Option<usize>, time: u64) -> usize {
+        let layers = self.inner.layers();
+        let tag = tag.and_then(|i| layers.tags().get(i));
+
+        layers.frame_at_time(tag, time)
+    }
+
+    /// Texture of a frame with all layers composited (only available for the
+    /// frames other than the active one, when there is more than one frame)
+    pub fn frame_tex(&self, frame: usize) -> Option<Texture2D> {
+        let id = self.inner.layers().frames().get(frame)?.id();
+        self.frame_textures.get(&id).copied()
+    }
+
+    /// Texture with all layers composited, if any of them has a [`BlendMode`]
+    /// that can't be drawn by blending the layer textures
+    pub fn blended_tex(&self) -> Option<Texture2D> {
+        self.blended_tex
+    }
+
     pub fn zoom_in(&mut self) {
         self.zoom_mul(2.);
     }